@@ -34,7 +34,7 @@ use tokio::task;
 use tracing::{info, warn};
 
 mod commands;
-fn trigger_post_turn_index_refresh() {
+fn trigger_post_turn_index_refresh(cwd: &Path) {
     // Respect global disable
     if std::env::var("CODEX_INDEXING")
         .map(|v| v == "0" || v.eq_ignore_ascii_case("off"))
@@ -58,11 +58,9 @@ fn trigger_post_turn_index_refresh() {
         }
         *last = now;
     }
-    std::thread::spawn(|| {
-        let _ = std::process::Command::new("codex-agentic")
-            .arg("index")
-            .arg("build")
-            .status();
+    let root = codex_index::repo_root(cwd);
+    std::thread::spawn(move || {
+        let _ = codex_index::Builder::background(root).build();
     });
 }
 // Placeholder for per-session state. Holds the Codex conversation
@@ -1004,7 +1002,7 @@ impl Agent for CodexAgent {
                 }
                 EventMsg::TaskComplete(_) => {
                     // Trigger a best‑effort post‑turn index refresh (git‑delta) if due.
-                    trigger_post_turn_index_refresh();
+                    trigger_post_turn_index_refresh(&self.config.cwd);
                     break;
                 }
                 EventMsg::Error(err) => {
@@ -1053,20 +1051,11 @@ impl Agent for CodexAgent {
     }
 }
 
-static EMBEDDER: OnceLock<std::sync::Mutex<fastembed::TextEmbedding>> = OnceLock::new();
 static RETRIEVAL_CACHE: OnceLock<std::sync::Mutex<lru::LruCache<String, (String, String)>>> =
     OnceLock::new();
 
-fn token_budget() -> usize {
-    std::env::var("CODEX_INDEX_CONTEXT_TOKENS")
-        .ok()
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(800)
-}
-
-fn est_tokens(s: &str) -> usize {
-    s.len().div_ceil(4)
-}
+// Candidates taken from each ranking before fusion and threshold gating.
+const RETRIEVAL_CANDIDATES: usize = 32;
 
 async fn fetch_retrieval_context(
     cwd: &Path,
@@ -1095,188 +1084,21 @@ async fn fetch_retrieval_context(
             return Some(v);
         }
     }
-    // Read manifest
-    let base = cwd.join(".codex/index");
-    let manifest_path = if base.join("manifest.json").exists() {
-        base.join("manifest.json")
-    } else {
-        std::path::Path::new(".codex/index/manifest.json").to_path_buf()
-    };
-    let mbytes = std::fs::read(manifest_path).ok()?;
-    #[derive(serde::Deserialize)]
-    struct ManifestLite {
-        model: String,
-        dim: usize,
-    }
-    let m: ManifestLite = serde_json::from_slice(&mbytes).ok()?;
-    let model = match m.model.as_str() {
-        "bge-large-en-v1.5" => fastembed::EmbeddingModel::BGELargeENV15,
-        _ => fastembed::EmbeddingModel::BGESmallENV15,
-    };
-    {
-        {
-            let init = || {
-                std::sync::Mutex::new(
-                    fastembed::TextEmbedding::try_new(fastembed::InitOptions::new(model))
-                        .expect("fastembed init"),
-                )
-            };
-            EMBEDDER.get_or_init(init);
-        }
-    }
-    let mut qv = {
-        let mut guard = EMBEDDER.get().unwrap().lock().ok()?;
-        guard
-            .embed(vec![q.clone()], None)
-            .ok()?
-            .into_iter()
-            .next()?
-    };
-    if qv.len() != m.dim {
-        return None;
-    }
-    // normalize
-    let mut n = 0f32;
-    for &v in &qv {
-        n += v * v;
-    }
-    n = n.sqrt().max(1e-12);
-    for v in &mut qv {
-        *v /= n;
-    }
-    // Load vectors + meta
-    let vectors_path = if base.join("vectors.hnsw").exists() {
-        base.join("vectors.hnsw")
-    } else {
-        std::path::Path::new(".codex/index/vectors.hnsw").to_path_buf()
-    };
-    let (ids, data) = load_vectors_mmap(&vectors_path).ok()?;
-    let meta_path = if base.join("meta.jsonl").exists() {
-        base.join("meta.jsonl")
-    } else {
-        std::path::Path::new(".codex/index/meta.jsonl").to_path_buf()
-    };
-    let meta = load_meta_jsonl(&meta_path).ok()?;
-    let dim = m.dim;
-    use rayon::prelude::*;
-    let mut scores: Vec<(usize, f32)> = (0..ids.len())
-        .into_par_iter()
-        .map(|i| {
-            let base = i * dim;
-            let mut dot = 0f32;
-            for j in 0..dim {
-                dot += qv[j] * data[base + j];
-            }
-            (i, dot)
-        })
-        .collect();
-    scores.par_sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-    // Threshold for context injection and display (default 0.725).
-    // If top score is below this, do not inject or display anything.
-    let threshold: f32 = std::env::var("CODEX_INDEX_RETRIEVAL_THRESHOLD")
-        .ok()
-        .and_then(|s| s.parse::<f32>().ok())
-        .unwrap_or(0.65);
-    // Compute quick stats and gate by threshold.
-    let top = scores.first().map(|(_, s)| *s).unwrap_or(0.0);
-    let found = scores.iter().take_while(|(_, s)| *s >= threshold).count();
-    if top < threshold {
-        return None;
-    }
-
-    // Build file reference list (no snippets) from top matches over threshold.
-    let mut refs: Vec<String> = Vec::new();
-    for (i, _score) in scores.into_iter().take_while(|(_, s)| *s >= threshold) {
-        let id = ids[i];
-        if let Some(r) = meta.get(&id) {
-            refs.push(format!("- @{}:{}-{} ({})", r.path, r.start, r.end, r.lang));
-        }
-    }
-    let header = concat!(
-        "Local code references — read these first.\n\n",
-        "Instructions:\n",
-        "1) Treat the files below as the primary sources for answering. Read them carefully before any grep/other searches.\n",
-        "2) Only if these sources are insufficient, you may run additional searches.\n",
-        "3) Do not include low-confidence references (< threshold) in your reasoning.\n",
-        "4) Cite file paths and line ranges when you reference code.\n\n",
-        "References:\n",
-    ).to_string();
-    let mut ctx = header.clone();
-    let budget = token_budget();
-    for line in refs {
-        if est_tokens(&(ctx.clone() + &line)) > budget {
-            break;
-        }
-        ctx.push_str(&line);
-        ctx.push('\n');
-    }
-    let cf_pct = (top * 100.0).round();
-    let summary = format!("> {:.0}% -- {} items found", cf_pct, found);
-    {
-        let out = (ctx.clone(), summary.clone());
-        if let Ok(mut c) = RETRIEVAL_CACHE.get().unwrap().lock() {
-            c.put(q.clone(), out.clone());
-        }
-        Some(out)
-    }
-}
-fn load_vectors_mmap(p: &Path) -> Result<(Vec<u64>, Vec<f32>), ()> {
-    use memmap2::MmapOptions;
-    use std::fs::File;
-    use std::io::Read;
-    let f = File::open(p).map_err(|_| ())?;
-    let mut r = std::io::BufReader::new(f);
-    let mut b4 = [0u8; 4];
-    let mut b8 = [0u8; 8];
-    r.read_exact(&mut b4).map_err(|_| ())?;
-    r.read_exact(&mut b4).map_err(|_| ())?;
-    let dim = u32::from_le_bytes(b4) as usize;
-    r.read_exact(&mut b8).map_err(|_| ())?;
-    let rows = u64::from_le_bytes(b8) as usize;
-    let mut ids = Vec::with_capacity(rows);
-    for _ in 0..rows {
-        r.read_exact(&mut b8).map_err(|_| ())?;
-        ids.push(u64::from_le_bytes(b8));
-    }
-    let f2 = r.into_inner();
-    let mmap = unsafe { MmapOptions::new().map(&f2).map_err(|_| ())? };
-    let start = 4 + 4 + 8 + rows * 8;
-    let bytes = &mmap[start..start + rows * dim * 4];
-    let mut data = Vec::with_capacity(rows * dim);
-    let mut i = 0usize;
-    while i < bytes.len() {
-        data.push(f32::from_le_bytes([
-            bytes[i],
-            bytes[i + 1],
-            bytes[i + 2],
-            bytes[i + 3],
-        ]));
-        i += 4;
-    }
-    Ok((ids, data))
-}
-#[derive(serde::Deserialize)]
-struct MetaLite {
-    id: u64,
-    path: String,
-    start: usize,
-    end: usize,
-    lang: String,
-    #[allow(dead_code)]
-    preview: String,
-}
-fn load_meta_jsonl(p: &Path) -> Result<std::collections::HashMap<u64, MetaLite>, ()> {
-    use std::fs::File;
-    use std::io::BufRead;
-    let f = std::io::BufReader::new(File::open(p).map_err(|_| ())?);
-    let mut map = std::collections::HashMap::new();
-    for line in f.lines() {
-        let l = line.map_err(|_| ())?;
-        if l.trim().is_empty() {
-            continue;
-        }
-        let r: MetaLite = serde_json::from_str(&l).map_err(|_| ())?;
-        map.insert(r.id, r);
+    let root = codex_index::repo_root(cwd);
+    let query = q.clone();
+    let rc = task::spawn_blocking(move || {
+        codex_index::retrieval_context(
+            &root,
+            &query,
+            RETRIEVAL_CANDIDATES,
+            codex_index::QuerySource::Acp,
+        )
+    })
+    .await
+    .ok()??;
+    let out = (rc.context, rc.summary);
+    if let Ok(mut c) = RETRIEVAL_CACHE.get().unwrap().lock() {
+        c.put(q, out.clone());
     }
-    Ok(map)
+    Some(out)
 }
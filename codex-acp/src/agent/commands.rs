@@ -63,14 +63,20 @@ impl CodexAgent {
             },
             AvailableCommand {
                 name: "index".into(),
-                description: "manage local index: /index status | build [--model bge-small|bge-large] [--force] | verify | clean".into(),
-                input: Some(AvailableCommandInput::Unstructured { hint: "status|build|verify|clean [args]".into() }),
+                description: "manage local index: /index status | build [--model bge-small|bge-large] [--force] | symbols <name> [--kind fn] [--exact] | verify [--deep|--repair] | clean".into(),
+                input: Some(AvailableCommandInput::Unstructured { hint: "status|build|symbols|verify|clean [args]".into() }),
                 meta: None,
             },
             AvailableCommand {
                 name: "search".into(),
-                description: "semantic search in codebase (local): /search <query> [-k N]".into(),
-                input: Some(AvailableCommandInput::Unstructured { hint: "<query> [-k N]".into() }),
+                description: "semantic search in codebase (local): /search <query> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]".into(),
+                input: Some(AvailableCommandInput::Unstructured { hint: "<query> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]".into() }),
+                meta: None,
+            },
+            AvailableCommand {
+                name: "similar".into(),
+                description: "find similar code in other files (local): /similar <path>:<line> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]".into(),
+                input: Some(AvailableCommandInput::Unstructured { hint: "<path>:<line> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]".into() }),
                 meta: None,
             }
 
@@ -366,14 +372,12 @@ Notes for Agents
                 }
             }
             "index" => {
-                let args = _rest.trim();
-                let mut cli: Vec<String> = vec!["index".into()];
-                if args.is_empty() {
-                    cli.push("status".into());
-                } else {
-                    cli.extend(args.split_whitespace().map(|s| s.to_string()));
-                }
-                let out = run_codex_agentic(cli).await;
+                let root = codex_index::repo_root(&self.config.cwd);
+                let input = _rest.trim().to_string();
+                let out =
+                    tokio::task::spawn_blocking(move || codex_index::index_command(&root, &input))
+                        .await
+                        .unwrap_or_else(|e| format!("Index command failed: {e}"));
                 let (tx, rx) = oneshot::channel();
                 self.send_message_chunk(
                     session_id,
@@ -395,19 +399,19 @@ Notes for Agents
                     let (tx, rx) = oneshot::channel();
                     self.send_message_chunk(
                         session_id,
-                        "Usage: /search <query> [-k N]".into(),
+                        "Usage: /search <query> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]".into(),
                         tx,
                     )?;
                     let _ = rx.await;
                     return Ok(true);
                 }
-                let mut cli: Vec<String> = vec!["index".into(), "query".into(), rest.into()];
-                if !rest.contains("-k ") {
-                    cli.push("-k".into());
-                    cli.push("8".into());
-                }
-                cli.push("--show-snippets".into());
-                let out = run_codex_agentic(cli).await;
+                let root = codex_index::repo_root(&self.config.cwd);
+                let input = rest.to_string();
+                let out = tokio::task::spawn_blocking(move || {
+                    codex_index::search_command(&root, &input, codex_index::QuerySource::Acp)
+                })
+                .await
+                .unwrap_or_else(|e| format!("Search failed: {e}"));
                 let (tx, rx) = oneshot::channel();
                 self.send_message_chunk(
                     session_id,
@@ -423,6 +427,31 @@ Notes for Agents
                 let _ = rx.await;
                 return Ok(true);
             }
+            "similar" => {
+                let rest = _rest.trim();
+                if rest.is_empty() {
+                    let (tx, rx) = oneshot::channel();
+                    self.send_message_chunk(
+                        session_id,
+                        "Usage: /similar <path>:<line> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]"
+                            .into(),
+                        tx,
+                    )?;
+                    let _ = rx.await;
+                    return Ok(true);
+                }
+                let root = codex_index::repo_root(&self.config.cwd);
+                let input = rest.to_string();
+                let out = tokio::task::spawn_blocking(move || {
+                    codex_index::similar_command(&root, &input)
+                })
+                .await
+                .unwrap_or_else(|e| format!("Similar search failed: {e}"));
+                let (tx, rx) = oneshot::channel();
+                self.send_message_chunk(session_id, format!("```text\n{out}\n```").into(), tx)?;
+                let _ = rx.await;
+                return Ok(true);
+            }
             "status" => {
                 let status_text = self.render_status(&sid_str).await;
                 let (tx, rx) = oneshot::channel();
@@ -763,7 +792,7 @@ Notes for Agents
         let web_search = self.config.tools_web_search_request;
 
         // Index status (best-effort)
-        let index_status = run_codex_agentic(vec!["index".into(), "status".into()]).await;
+        let index_status = codex_index::Status::describe(&codex_index::repo_root(&self.config.cwd));
 
         // Markdown output with headings and lists
         format!(
@@ -845,32 +874,3 @@ Notes for Agents
         format!("{}{}", first, rest)
     }
 }
-
-async fn run_codex_agentic(args: Vec<String>) -> String {
-    use tokio::process::Command;
-    let mut cmd = Command::new("codex-agentic");
-    for a in args {
-        cmd.arg(a);
-    }
-    match cmd.output().await {
-        Ok(o) => {
-            let mut s = String::new();
-            if !o.stdout.is_empty() {
-                s.push_str(&String::from_utf8_lossy(&o.stdout));
-            }
-            if !o.stderr.is_empty() {
-                if !s.is_empty() {
-                    s.push('\n');
-                    s.push(' ');
-                }
-                s.push_str(&String::from_utf8_lossy(&o.stderr));
-            }
-            if s.trim().is_empty() {
-                "(no output)".into()
-            } else {
-                s
-            }
-        }
-        Err(e) => format!("Failed to run codex-agentic: {}", e),
-    }
-}
@@ -1,132 +1,41 @@
-#![allow(
-    clippy::collapsible_if,
-    clippy::collapsible_else_if,
-    clippy::needless_range_loop,
-    clippy::manual_ok_err,
-    clippy::unnecessary_cast,
-    clippy::default_constructed_unit_structs,
-    clippy::print_with_newline,
-    clippy::get_first,
-    dead_code
-)]
-use anyhow::{Context, Result, bail};
-use ignore::WalkBuilder;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader, BufWriter, Read, Write};
-use std::path::{Path, PathBuf};
-use time::OffsetDateTime;
-
-// HNSW (approximate nearest neighbor) for fast query path
-use hnsw_rs::prelude::*;
-
-const INDEX_DIR: &str = ".codex/index";
-const IGNORE_FILE: &str = ".index-ignore";
-const VECTORS_FILE: &str = "vectors.hnsw"; // flat vectors + ids (linear scan + id map for HNSW)
-const HNSW_BASENAME: &str = "vectors"; // will create vectors.hnsw.graph + vectors.hnsw.data // flat store for now
-const META_FILE: &str = "meta.jsonl";
-const MANIFEST_FILE: &str = "manifest.json";
-const ANALYTICS_FILE: &str = "analytics.json";
-const LOCK_FILE: &str = "lock";
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Manifest {
-    index_version: u32,
-    engine: String,
-    model: String,
-    dim: usize,
-    metric: String,
-    chunk_mode: String,
-    chunk: ChunkCfg,
-    repo: RepoInfo,
-    counts: Counts,
-    checksums: Checksums,
-    created_at: String,
-    last_refresh: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ChunkCfg {
-    lines: usize,
-    overlap: usize,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct RepoInfo {
-    root: String,
-    git_sha: Option<String>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct Counts {
-    files: usize,
-    chunks: usize,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct Checksums {
-    vectors_hnsw: String,
-    meta_jsonl: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct MetaRow {
-    id: u64,
-    path: String,
-    start: usize,
-    end: usize,
-    lang: String,
-    sha256: String,
-    preview: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct Analytics {
-    queries: u64,
-    hits: u64,
-    misses: u64,
-    last_query_ts: Option<String>,
-    // New: last time a background or manual index attempt/check ran
-    last_attempt_ts: Option<String>,
-}
+//! `codex-agentic index …` and `search-code`: argument handling and output
+//! formatting over the `codex-index` library.
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct VectorStore {
-    dim: usize,
-    ids: Vec<u64>,
-    data: Vec<f32>, // flattened row-major
-}
-
-impl VectorStore {
-    fn new(dim: usize) -> Self {
-        Self {
-            dim,
-            ids: Vec::new(),
-            data: Vec::new(),
-        }
-    }
-    fn push(&mut self, id: u64, v: &[f32]) {
-        self.ids.push(id);
-        self.data.extend_from_slice(v);
-    }
-    fn rows(&self) -> usize {
-        self.ids.len()
-    }
-}
+use anyhow::{Context, Result, bail};
+use codex_index::{
+    BuildOptions, BuildPhase, BuildProgress, BuildReport, Builder, CommitHit, FileHits,
+    HistoryOptions, Hit, Member, MemberProgressFn, NO_MATCH, Provider, QueryRecord, QuerySource,
+    Retriever, SearchMode, SearchOptions, Status, Storage, TextFormat, Timings, Workspace,
+};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
 pub fn dispatch(cmd: crate::IndexCmd) -> Result<()> {
     match cmd {
         crate::IndexCmd::Build(args) => build(&args),
         crate::IndexCmd::Query(args) => query(&args),
+        crate::IndexCmd::Symbols(args) => symbols(&args),
+        crate::IndexCmd::Similar(args) => similar(&args),
+        crate::IndexCmd::Dupes(args) => dupes(&args),
+        crate::IndexCmd::Eval(args) => eval(&args),
+        crate::IndexCmd::Stats(args) => stats(&args),
         crate::IndexCmd::Status => status(),
-        crate::IndexCmd::Verify => verify(),
+        crate::IndexCmd::Verify(args) => verify(&args),
         crate::IndexCmd::Clean => clean(),
         crate::IndexCmd::Ignore(args) => ignore_cmd(&args),
+        crate::IndexCmd::Serve(args) => serve(&args),
+        crate::IndexCmd::Export(args) => export(&args),
+        crate::IndexCmd::Import(args) => import(&args),
     }
 }
 
+fn repo_root() -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    codex_index::repo_root(&cwd)
+}
+
 // Public background helpers -------------------------------------------------
 static STARTED_MAINTENANCE: std::sync::OnceLock<()> = std::sync::OnceLock::new();
 
@@ -138,1186 +47,1097 @@ pub fn spawn_first_run_if_enabled() {
     {
         return;
     }
-    let manifest_exists = idx_dir().join(MANIFEST_FILE).exists();
-    if manifest_exists {
+    let root = repo_root();
+    if codex_index::Status::load(&root).is_ok_and(|s| s.is_some()) {
         return;
     }
-    std::thread::spawn(|| {
-        let _ = build(&crate::IndexBuildArgs {
-            model: "bge-small".into(),
-            force: false,
-            chunk: "auto".into(),
-            lines: 160,
-            overlap: 32,
-        });
+    std::thread::spawn(move || {
+        let _ = Builder::background(root).build();
     });
 }
 
-/// Spawn periodic maintenance (simple timer-based) that triggers a rebuild
-/// when git reports modified/untracked files. No-op if already started.
+/// Spawn background maintenance. With `CODEX_INDEX_WATCH=1` a file watcher
+/// updates just the changed files as they settle; otherwise (or if the
+/// watcher cannot start) a 5-minute timer rebuilds when git reports
+/// modified/untracked files. No-op if already started.
 pub fn spawn_periodic_maintenance() {
     if STARTED_MAINTENANCE.set(()).is_err() {
         return;
     }
+    if watch_enabled()
+        && codex_index::spawn_watcher(repo_root(), codex_index::watch_debounce()).is_ok()
+    {
+        return;
+    }
     std::thread::spawn(|| {
         loop {
             std::thread::sleep(std::time::Duration::from_secs(300)); // 5 minutes
+            let root = repo_root();
             // Record an attempt timestamp regardless of whether we rebuild.
-            let _ = update_analytics(|mut a| {
-                a.last_attempt_ts = Some(now_iso());
-                a
-            });
-            let changed = git_has_changes();
-            if changed {
-                let _ = build(&crate::IndexBuildArgs {
-                    model: "bge-small".into(),
-                    force: false,
-                    chunk: "auto".into(),
-                    lines: 160,
-                    overlap: 32,
-                });
+            let _ = codex_index::record_attempt(&root);
+            if git_has_changes(&root) {
+                let _ = Builder::background(root).build();
             }
         }
     });
 }
 
-fn repo_root() -> PathBuf {
-    if let Ok(out) = std::process::Command::new("git")
-        .args(["rev-parse", "--show-toplevel"])
-        .output()
-    {
-        if out.status.success() {
-            let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
-            if !s.is_empty() {
-                return PathBuf::from(s);
-            }
-        }
-    }
-    std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
-}
-
-fn idx_dir() -> PathBuf {
-    repo_root().join(INDEX_DIR)
-}
-
-fn ignore_file() -> PathBuf {
-    repo_root().join(IGNORE_FILE)
-}
-
-const DEFAULT_IGNORE: &str = r"# Patterns ignored by the local code index (glob-like)
-# Hidden files/dirs
-.*
-# VCS / tooling / caches
-.git
-.codex
-.idea
-.vscode
-node_modules
-target
-dist
-build
-";
-
-fn load_ignore_patterns() -> Vec<String> {
-    let p = ignore_file();
-    if !p.exists() {
-        let _ = std::fs::write(&p, DEFAULT_IGNORE);
-    }
-    std::fs::read_to_string(&p)
-        .map(|s| {
-            s.lines()
-                .map(|l| l.trim())
-                .filter(|l| !l.is_empty() && !l.starts_with('#'))
-                .map(|l| l.to_string())
-                .collect()
-        })
-        .unwrap_or_default()
-}
-
-fn save_ignore_patterns(mut pats: Vec<String>) -> anyhow::Result<()> {
-    pats.sort();
-    pats.dedup();
-    let body = pats.join(
-        "
-",
-    );
-    std::fs::write(
-        ignore_file(),
-        format!(
-            "{}
-",
-            body
-        ),
-    )
-    .context("write .index-ignore")
-}
-
-fn glob_to_regex(glob: &str) -> Regex {
-    let mut r = String::from("^");
-    for ch in glob.chars() {
-        match ch {
-            '*' => r.push_str(".*"),
-            '?' => r.push('.'),
-            '.' | '+' | '(' | ')' | '|' | '{' | '}' | '[' | ']' | '^' | '$' | '\\' => {
-                r.push('\\');
-                r.push(ch);
-            }
-            _ => r.push(ch),
-        }
-    }
-    r.push('$');
-    Regex::new(&r).unwrap_or_else(|_| Regex::new(r"^$\b").unwrap())
-}
-
-fn ensure_dir() -> Result<()> {
-    fs::create_dir_all(idx_dir()).context("create index dir")
-}
-
-fn now_iso() -> String {
-    OffsetDateTime::now_utc()
-        .format(&time::format_description::well_known::Rfc3339)
-        .unwrap_or_else(|_| "1970-01-01T00:00:00Z".into())
-}
-
-fn read_git_head_sha() -> Option<String> {
-    // Best-effort call to git if available
-    let out = std::process::Command::new("git")
-        .args(["rev-parse", "HEAD"])
-        .output()
-        .ok()?;
-    if out.status.success() {
-        Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
-    } else {
-        None
-    }
+fn watch_enabled() -> bool {
+    std::env::var("CODEX_INDEX_WATCH")
+        .map(|v| matches!(v.as_str(), "1" | "true" | "on"))
+        .unwrap_or(false)
 }
 
 fn build(args: &crate::IndexBuildArgs) -> Result<()> {
-    ensure_dir()?;
-    let _guard = match BuildLock::acquire() {
-        Ok(g) => Some(g),
-        Err(_) => None, // if concurrent, become a no-op quietly
+    let embedder = Provider::parse(&args.embedder)
+        .with_context(|| format!("unknown embedder `{}`", args.embedder))?;
+    let embed_url = match (&args.embed_url, embedder) {
+        (Some(url), _) => Some(url.clone()),
+        (None, Provider::Ollama) => oss_provider_url(),
+        (None, _) => None,
     };
-
-    // Record an attempt timestamp (manual or scheduled)
-    let _ = update_analytics(|mut a| {
-        a.last_attempt_ts = Some(now_iso());
-        a
+    let storage = Storage::parse(&args.storage)
+        .with_context(|| format!("unknown storage `{}`", args.storage))?;
+    let opts = BuildOptions {
+        embedder,
+        model: args
+            .model
+            .clone()
+            .unwrap_or_else(|| embedder.default_model().to_string()),
+        embed_url,
+        force: args.force,
+        chunk: args.chunk.clone(),
+        lines: args.lines,
+        overlap: args.overlap,
+        batch_size: args.batch_size,
+        threads: args.threads,
+        max_file_size: codex_index::parse_size(&args.max_file_size)?,
+        max_files: args.max_files,
+        nice: args.nice,
+        storage,
+        rescore: args.rescore,
+        chunk_header: !args.no_chunk_header,
+    };
+    let history = args.history.then(|| HistoryOptions {
+        since: args.since.clone(),
+        max_commits: args.max_commits,
     });
-
-    // Scan repository files (apply .index-ignore + sanity limits)
-    let root = repo_root();
-    let mut files: Vec<PathBuf> = Vec::new();
-    for dent in WalkBuilder::new(&root)
-        .hidden(true)
-        .git_ignore(true)
-        .git_global(true)
-        .git_exclude(true)
-        .max_depth(None)
-        .build()
-    {
-        let dent = match dent {
-            Ok(d) => d,
-            Err(_) => continue,
-        };
-        let path = dent.path();
-        if !path.is_file() {
-            continue;
+    let progress = ProgressMode::parse(&args.progress);
+    if !args.all {
+        let root = repo_root();
+        let mut builder = Builder::new(root.clone(), opts.clone());
+        if progress != ProgressMode::Off {
+            builder = builder.on_progress(move |p| progress.show(None, p));
         }
-        if should_skip(path) {
-            continue;
+        let report = builder.build()?;
+        warn_skipped("", &report, &opts);
+        if let Some(history) = &history {
+            build_history(&root, &opts, history, "")?;
         }
-        files.push(path.to_path_buf());
+        return Ok(());
     }
-
-    // Prepare embedding model
-    use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
-    let model = match args.model.as_str() {
-        "bge-large" => EmbeddingModel::BGELargeENV15,
-        _ => EmbeddingModel::BGESmallENV15,
-    };
-    let opts = InitOptions::new(model);
-    let mut embedder = TextEmbedding::try_new(opts).context("init fastembed")?;
-
-    // Accumulate chunks, vectors (normalized), ids, and meta rows
-    let mut all_vecs: Vec<Vec<f32>> = Vec::new();
-    let mut all_ids: Vec<u64> = Vec::new();
-    let mut meta_rows: Vec<MetaRow> = Vec::new();
-    let mut next_id: u64 = 0;
-    let mut file_count: usize = 0;
-
-    for p in files.into_iter() {
-        let text = match read_text_if_textual(&p)? {
-            Some(t) => t,
-            None => continue,
-        };
-        file_count += 1;
-        let lang = language_for(&p);
-        let chunks = match args.chunk.as_str() {
-            "lines" => chunk_lines(&text, args.lines.max(8), args.overlap.min(args.lines / 2)),
-            _ => chunk_auto(
-                &text,
-                &p,
-                args.lines.max(80),
-                args.overlap.min(args.lines / 2),
-                &lang,
-            ),
-        };
-        let relp = rel(&root, &p);
-        for (s, e, prev) in chunks {
-            let chunk = &text[s..e];
-            // Embed (single item)
-            let v = embedder
-                .embed(vec![chunk.to_string()], None)
-                .context("embed chunk")?
-                .into_iter()
-                .next()
-                .unwrap_or_default();
-            if v.is_empty() {
-                continue;
+    let on_progress = (progress != ProgressMode::Off).then(|| {
+        Arc::new(move |m: &Member, p: &BuildProgress| progress.show(Some(&m.name), p))
+            as MemberProgressFn
+    });
+    let mut failed = 0;
+    for (member, report) in Workspace::load(&repo_root())?.build_all(&opts, on_progress) {
+        match report {
+            // JSON progress already ends with each member's totals.
+            Ok(r) if progress == ProgressMode::Json => {
+                warn_skipped(&format!("{}: ", member.name), &r, &opts)
             }
-            // Normalize vector (L2)
-            let mut vnorm = v;
-            let mut norm = 0f32;
-            for x in &vnorm {
-                norm += *x as f32 * *x as f32;
+            Ok(r) => {
+                println!(
+                    "{}: {} files, {} chunks ({} embedded)",
+                    member.name, r.files, r.chunks, r.embedded
+                );
+                warn_skipped(&format!("{}: ", member.name), &r, &opts);
             }
-            let norm = norm.sqrt();
-            if norm == 0.0 {
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {e:#}", member.name);
                 continue;
             }
-            for x in vnorm.iter_mut() {
-                *x /= norm;
-            }
-
-            let id = next_id;
-            next_id += 1;
-            all_ids.push(id);
-            all_vecs.push(vnorm);
-            meta_rows.push(MetaRow {
-                id,
-                path: relp.clone(),
-                start: offset_to_line(&text, s),
-                end: offset_to_line(&text, e),
-                lang: lang.clone(),
-                sha256: sha256_hex(chunk),
-                preview: prev,
-            });
         }
-    }
-
-    if all_vecs.is_empty() {
-        // Nothing to index; create minimal manifest and return Ok
-        let created = now_iso();
-        let m = Manifest {
-            index_version: 1,
-            engine: "fastembed+hnsw".into(),
-            model: args.model.clone(),
-            dim: 0,
-            metric: "cosine".into(),
-            chunk_mode: args.chunk.clone(),
-            chunk: ChunkCfg {
-                lines: args.lines,
-                overlap: args.overlap,
-            },
-            repo: RepoInfo {
-                root: root.to_string_lossy().into(),
-                git_sha: read_git_head_sha(),
-            },
-            counts: Counts {
-                files: file_count,
-                chunks: 0,
-            },
-            checksums: Checksums {
-                vectors_hnsw: String::new(),
-                meta_jsonl: String::new(),
-            },
-            created_at: created.clone(),
-            last_refresh: created,
-        };
-        fs::write(
-            idx_dir().join(MANIFEST_FILE),
-            serde_json::to_vec_pretty(&m)?,
-        )?;
-        return Ok(());
-    }
-
-    let dim = all_vecs[0].len();
-    // Flatten vectors for our mmap-friendly linear scan file (ids + contiguous f32)
-    let mut flat: Vec<f32> = Vec::with_capacity(dim * all_vecs.len());
-    for v in &all_vecs {
-        flat.extend_from_slice(&v[..]);
-    }
-
-    // Persist vectors + ids atomically
-    let vec_tmp = idx_dir().join(format!("{}.tmp", VECTORS_FILE));
-    write_vectors_file(&vec_tmp, dim as u32, &all_ids, &flat)?;
-    fs::rename(&vec_tmp, idx_dir().join(VECTORS_FILE))?;
-
-    // Persist meta.jsonl atomically
-    let meta_tmp = idx_dir().join(format!("{}.tmp", META_FILE));
-    {
-        let mut w = BufWriter::new(File::create(&meta_tmp)?);
-        for row in &meta_rows {
-            let line = serde_json::to_string(row)?;
-            w.write_all(line.as_bytes())?;
-            w.write_all(
-                b"
-",
-            )?;
+        if let Some(history) = &history
+            && let Err(e) =
+                build_history(&member.root, &opts, history, &format!("{}: ", member.name))
+        {
+            failed += 1;
+            eprintln!("{}: history: {e:#}", member.name);
         }
-        w.flush()?;
     }
-    fs::rename(&meta_tmp, idx_dir().join(META_FILE))?;
-
-    // Build and persist HNSW graph (prefer fast query path)
-    {
-        let max_points = all_vecs.len();
-        let m = 32usize; // max connections (M)
-        let ef_c = 200usize; // construction ef
-        let max_layer = 16usize;
-        let dist = DistCosine::default();
-        let hnsw: Hnsw<f32, DistCosine> = Hnsw::new(m, max_points, max_layer, ef_c, dist);
-        // Prepare slice of (&vec, id)
-        let items: Vec<(&[f32], usize)> = all_vecs
-            .iter()
-            .enumerate()
-            .map(|(i, v)| (&v[..], i))
-            .collect();
-        hnsw.parallel_insert_slice(&items);
-        // Dump alongside other index files: vectors.hnsw.graph + vectors.hnsw.data
-        hnsw.file_dump(idx_dir().as_path(), HNSW_BASENAME)
-            .context("dump HNSW files")?;
+    if failed > 0 {
+        bail!("{failed} workspace index build(s) failed");
     }
-
-    // Compute checksums for manifest
-    let chk_vec = sha256_file(idx_dir().join(VECTORS_FILE))?;
-    let chk_meta = sha256_file(idx_dir().join(META_FILE))?;
-
-    let now = now_iso();
-    let manifest = Manifest {
-        index_version: 1,
-        engine: "fastembed+hnsw".into(),
-        model: args.model.clone(),
-        dim,
-        metric: "cosine".into(),
-        chunk_mode: args.chunk.clone(),
-        chunk: ChunkCfg {
-            lines: args.lines,
-            overlap: args.overlap,
-        },
-        repo: RepoInfo {
-            root: root.to_string_lossy().into(),
-            git_sha: read_git_head_sha(),
-        },
-        counts: Counts {
-            files: file_count,
-            chunks: all_ids.len(),
-        },
-        checksums: Checksums {
-            vectors_hnsw: chk_vec,
-            meta_jsonl: chk_meta,
-        },
-        created_at: now.clone(),
-        last_refresh: now.clone(),
-    };
-    let man_tmp = idx_dir().join(format!("{}.tmp", MANIFEST_FILE));
-    fs::write(&man_tmp, serde_json::to_vec_pretty(&manifest)?)?;
-    fs::rename(&man_tmp, idx_dir().join(MANIFEST_FILE))?;
-
     Ok(())
 }
-fn verify() -> Result<()> {
-    let m: Manifest = serde_json::from_slice(&fs::read(idx_dir().join(MANIFEST_FILE))?)?;
-    let vchk = sha256_file(idx_dir().join(VECTORS_FILE))?;
-    let mchk = sha256_file(idx_dir().join(META_FILE))?;
-    if vchk == m.checksums.vectors_hnsw && mchk == m.checksums.meta_jsonl {
-        println!("Index verify: OK");
-        Ok(())
-    } else {
-        bail!("Index verify FAILED (checksums mismatch)")
-    }
-}
 
-fn clean() -> Result<()> {
-    if idx_dir().exists() {
-        fs::remove_dir_all(idx_dir())?;
-    }
-    println!("Index removed");
-    Ok(())
+// How `index build` reports progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProgressMode {
+    /// A bar redrawn in place on stderr.
+    Bar,
+    /// One JSON object per event on stdout.
+    Json,
+    Off,
 }
 
-fn ignore_cmd(args: &crate::IndexIgnoreArgs) -> anyhow::Result<()> {
-    if args.reset {
-        std::fs::write(ignore_file(), DEFAULT_IGNORE).context("write default .index-ignore")?;
-    }
-    let mut pats = load_ignore_patterns();
-    if !args.add.is_empty() {
-        pats.extend(args.add.clone());
-    }
-    if !args.remove.is_empty() {
-        let remove: std::collections::HashSet<String> = args.remove.iter().cloned().collect();
-        pats.retain(|p| !remove.contains(p));
-    }
-    if args.reset || !args.add.is_empty() || !args.remove.is_empty() {
-        save_ignore_patterns(pats)?;
+impl ProgressMode {
+    fn parse(arg: &str) -> Self {
+        match arg {
+            "bar" => Self::Bar,
+            "json" => Self::Json,
+            "off" => Self::Off,
+            _ if std::io::stderr().is_terminal() => Self::Bar,
+            _ => Self::Off,
+        }
     }
-    if args.list || (!args.reset && args.add.is_empty() && args.remove.is_empty()) {
-        print!(
-            "Ignore file: {}
-",
-            ignore_file().display()
-        );
-        for p in load_ignore_patterns() {
-            println!("{p}");
+
+    // `member` names the workspace member being built with --all.
+    fn show(self, member: Option<&str>, p: &BuildProgress) {
+        match self {
+            Self::Bar => {
+                let prefix = member.map(|m| format!("{m}: ")).unwrap_or_default();
+                let end = if p.phase == BuildPhase::Done {
+                    "\n"
+                } else {
+                    ""
+                };
+                eprint!(
+                    "\r\x1b[2K{prefix}{}{end}",
+                    codex_index::format_progress_line(p)
+                );
+            }
+            Self::Json => {
+                let mut event = serde_json::to_value(p).unwrap_or_default();
+                if let Some(m) = member {
+                    event["member"] = m.into();
+                }
+                println!("{event}");
+            }
+            Self::Off => {}
         }
     }
-    Ok(())
 }
 
-// ---------- helpers ----------
-
-fn load_vectors(path: impl AsRef<Path>) -> Result<(Vec<u64>, Vec<f32>)> {
-    let mut f = BufReader::new(File::open(path)?);
-    let mut buf4 = [0u8; 4];
-    let mut buf8 = [0u8; 8];
-    f.read_exact(&mut buf4)?; // magic
-    f.read_exact(&mut buf4)?;
-    let _dim = u32::from_le_bytes(buf4) as usize;
-    f.read_exact(&mut buf8)?;
-    let rows = u64::from_le_bytes(buf8) as usize;
-    let mut ids = Vec::with_capacity(rows);
-    for _ in 0..rows {
-        f.read_exact(&mut buf8)?;
-        ids.push(u64::from_le_bytes(buf8));
-    }
-    let mut data_bytes = Vec::new();
-    f.read_to_end(&mut data_bytes)?;
-    let n = rows.checked_mul(_dim).unwrap_or(0);
-    let mut data = Vec::with_capacity(n);
-    for i in 0..n {
-        let off = i * 4;
-        let v = f32::from_le_bytes([
-            data_bytes[off],
-            data_bytes[off + 1],
-            data_bytes[off + 2],
-            data_bytes[off + 3],
-        ]);
-        data.push(v);
+fn warn_skipped(prefix: &str, report: &BuildReport, opts: &BuildOptions) {
+    if report.skipped > 0 {
+        eprintln!(
+            "{prefix}{} files not indexed (--max-files {})",
+            report.skipped, opts.max_files
+        );
     }
-    Ok((ids, data))
 }
-fn write_vectors_file(path: impl AsRef<Path>, dim: u32, ids: &[u64], data: &[f32]) -> Result<()> {
-    let mut w = BufWriter::new(File::create(path)?);
-    // magic + dim + rows + ids + data
-    w.write_all(b"VEC0")?;
-    w.write_all(&dim.to_le_bytes())?;
-    let rows = ids.len() as u64;
-    w.write_all(&rows.to_le_bytes())?;
-    for id in ids {
-        w.write_all(&id.to_le_bytes())?;
-    }
-    // write f32 little endian
-    for &v in data {
-        w.write_all(&v.to_le_bytes())?;
-    }
-    w.flush()?;
+
+fn build_history(
+    root: &std::path::Path,
+    opts: &BuildOptions,
+    history: &HistoryOptions,
+    prefix: &str,
+) -> Result<()> {
+    let r = codex_index::build_history(root, opts, history)?;
+    println!(
+        "{prefix}history: {} commits ({} new), {} rows ({} embedded)",
+        r.commits, r.new_commits, r.rows, r.embedded
+    );
     Ok(())
 }
 
-fn load_meta(path: impl AsRef<Path>) -> Result<std::collections::HashMap<u64, MetaRow>> {
-    let f = BufReader::new(File::open(path)?);
-    let mut map = std::collections::HashMap::new();
-    for line in f.lines() {
-        let l = line?;
-        if l.trim().is_empty() {
-            continue;
-        }
-        let row: MetaRow = serde_json::from_str(&l)?;
-        map.insert(row.id, row);
-    }
-    Ok(map)
+/// Ollama endpoint of the `oss` model provider from the Codex config (falls
+/// back to the built-in provider, which honors `CODEX_OSS_BASE_URL`/`CODEX_OSS_PORT`).
+fn oss_provider_url() -> Option<String> {
+    let id = codex_core::BUILT_IN_OSS_MODEL_PROVIDER_ID;
+    let configured = codex_core::config::Config::load_with_cli_overrides(
+        Vec::new(),
+        codex_core::config::ConfigOverrides::default(),
+    )
+    .ok()
+    .and_then(|cfg| cfg.model_providers.get(id).and_then(|p| p.base_url.clone()));
+    configured
+        .or_else(|| {
+            codex_core::built_in_model_providers()
+                .get(id)
+                .and_then(|p| p.base_url.clone())
+        })
+        .map(|url| codex_index::embed::ollama_base(&url))
 }
 
-fn sha256_file(path: impl AsRef<Path>) -> Result<String> {
-    let mut f = File::open(path)?;
-    let mut hasher = Sha256::new();
-    let mut buf = [0u8; 8192];
-    loop {
-        let n = f.read(&mut buf)?;
-        if n == 0 {
-            break;
+impl From<crate::SearchModeArg> for SearchMode {
+    fn from(m: crate::SearchModeArg) -> Self {
+        match m {
+            crate::SearchModeArg::Semantic => SearchMode::Semantic,
+            crate::SearchModeArg::Lexical => SearchMode::Lexical,
+            crate::SearchModeArg::Hybrid => SearchMode::Hybrid,
         }
-        hasher.update(&buf[..n]);
     }
-    Ok(hex::encode(hasher.finalize()))
 }
 
-fn sha256_hex(s: &str) -> String {
-    hex::encode(Sha256::digest(s.as_bytes()))
-}
+fn query(args: &crate::IndexQueryArgs) -> Result<()> {
+    let root = repo_root();
+    let mut opts = SearchOptions {
+        k: args.k,
+        mode: args.mode.clone().into(),
+        paths: args.path.clone(),
+        excludes: args.exclude.clone(),
+        langs: args.lang.clone(),
+        rerank: args.rerank.clone(),
+        diversity: args.diversity,
+    };
+    let query_text = opts.take_inline_filters(&args.query);
+    if args.scope == "history" {
+        return query_history(&root, &query_text, &opts, args);
+    }
+    let workspace = if args.local {
+        None
+    } else {
+        Some(Workspace::load(&root)?).filter(Workspace::is_federated)
+    };
+    let (hits, timings) = if let Some(ws) = workspace {
+        ws.search_timed(&query_text, &opts)?
+    } else if let Some(found) = server_query(&root, &query_text, &opts) {
+        found
+    } else {
+        autostart_server(&root);
+        Retriever::open(&root)?.search_timed(&query_text, &opts)?
+    };
 
-fn rel(root: &Path, p: &Path) -> String {
-    pathdiff::diff_paths(p, root)
-        .unwrap_or_else(|| p.to_path_buf())
-        .to_string_lossy()
-        .to_string()
+    // Confidence gating: hide low-confidence results (< threshold)
+    let threshold = codex_index::score_threshold(0.60);
+    let top = hits.iter().map(|h| h.score).fold(0.0f32, f32::max);
+    codex_index::record_query(&root, top >= threshold)?;
+    let mut record = QueryRecord::new(QuerySource::Cli, &query_text, &hits, threshold, timings);
+    let started = Instant::now();
+    if hits.is_empty() || top < threshold {
+        match args.output {
+            crate::OutputFormatArg::Text => println!("{NO_MATCH}"),
+            crate::OutputFormatArg::Json => println!("[]"),
+            crate::OutputFormatArg::Xml => println!("<results/>"),
+        }
+    } else if args.group_by.is_some() {
+        print_files(&codex_index::group_by_file(&hits), args)?;
+    } else {
+        print_hits(&hits, args)?;
+    }
+    record.format_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let _ = codex_index::log_query(&root, &record);
+    Ok(())
 }
 
-fn language_for(path: &Path) -> String {
-    match path.extension().and_then(|s| s.to_str()).unwrap_or("") {
-        "rs" => "rust",
-        "ts" | "tsx" => "typescript",
-        "js" | "jsx" => "javascript",
-        "py" => "python",
-        _ => "text",
+// `--scope history`: commits whose message or diff matches.
+fn query_history(
+    root: &std::path::Path,
+    query_text: &str,
+    opts: &SearchOptions,
+    args: &crate::IndexQueryArgs,
+) -> Result<()> {
+    let (hits, timings) = codex_index::search_history_timed(root, query_text, opts)?;
+    let threshold = codex_index::score_threshold(0.60);
+    let top = hits.iter().map(|h| h.score).fold(0.0f32, f32::max);
+    codex_index::record_query(root, top >= threshold)?;
+    let mut record =
+        QueryRecord::for_commits(QuerySource::Cli, query_text, &hits, threshold, timings);
+    let started = Instant::now();
+    if hits.is_empty() || top < threshold {
+        match args.output {
+            crate::OutputFormatArg::Text => println!("{NO_MATCH}"),
+            crate::OutputFormatArg::Json => println!("[]"),
+            crate::OutputFormatArg::Xml => println!("<results/>"),
+        }
+    } else {
+        print_commits(&hits, args)?;
     }
-    .into()
+    record.format_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let _ = codex_index::log_query(root, &record);
+    Ok(())
 }
 
-fn should_skip(path: &Path) -> bool {
-    let relp = rel(&repo_root(), path);
-    let patterns = load_ignore_patterns();
-    if !patterns.is_empty() {
-        for p in &patterns {
-            let re = glob_to_regex(p);
-            if re.is_match(&relp) {
-                return true;
+fn print_commits(hits: &[CommitHit], args: &crate::IndexQueryArgs) -> Result<()> {
+    match args.output {
+        crate::OutputFormatArg::Text => {
+            for (rank, hit) in hits.iter().enumerate() {
+                print_commit_text(rank, hit, args);
             }
         }
-    }
-    // Skip symlinks to avoid cycles and out-of-tree traversal
-    if let Ok(md) = fs::symlink_metadata(path) {
-        if md.file_type().is_symlink() {
-            return true;
-        }
-    }
-    // Conservative Windows path handling: skip very long paths
-    #[cfg(windows)]
-    {
-        use std::os::windows::ffi::OsStrExt;
-        let wlen = path.as_os_str().encode_wide().count();
-        if wlen > 245 {
-            // ~MAX_PATH minus buffer
-            return true;
+        crate::OutputFormatArg::Json => {
+            let items: Vec<serde_json::Value> = hits
+                .iter()
+                .enumerate()
+                .map(|(rank, hit)| {
+                    serde_json::json!({
+                        "rank": rank,
+                        "score": (hit.score as f64),
+                        "sha": hit.sha,
+                        "author": hit.author,
+                        "date": hit.date,
+                        "subject": hit.subject,
+                        "paths": hit.paths,
+                        "show": hit.show_command(),
+                        "snippet": args.show_snippets.then(|| hit.preview.lines().collect::<Vec<_>>()),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&items)?);
         }
-    }
-    if let Ok(md) = fs::metadata(path) {
-        if md.len() > 5 * 1024 * 1024 {
-            return true;
+        crate::OutputFormatArg::Xml => {
+            println!("<results>");
+            for (rank, hit) in hits.iter().enumerate() {
+                println!(
+                    r#"  <commit rank="{rank}" score="{:.3}" sha="{}" author="{}" date="{}" show="{}">"#,
+                    hit.score,
+                    hit.sha,
+                    xml_escape(&hit.author),
+                    xml_escape(&hit.date),
+                    xml_escape(&hit.show_command())
+                );
+                println!("    <subject>{}</subject>", xml_escape(&hit.subject));
+                for path in &hit.paths {
+                    println!("    <path>{}</path>", xml_escape(path));
+                }
+                if args.show_snippets {
+                    println!("    <snippet>");
+                    for line in hit.preview.lines() {
+                        println!("      <line>{}</line>", xml_escape(line));
+                    }
+                    println!("    </snippet>");
+                }
+                println!("  </commit>");
+            }
+            println!("</results>");
         }
     }
-    false
-}
-
-fn read_text_if_textual(path: &Path) -> Result<Option<String>> {
-    let mut f = File::open(path)?;
-    let mut buf = Vec::new();
-    f.read_to_end(&mut buf)?;
-    if looks_binary(&buf) {
-        return Ok(None);
-    }
-    Ok(String::from_utf8(buf).ok())
+    Ok(())
 }
 
-fn looks_binary(buf: &[u8]) -> bool {
-    if buf.len() >= 4 {
-        let magic = &buf[..8.min(buf.len())];
-        const PDF: &[u8] = b"%PDF-";
-        const ZIP: &[u8] = b"PK\x03\x04";
-        const ELF: &[u8] = b"\x7FELF";
-        const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
-        const MZ: &[u8] = b"MZ";
-        if magic.starts_with(PDF)
-            || magic.starts_with(ZIP)
-            || magic.starts_with(ELF)
-            || magic.starts_with(PNG)
-            || magic.starts_with(MZ)
-        {
-            return true;
+// `[rank] score sha date author subject`, then the `git show` command.
+fn print_commit_text(rank: usize, hit: &CommitHit, args: &crate::IndexQueryArgs) {
+    let date = hit.date.get(..10).unwrap_or(&hit.date);
+    println!(
+        "[{rank}] {:.3} {} {date} {}: {}",
+        hit.score,
+        hit.short_sha(),
+        hit.author,
+        hit.subject
+    );
+    println!("    {}", hit.show_command());
+    if args.show_snippets {
+        for line in hit.preview.lines() {
+            println!("    | {line}");
         }
+        println!("---");
     }
-    // if >10% NUL bytes or invalid UTF-8
-    let nul = buf.iter().filter(|b| **b == 0).count();
-    if nul * 10 > buf.len().max(1) {
-        return true;
-    }
-    std::str::from_utf8(buf).is_err()
 }
 
-fn chunk_auto(
-    text: &str,
-    _path: &Path,
-    target: usize,
-    overlap: usize,
-    lang: &str,
-) -> Vec<(usize, usize, String)> {
-    if lang == "rust" {
-        if let Some(chunks) = chunk_rust_treesitter(text, target, overlap) {
-            return chunks;
-        }
-    }
-    if lang == "python" {
-        if let Some(chunks) = chunk_python_treesitter(text, target, overlap) {
-            return chunks;
-        }
+fn text_format(args: &crate::IndexQueryArgs) -> TextFormat {
+    TextFormat {
+        snippets: args.show_snippets,
+        line_numbers: !args.no_line_numbers,
+        line_number_width: args.line_number_width,
+        diff: args.diff,
     }
-    // fallback
-    chunk_blanklines(text, target, overlap)
 }
 
-fn chunk_lines(text: &str, target: usize, overlap: usize) -> Vec<(usize, usize, String)> {
-    // Build line start offsets
-    let mut starts = vec![0usize];
-    for (i, _) in text.match_indices("\n") {
-        starts.push(i + 1);
-    }
-    let line_count = starts.len();
-    let mut res = Vec::new();
-    let mut cur = 1usize; // 1-based line index
-    while cur <= line_count {
-        let end_line = (cur + target - 1).min(line_count);
-        let s = starts[cur - 1];
-        let e = if end_line < line_count {
-            starts[end_line]
-        } else {
-            text.len()
-        };
-        if e > s {
-            res.push((s, e, preview(&text[s..e])));
+fn print_hits(hits: &[Hit], args: &crate::IndexQueryArgs) -> Result<()> {
+    match args.output {
+        crate::OutputFormatArg::Text => {
+            print!(
+                "{}",
+                codex_index::format_hits_text(hits, &text_format(args))
+            );
         }
-        if end_line == line_count {
-            break;
+        crate::OutputFormatArg::Json => {
+            let items: Vec<serde_json::Value> = hits
+                .iter()
+                .enumerate()
+                .map(|(rank, hit)| json_hit(rank, hit, args.show_snippets))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&items)?);
         }
-        let next = end_line.saturating_sub(overlap) + 1;
-        if next <= cur {
-            break;
+        crate::OutputFormatArg::Xml => {
+            println!("<results>");
+            for (rank, hit) in hits.iter().enumerate() {
+                print_xml_hit(rank, hit, &text_format(args));
+            }
+            println!("</results>");
         }
-        cur = next;
     }
-    res
+    Ok(())
 }
 
-fn chunk_blanklines(text: &str, target: usize, overlap: usize) -> Vec<(usize, usize, String)> {
-    let mut blocks: Vec<(usize, usize)> = Vec::new();
-    let mut start = 0usize;
-    let mut i = 0usize;
-    let bytes = text.as_bytes();
-    while i < bytes.len() {
-        if bytes[i] == b'\n' && i + 1 < bytes.len() && bytes[i + 1] == b'\n' {
-            blocks.push((start, i + 1));
-            start = i + 2;
-            i += 2;
-            continue;
-        }
-        i += 1;
-    }
-    blocks.push((start, text.len()));
-    let mut res = Vec::new();
-    let mut cur_start = blocks[0].0;
-    let mut cur_end = blocks[0].1;
-    for b in blocks.into_iter().skip(1) {
-        if line_span(text, cur_start, b.1) < target {
-            cur_end = b.1;
-        } else {
-            res.push((cur_start, cur_end, preview(&text[cur_start..cur_end])));
-            cur_start = b.0;
-            cur_end = b.1;
+// `--group-by file`: each file once, with its best score and every range.
+fn print_files(files: &[FileHits], args: &crate::IndexQueryArgs) -> Result<()> {
+    match args.output {
+        crate::OutputFormatArg::Text => {
+            print!(
+                "{}",
+                codex_index::format_files_text(files, &text_format(args))
+            );
         }
-    }
-    res.push((cur_start, cur_end, preview(&text[cur_start..cur_end])));
-    if overlap > 0 && res.len() > 1 {
-        for i in 1..res.len() {
-            let (s, e, pr) = res[i].clone();
-            let new_s = back_n_lines(text, s, overlap);
-            res[i] = (new_s, e, pr);
+        crate::OutputFormatArg::Json => {
+            let items: Vec<serde_json::Value> = files
+                .iter()
+                .enumerate()
+                .map(|(rank, file)| {
+                    let ranges: Vec<serde_json::Value> = file
+                        .ranges
+                        .iter()
+                        .map(|hit| {
+                            let snippet_lines = args
+                                .show_snippets
+                                .then(|| hit.preview.lines().collect::<Vec<_>>());
+                            serde_json::json!({
+                                "start": hit.start,
+                                "end": hit.end,
+                                "score": (hit.score as f64),
+                                "snippet": snippet_lines,
+                            })
+                        })
+                        .collect();
+                    serde_json::json!({
+                        "rank": rank,
+                        "score": (file.score as f64),
+                        "repo": file.repo,
+                        "path": file.path,
+                        "lang": file.lang,
+                        "ranges": ranges,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&items)?);
         }
-    }
-    res
-}
-
-fn back_n_lines(text: &str, mut off: usize, n: usize) -> usize {
-    let mut k = 0;
-    while off > 0 && k < n {
-        off -= 1;
-        if text.as_bytes()[off] == b'\n' {
-            k += 1;
+        crate::OutputFormatArg::Xml => {
+            println!("<results>");
+            for (rank, file) in files.iter().enumerate() {
+                let repo = file
+                    .repo
+                    .as_deref()
+                    .map(|r| format!(r#" repo="{}""#, xml_escape(r)))
+                    .unwrap_or_default();
+                println!(
+                    r#"  <file rank="{rank}" score="{:.3}"{repo} path="{}" lang="{}">"#,
+                    file.score,
+                    xml_escape(&file.path),
+                    xml_escape(&file.lang)
+                );
+                for hit in &file.ranges {
+                    println!(
+                        r#"    <range start="{}" end="{}" score="{:.3}">"#,
+                        hit.start, hit.end, hit.score
+                    );
+                    if args.show_snippets {
+                        print_xml_lines(hit, &text_format(args), "      ");
+                    }
+                    println!("    </range>");
+                }
+                println!("  </file>");
+            }
+            println!("</results>");
         }
     }
-    off
-}
-
-fn line_span(text: &str, s: usize, e: usize) -> usize {
-    text[s..e].bytes().filter(|b| *b == b'\n').count() + 1
+    Ok(())
 }
 
-fn offset_to_line(text: &str, off: usize) -> usize {
-    text[..off].bytes().filter(|b| *b == b'\n').count() + 1
+fn json_hit(rank: usize, hit: &Hit, snippets: bool) -> serde_json::Value {
+    let snippet_lines = snippets.then(|| hit.preview.lines().collect::<Vec<_>>());
+    serde_json::json!({
+        "rank": rank,
+        "score": (hit.score as f64),
+        "semantic": (hit.semantic as f64),
+        "lexical": (hit.lexical as f64),
+        "rerank": hit.rerank.map(f64::from),
+        "repo": hit.repo,
+        "path": hit.path,
+        "start": hit.start,
+        "end": hit.end,
+        "lang": hit.lang,
+        "section": hit.section,
+        "snippet": snippet_lines,
+    })
 }
 
-fn preview(chunk: &str) -> String {
-    let mut p = chunk.lines().take(8).collect::<Vec<_>>().join("\n");
-    if p.len() > 800 {
-        p.truncate(800);
+fn print_xml_hit(rank: usize, hit: &Hit, fmt: &TextFormat) {
+    let repo = hit
+        .repo
+        .as_deref()
+        .map(|r| format!(r#" repo="{}""#, xml_escape(r)))
+        .unwrap_or_default();
+    let cosine = hit
+        .rerank
+        .map(|_| format!(r#" semantic="{:.3}""#, hit.semantic))
+        .unwrap_or_default();
+    let section = hit
+        .section
+        .as_deref()
+        .map(|s| format!(r#" section="{}""#, xml_escape(s)))
+        .unwrap_or_default();
+    println!(
+        r#"  <hit rank="{rank}" score="{:.3}"{cosine}{repo} path="{}" start="{}" end="{}" lang="{}"{section}>"#,
+        hit.score,
+        xml_escape(&hit.path),
+        hit.start,
+        hit.end,
+        xml_escape(&hit.lang)
+    );
+    if !fmt.snippets {
+        println!("  </hit>");
+        return;
     }
-    p
+    println!("    <snippet>");
+    print_xml_lines(hit, fmt, "      ");
+    println!("    </snippet>\n  </hit>");
 }
 
-fn chunk_rust_treesitter(
-    text: &str,
-    target: usize,
-    overlap: usize,
-) -> Option<Vec<(usize, usize, String)>> {
-    let mut parser = tree_sitter::Parser::new();
-
-    let lang_ts: tree_sitter::Language = tree_sitter_rust::LANGUAGE.into();
-    parser.set_language(&lang_ts).ok()?;
-    let tree = parser.parse(text, None)?;
-    let root = tree.root_node();
-    let mut ranges: Vec<(usize, usize)> = Vec::new();
-    fn collect(node: tree_sitter::Node, out: &mut Vec<(usize, usize)>) {
-        let kind = node.kind();
-        if matches!(
-            kind,
-            "function_item" | "impl_item" | "trait_item" | "struct_item" | "enum_item" | "mod_item"
-        ) {
-            let r = node.range();
-            out.push((r.start_byte, r.end_byte));
-        }
-        for i in 0..node.child_count() {
-            if let Some(ch) = node.child(i) {
-                collect(ch, out);
-            }
-        }
-    }
-    collect(root, &mut ranges);
-    if ranges.is_empty() {
-        return None;
-    }
-    ranges.sort_by_key(|r| r.0);
-    let mut merged: Vec<(usize, usize)> = Vec::new();
-    let mut cur = ranges[0];
-    for r in ranges.into_iter().skip(1) {
-        if line_span(text, cur.0, r.1) < target {
-            cur.1 = r.1;
+fn print_xml_lines(hit: &Hit, fmt: &TextFormat, indent: &str) {
+    let op = if fmt.diff { r#" op="add""# } else { "" };
+    for (i, line) in hit.preview.lines().enumerate() {
+        if !fmt.line_numbers {
+            println!(r#"{indent}<line{op}>{}</line>"#, xml_escape(line));
         } else {
-            merged.push(cur);
-            cur = r;
-        }
-    }
-    merged.push(cur);
-    let mut res = Vec::new();
-    for (s, e) in merged {
-        res.push((s, e, preview(&text[s..e])));
-    }
-    if overlap > 0 && res.len() > 1 {
-        for i in 1..res.len() {
-            let (s, e, pr) = res[i].clone();
-            let new_s = back_n_lines(text, s, overlap);
-            res[i] = (new_s, e, pr);
+            let ln = hit.start.saturating_add(i);
+            println!(r#"{indent}<line n="{ln}"{op}>{}</line>"#, xml_escape(line));
         }
     }
-    Some(res)
 }
 
-fn chunk_python_treesitter(
-    text: &str,
-    target: usize,
-    overlap: usize,
-) -> Option<Vec<(usize, usize, String)>> {
-    let mut parser = tree_sitter::Parser::new();
-    let lang_ts: tree_sitter::Language = tree_sitter_python::LANGUAGE.into();
-    if parser.set_language(&lang_ts).is_err() {
-        return None;
-    }
-    let tree = parser.parse(text, None)?;
-    let root = tree.root_node();
-    let mut ranges: Vec<(usize, usize)> = Vec::new();
-    fn collect(node: tree_sitter::Node, out: &mut Vec<(usize, usize)>) {
-        let kind = node.kind();
-        if matches!(kind, "function_definition" | "class_definition") {
-            let r = node.range();
-            out.push((r.start_byte, r.end_byte));
-        }
-        for i in 0..node.child_count() {
-            if let Some(ch) = node.child(i) {
-                collect(ch, out);
+fn symbols(args: &crate::IndexSymbolsArgs) -> Result<()> {
+    let root = repo_root();
+    let found = match server_symbols(&root, args) {
+        Some(found) => found,
+        None => codex_index::find_symbols(
+            &root,
+            &args.name,
+            args.kind.as_deref(),
+            args.exact,
+            args.limit,
+        )?,
+    };
+    match args.output {
+        crate::OutputFormatArg::Text => {
+            if found.is_empty() {
+                println!("No symbols match `{}`.", args.name);
+            }
+            for m in &found {
+                let sym = &m.symbol;
+                let qualified = match &sym.container {
+                    Some(c) => format!("{c}::{}", sym.name),
+                    None => sym.name.clone(),
+                };
+                println!("{}:{} {} {qualified}", m.path, sym.line, sym.kind);
             }
         }
-    }
-    collect(root, &mut ranges);
-    if ranges.is_empty() {
-        return None;
-    }
-    ranges.sort_by_key(|r| r.0);
-    let mut merged: Vec<(usize, usize)> = Vec::new();
-    let mut cur = ranges[0];
-    for r in ranges.into_iter().skip(1) {
-        if line_span(text, cur.0, r.1) < target {
-            cur.1 = r.1;
-        } else {
-            merged.push(cur);
-            cur = r;
+        crate::OutputFormatArg::Json => {
+            let items: Vec<serde_json::Value> = found
+                .iter()
+                .map(|m| {
+                    serde_json::json!({
+                        "name": m.symbol.name,
+                        "kind": m.symbol.kind,
+                        "container": m.symbol.container,
+                        "path": m.path,
+                        "line": m.symbol.line,
+                        "score": (m.score as f64),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&items)?);
         }
-    }
-    merged.push(cur);
-    let mut res = Vec::new();
-    for (s, e) in merged {
-        res.push((s, e, preview(&text[s..e])));
-    }
-    if overlap > 0 && res.len() > 1 {
-        for i in 1..res.len() {
-            let (s, e, pr) = res[i].clone();
-            let new_s = back_n_lines(text, s, overlap);
-            res[i] = (new_s, e, pr);
+        crate::OutputFormatArg::Xml => {
+            if found.is_empty() {
+                println!("<symbols/>");
+                return Ok(());
+            }
+            println!("<symbols>");
+            for m in &found {
+                let sym = &m.symbol;
+                let container = sym
+                    .container
+                    .as_deref()
+                    .map(|c| format!(r#" container="{}""#, xml_escape(c)))
+                    .unwrap_or_default();
+                println!(
+                    r#"  <symbol name="{}" kind="{}"{container} path="{}" line="{}" score="{:.3}"/>"#,
+                    xml_escape(&sym.name),
+                    xml_escape(&sym.kind),
+                    xml_escape(&m.path),
+                    sym.line,
+                    m.score,
+                );
+            }
+            println!("</symbols>");
         }
     }
-    Some(res)
+    Ok(())
 }
 
-// Embedding: fastembed wrapper (sync)
-fn embed_text(model_name: &str, text: &str) -> Result<Vec<f32>> {
-    use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
-    let model = match model_name {
-        "bge-large-en-v1.5" => EmbeddingModel::BGELargeENV15,
-        _ => EmbeddingModel::BGESmallENV15,
+fn similar(args: &crate::IndexSimilarArgs) -> Result<()> {
+    let root = repo_root();
+    let (path, line) = codex_index::parse_location(&root, &args.location)?;
+    let opts = SearchOptions {
+        k: args.k,
+        paths: args.path.clone(),
+        excludes: args.exclude.clone(),
+        langs: args.lang.clone(),
+        diversity: args.diversity,
+        ..SearchOptions::default()
     };
-    let opts = InitOptions::new(model);
-    let mut embedder = TextEmbedding::try_new(opts).context("init fastembed")?;
-    let out = embedder
-        .embed(vec![text.to_string()], None)
-        .context("embed")?;
-    Ok(out.into_iter().next().unwrap_or_default())
-}
-
-fn cosine(a: &[f32], b: &[f32]) -> f32 {
-    let mut dot = 0f32;
-    let mut na = 0f32;
-    let mut nb = 0f32;
-    for i in 0..a.len() {
-        let x = a[i];
-        let y = b[i];
-        dot += x * y;
-        na += x * x;
-        nb += y * y;
-    }
-    if na == 0.0 || nb == 0.0 {
-        return 0.0;
+    let found = Retriever::open(&root)?.similar(&path, line, &opts)?;
+    let fmt = TextFormat {
+        snippets: args.show_snippets,
+        line_numbers: !args.no_line_numbers,
+        line_number_width: args.line_number_width,
+        diff: false,
+    };
+    match args.output {
+        crate::OutputFormatArg::Text => {
+            print!("{}", codex_index::format_similar_text(&found, &fmt));
+        }
+        crate::OutputFormatArg::Json => {
+            let hits: Vec<serde_json::Value> = found
+                .hits
+                .iter()
+                .enumerate()
+                .map(|(rank, hit)| json_hit(rank, hit, args.show_snippets))
+                .collect();
+            let source = &found.source;
+            let out = serde_json::json!({
+                "source": {
+                    "path": source.path,
+                    "start": source.start,
+                    "end": source.end,
+                    "lang": source.lang,
+                    "section": source.section,
+                },
+                "hits": hits,
+            });
+            println!("{}", serde_json::to_string_pretty(&out)?);
+        }
+        crate::OutputFormatArg::Xml => {
+            let source = &found.source;
+            println!(
+                r#"<results source="{}:{}-{}">"#,
+                xml_escape(&source.path),
+                source.start,
+                source.end
+            );
+            for (rank, hit) in found.hits.iter().enumerate() {
+                print_xml_hit(rank, hit, &fmt);
+            }
+            println!("</results>");
+        }
     }
-    dot / (na.sqrt() * nb.sqrt())
+    Ok(())
 }
 
-// ---------- analytics ----------
-fn read_analytics() -> Result<Analytics> {
-    let p = idx_dir().join(ANALYTICS_FILE);
-    if !p.exists() {
-        return Ok(Analytics::default());
+fn dupes(args: &crate::IndexDupesArgs) -> Result<()> {
+    let opts = codex_index::DupesOptions {
+        min_score: args.min_score,
+        min_lines: args.min_lines,
+        limit: args.limit,
+        paths: args.path.clone(),
+        excludes: args.exclude.clone(),
+        langs: args.lang.clone(),
+    };
+    let groups = Retriever::open(&repo_root())?.dupes(&opts);
+    match args.output {
+        crate::OutputFormatArg::Text => print!("{}", codex_index::format_dupes_text(&groups)),
+        crate::OutputFormatArg::Json => println!("{}", serde_json::to_string_pretty(&groups)?),
+        crate::OutputFormatArg::Xml => {
+            println!("<dupes>");
+            for g in &groups {
+                println!(
+                    r#"  <group score="{:.3}" redundant_lines="{}">"#,
+                    g.score, g.redundant_lines
+                );
+                for hit in &g.chunks {
+                    println!(
+                        r#"    <chunk path="{}" start="{}" end="{}" score="{:.3}"/>"#,
+                        xml_escape(&hit.path),
+                        hit.start,
+                        hit.end,
+                        hit.score
+                    );
+                }
+                println!("  </group>");
+            }
+            println!("</dupes>");
+        }
     }
-    Ok(serde_json::from_slice(&fs::read(p)?)?)
-}
-
-fn update_analytics<F: FnOnce(Analytics) -> Analytics>(f: F) -> Result<()> {
-    let a0 = read_analytics().unwrap_or_default();
-    let a1 = f(a0);
-    let tmp = idx_dir().join(format!("{}.tmp", ANALYTICS_FILE));
-    fs::write(&tmp, serde_json::to_vec_pretty(&a1)?)?;
-    fs::rename(tmp, idx_dir().join(ANALYTICS_FILE))?;
     Ok(())
 }
 
-fn min_score_threshold() -> f32 {
-    std::env::var("CODEX_INDEX_RETRIEVAL_THRESHOLD")
-        .ok()
-        .and_then(|s| s.parse::<f32>().ok())
-        .map(|v| v.clamp(0.0, 1.0))
-        .unwrap_or(0.60)
-}
-
-fn xml_escape(s: &str) -> String {
-    s.replace("&", "&amp;")
-        .replace("<", "&lt;")
-        .replace(">", "&gt;")
-        .replace("\"", "&quot;")
-        .replace("'", "&apos;")
-}
-
-fn query(args: &crate::IndexQueryArgs) -> anyhow::Result<()> {
-    let manifest: Manifest = serde_json::from_slice(&fs::read(idx_dir().join(MANIFEST_FILE))?)?;
-    let (ids, data) = load_vectors(idx_dir().join(VECTORS_FILE))?;
-    let meta = load_meta(idx_dir().join(META_FILE))?;
-
-    // Embed + normalize query
-    let mut qv = embed_text(&manifest.model, &args.query)?;
-    if qv.len() != manifest.dim {
-        bail!("query dim {} != index dim {}", qv.len(), manifest.dim);
-    }
-    let mut qn = 0f32;
-    for x in &qv {
-        qn += *x * *x;
-    }
-    let qn = qn.sqrt();
-    if qn > 0.0 {
-        for x in qv.iter_mut() {
-            *x /= qn;
+fn eval(args: &crate::IndexEvalArgs) -> Result<()> {
+    let cases = codex_index::load_golden(&args.golden)?;
+    let opts = SearchOptions {
+        k: args.k,
+        mode: args.mode.clone().into(),
+        rerank: args.rerank.clone(),
+        diversity: args.diversity,
+        ..SearchOptions::default()
+    };
+    let report = codex_index::evaluate(&repo_root(), &cases, &opts, args.local)?;
+    match args.output {
+        crate::OutputFormatArg::Text => print!("{report}"),
+        crate::OutputFormatArg::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        crate::OutputFormatArg::Xml => {
+            let threshold = report
+                .suggested_threshold
+                .map(|t| format!(r#" suggested_threshold="{t:.3}""#))
+                .unwrap_or_default();
+            println!(
+                r#"<eval queries="{}" k="{}" mrr="{:.3}"{threshold}>"#,
+                report.queries, report.k, report.mrr
+            );
+            for (n, recall) in &report.recall {
+                println!(r#"  <recall at="{n}" value="{recall:.3}"/>"#);
+            }
+            for case in &report.cases {
+                let rank = case
+                    .first_rank
+                    .map(|r| format!(r#" first_rank="{r}""#))
+                    .unwrap_or_default();
+                let error = case
+                    .error
+                    .as_deref()
+                    .map(|e| format!(r#" error="{}""#, xml_escape(e)))
+                    .unwrap_or_default();
+                println!(
+                    r#"  <case query="{}" found="{}" expected="{}"{rank}{error}/>"#,
+                    xml_escape(&case.query),
+                    case.found,
+                    case.expected.len()
+                );
+            }
+            println!("</eval>");
         }
     }
+    Ok(())
+}
 
-    // Prefer HNSW when files exist; fallback to linear scan
-    let graph_path = idx_dir().join(format!("{}.hnsw.graph", HNSW_BASENAME));
-    let data_path = idx_dir().join(format!("{}.hnsw.data", HNSW_BASENAME));
-    let use_hnsw = graph_path.exists() && data_path.exists();
-
-    let mut scores: Vec<(usize, f32)> = Vec::new();
-    if use_hnsw {
-        let mut reloader = HnswIo::new(idx_dir().as_path(), HNSW_BASENAME);
-        let options = hnsw_rs::prelude::ReloadOptions::default().set_mmap(true);
-        reloader.set_options(options);
-        let hnsw: Hnsw<f32, DistCosine> = reloader.load_hnsw::<f32, DistCosine>()?;
-        let ef_s = 256usize;
-        let max_k = args.k.min(ids.len());
-        let result = hnsw.search(&qv, max_k, ef_s);
-        for n in result.iter() {
-            // DistCosine distance in [0,2]; similarity ~ 1 - d
-            let sim = 1.0f32 - (n.distance as f32);
-            scores.push((n.d_id, sim)); // d_id is the insertion id (usize)
+fn stats(args: &crate::IndexStatsArgs) -> Result<()> {
+    let since = args
+        .since
+        .as_deref()
+        .map(codex_index::parse_since)
+        .transpose()?;
+    let stats = codex_index::query_stats(&repo_root(), since, args.top)?;
+    match args.output {
+        crate::OutputFormatArg::Text => print!("{stats}"),
+        crate::OutputFormatArg::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+        crate::OutputFormatArg::Xml => {
+            println!(
+                r#"<stats queries="{}" misses="{}" injected="{}" p50_ms="{:.1}" p95_ms="{:.1}">"#,
+                stats.queries, stats.misses, stats.injected, stats.p50_ms, stats.p95_ms
+            );
+            for (source, n) in &stats.sources {
+                println!(r#"  <source name="{}" queries="{n}"/>"#, xml_escape(source));
+            }
+            for r in &stats.slowest {
+                println!(
+                    r#"  <slow source="{}" ms="{:.1}">{}</slow>"#,
+                    r.source.as_str(),
+                    r.total_ms(),
+                    xml_escape(&r.label())
+                );
+            }
+            for (path, n) in &stats.top_files {
+                println!(r#"  <file path="{}" retrieved="{n}"/>"#, xml_escape(path));
+            }
+            for (query, n) in &stats.top_misses {
+                println!(r#"  <miss times="{n}">{}</miss>"#, xml_escape(query));
+            }
+            println!("</stats>");
         }
-    } else {
-        let k = args.k.min(ids.len());
-        scores = (0..ids.len())
-            .map(|i| {
-                (
-                    i,
-                    cosine(&qv, &data[i * manifest.dim..(i + 1) * manifest.dim]),
-                )
-            })
-            .collect();
-        scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-        scores.truncate(k);
     }
+    Ok(())
+}
 
-    // Sort scores (desc) to ensure top-first regardless of path
-    scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+fn status() -> Result<()> {
+    println!("{}", Status::describe(&repo_root()));
+    Ok(())
+}
 
-    // Confidence gating: hide low-confidence results (< threshold)
-    let threshold = min_score_threshold();
-    let top = scores.first().map(|(_, s)| *s).unwrap_or(0.0);
-    if scores.is_empty() || top < threshold {
+fn verify(args: &crate::IndexVerifyArgs) -> Result<()> {
+    let root = repo_root();
+    if args.repair {
+        let report = codex_index::repair(&root)?;
         match args.output {
             crate::OutputFormatArg::Text => {
-                println!("No information exists that matches the request.");
+                if report.repaired.is_empty() {
+                    println!("Index verify: OK (nothing to repair)");
+                }
+                for action in &report.actions {
+                    println!("Repaired: {action}");
+                }
+                print_issues_text(&report.remaining);
             }
             crate::OutputFormatArg::Json => {
-                println!("[]");
+                println!("{}", serde_json::to_string_pretty(&report)?);
             }
             crate::OutputFormatArg::Xml => {
-                println!("<results/>");
+                println!(r#"<repair ok="{}">"#, report.remaining.is_empty());
+                for action in &report.actions {
+                    println!("  <action>{}</action>", xml_escape(action));
+                }
+                print_issues_xml("remaining", &report.remaining);
+                println!("</repair>");
             }
         }
-        update_analytics(|mut a| {
-            a.queries += 1;
-            let hit = if top >= threshold { 1 } else { 0 };
-            a.hits += hit;
-            a.misses = a.queries.saturating_sub(a.hits);
-            a.last_query_ts = Some(now_iso());
-            a
-        })?;
+        if !report.remaining.is_empty() {
+            bail!("Index repair left {} issue(s)", report.remaining.len());
+        }
         return Ok(());
     }
 
-    let mut out_items: Vec<serde_json::Value> = Vec::new();
-    let k = args.k.min(scores.len());
-    for (rank, (i, score)) in scores.iter().take(k).enumerate() {
-        let id = ids[*i];
-        if let Some(row) = meta.get(&id) {
-            let snippet_lines = if args.show_snippets {
-                Some(row.preview.lines().collect::<Vec<_>>())
-            } else {
-                None
-            };
-            out_items.push(serde_json::json!({
-                "rank": rank,
-                "score": (*score as f64),
-                "path": row.path,
-                "start": row.start,
-                "end": row.end,
-                "lang": row.lang,
-                "snippet": snippet_lines,
-            }));
+    if !args.deep {
+        let ok = codex_index::verify(&root)?;
+        match args.output {
+            crate::OutputFormatArg::Text if ok => println!("Index verify: OK"),
+            crate::OutputFormatArg::Text => {}
+            crate::OutputFormatArg::Json => println!("{}", serde_json::json!({ "ok": ok })),
+            crate::OutputFormatArg::Xml => println!(r#"<verify ok="{ok}"/>"#),
+        }
+        if !ok {
+            bail!("Index verify FAILED (checksums mismatch)")
         }
+        return Ok(());
     }
 
+    let report = codex_index::verify_deep(&root)?;
     match args.output {
         crate::OutputFormatArg::Text => {
-            for item in &out_items {
-                let rank = item["rank"].as_u64().unwrap_or(0);
-                let score = item["score"].as_f64().unwrap_or(0.0);
-                let path = item["path"].as_str().unwrap_or("");
-                let start = item["start"].as_u64().unwrap_or(0);
-                let end = item["end"].as_u64().unwrap_or(0);
-                let lang = item["lang"].as_str().unwrap_or("");
-                println!("[{rank}] {score:.3} {path}:{start}-{end} ({lang})");
-                if let Some(snippet) = item["snippet"].as_array() {
-                    let start_num = start as usize;
-                    for (i, line) in snippet.iter().filter_map(|v| v.as_str()).enumerate() {
-                        let prefix = if args.diff { "+ " } else { "" };
-                        if args.no_line_numbers {
-                            println!("{prefix}{line}");
-                        } else {
-                            let ln = start_num.saturating_add(i);
-                            println!(
-                                "{ln:>width$} | {prefix}{line}",
-                                width = args.line_number_width
-                            );
-                        }
-                    }
-                    println!("---");
-                }
+            if report.ok() {
+                println!("Index verify: OK");
             }
+            print_issues_text(&report.issues);
         }
         crate::OutputFormatArg::Json => {
-            println!("{}", serde_json::to_string_pretty(&out_items).unwrap());
+            let value = serde_json::json!({ "ok": report.ok(), "issues": report.issues });
+            println!("{}", serde_json::to_string_pretty(&value)?);
         }
         crate::OutputFormatArg::Xml => {
-            println!("<results>");
-            for item in &out_items {
-                let rank = item["rank"].as_u64().unwrap_or(0);
-                let score = item["score"].as_f64().unwrap_or(0.0);
-                let path = xml_escape(item["path"].as_str().unwrap_or(""));
-                let start = item["start"].as_u64().unwrap_or(0);
-                let end = item["end"].as_u64().unwrap_or(0);
-                let lang = xml_escape(item["lang"].as_str().unwrap_or(""));
-                println!(
-                    r#"  <hit rank="{rank}" score="{score:.3}" path="{path}" start="{start}" end="{end}" lang="{lang}">"#
-                );
-                if let Some(snippet) = item["snippet"].as_array() {
-                    println!("    <snippet>");
-                    let start_num = start as usize;
-                    for (i, line) in snippet.iter().filter_map(|v| v.as_str()).enumerate() {
-                        let ln = start_num.saturating_add(i);
-                        if args.no_line_numbers {
-                            if args.diff {
-                                println!(r#"      <line op="add">{}</line>"#, xml_escape(line));
-                            } else {
-                                println!(r#"      <line>{}</line>"#, xml_escape(line));
-                            }
-                        } else {
-                            if args.diff {
-                                println!(
-                                    r#"      <line n="{ln}" op="add">{}</line>"#,
-                                    xml_escape(line)
-                                );
-                            } else {
-                                println!(r#"      <line n="{ln}">{}</line>"#, xml_escape(line));
-                            }
-                        }
-                    }
-                    println!(
-                        "    </snippet>
-  </hit>"
-                    );
-                } else {
-                    println!("  </hit>");
-                }
-            }
-            println!("</results>");
+            println!(r#"<verify ok="{}">"#, report.ok());
+            print_issues_xml("issues", &report.issues);
+            println!("</verify>");
         }
     }
-    update_analytics(|mut a| {
-        a.queries += 1;
-        let threshold = min_score_threshold();
-        let hit = if scores.get(0).map(|(_, s)| *s >= threshold).unwrap_or(false) {
-            1
-        } else {
-            0
-        };
-        a.hits += hit;
-        a.misses = a.queries.saturating_sub(a.hits);
-        a.last_query_ts = Some(now_iso());
-        a
-    })?;
+    if !report.ok() {
+        bail!(
+            "Index verify FAILED ({} issue(s); run `index verify --repair`)",
+            report.issues.len()
+        );
+    }
     Ok(())
 }
-fn status() -> anyhow::Result<()> {
-    let manifest_path = idx_dir().join(MANIFEST_FILE);
-    if !manifest_path.exists() {
-        println!("Index: Missing");
-        return Ok(());
+
+fn print_issues_text(issues: &[codex_index::Issue]) {
+    for issue in issues {
+        println!("{:<12} {}", issue.kind.as_str(), issue.detail);
     }
-    let m: Manifest = serde_json::from_slice(&fs::read(&manifest_path)?)?;
-    let an: Analytics = read_analytics().unwrap_or_default();
-    let vec_sz = fs::metadata(idx_dir().join(VECTORS_FILE))
-        .map(|m| m.len())
-        .unwrap_or(0);
+}
+
+fn print_issues_xml(tag: &str, issues: &[codex_index::Issue]) {
+    println!("  <{tag}>");
+    for issue in issues {
+        let path = issue
+            .path
+            .as_deref()
+            .map(|p| format!(r#" path="{}""#, xml_escape(p)))
+            .unwrap_or_default();
+        println!(
+            r#"    <issue kind="{}"{path}>{}</issue>"#,
+            issue.kind.as_str(),
+            xml_escape(&issue.detail)
+        );
+    }
+    println!("  </{tag}>");
+}
+
+fn clean() -> Result<()> {
+    codex_index::clean(&repo_root())?;
+    println!("Index removed");
+    Ok(())
+}
+
+fn export(args: &crate::IndexExportArgs) -> Result<()> {
+    let root = repo_root();
+    let out = args.out.clone().unwrap_or_else(|| {
+        let sha = codex_index::Status::load(&root)
+            .ok()
+            .flatten()
+            .and_then(|s| s.manifest.repo.git_sha)
+            .unwrap_or_else(|| "worktree".into());
+        PathBuf::from(format!("codex-index-{}.tar.zst", &sha[..sha.len().min(12)]))
+    });
+    let info = codex_index::export(&root, &out)?;
     println!(
-        "Index: Ready
-Last indexed: {}
-Model: {} ({}-D)
-Vectors: {}
-Size: {} bytes
-Analytics: queries={}, hit_ratio={:.2}",
-        relative_age(&m.last_refresh).unwrap_or_else(|| m.last_refresh.clone()),
-        m.model,
-        m.dim,
-        m.counts.chunks,
-        vec_sz,
-        an.queries,
-        if an.queries > 0 {
-            an.hits as f32 / an.queries as f32
+        "Exported {} chunks ({} {}, {}-D) at {} to {} [{}]",
+        info.chunks,
+        info.embedder,
+        info.model,
+        info.dim,
+        info.git_sha.as_deref().unwrap_or("no commit"),
+        out.display(),
+        if info.is_signed() {
+            "signed, hmac-sha256"
         } else {
-            0.0
+            "unsigned, sha256 checksum"
         }
     );
     Ok(())
 }
 
-// ---------- git & locking & time helpers ----------
-fn git_has_changes() -> bool {
-    fn git_changed_sets() -> (
-        std::collections::HashSet<String>,
-        std::collections::HashSet<String>,
-    ) {
-        use std::process::Command;
-        let mut changed = std::collections::HashSet::new();
-        let mut deleted = std::collections::HashSet::new();
-        let root = repo_root();
-        let run = |args: &[&str]| -> Option<String> {
-            let out = Command::new("git")
-                .args(args)
-                .current_dir(&root)
-                .output()
-                .ok()?;
-            if out.status.success() {
-                Some(String::from_utf8_lossy(&out.stdout).to_string())
-            } else {
-                None
-            }
-        };
-        if let Some(s) = run(&["ls-files", "-m"]) {
-            for l in s.lines() {
-                if !l.trim().is_empty() {
-                    changed.insert(l.trim().to_string());
-                }
-            }
-        }
-        if let Some(s) = run(&["ls-files", "-o", "--exclude-standard"]) {
-            for l in s.lines() {
-                if !l.trim().is_empty() {
-                    changed.insert(l.trim().to_string());
-                }
-            }
+fn import(args: &crate::IndexImportArgs) -> Result<()> {
+    let root = repo_root();
+    let report = codex_index::import(
+        &root,
+        &args.snapshot,
+        args.force,
+        args.use_snapshot_endpoint,
+        !args.no_catch_up,
+    )?;
+    let snap = &report.snapshot;
+    println!(
+        "Imported {} chunks ({} {}, {}-D) from {}",
+        snap.chunks,
+        snap.embedder,
+        snap.model,
+        snap.dim,
+        snap.git_sha.as_deref().unwrap_or("no commit")
+    );
+    if let Some(r) = &report.catch_up {
+        match report.changed_files {
+            Some(n) => println!(
+                "Caught up {n} changed files: {} chunks, {} embedded",
+                r.chunks, r.embedded
+            ),
+            None => println!(
+                "Snapshot commit not found locally; re-checked all files: {} chunks, {} embedded",
+                r.chunks, r.embedded
+            ),
         }
-        if let Some(s) = run(&["ls-files", "-d"]) {
-            for l in s.lines() {
-                if !l.trim().is_empty() {
-                    deleted.insert(l.trim().to_string());
-                }
-            }
+    }
+    Ok(())
+}
+
+fn ignore_cmd(args: &crate::IndexIgnoreArgs) -> Result<()> {
+    let root = repo_root();
+    if let Some(path) = &args.explain {
+        let path = std::env::current_dir()?.join(path);
+        println!("{}", codex_index::explain_ignore(&root, &path));
+        return Ok(());
+    }
+    if args.reset {
+        codex_index::reset_ignore_patterns(&root)?;
+    }
+    let mut pats = codex_index::load_ignore_patterns(&root);
+    if !args.add.is_empty() {
+        pats.extend(args.add.clone());
+    }
+    if !args.remove.is_empty() {
+        let remove: std::collections::HashSet<String> = args.remove.iter().cloned().collect();
+        pats.retain(|p| !remove.contains(p));
+    }
+    if args.reset || !args.add.is_empty() || !args.remove.is_empty() {
+        codex_index::save_ignore_patterns(&root, pats)?;
+    }
+    if args.list || (!args.reset && args.add.is_empty() && args.remove.is_empty()) {
+        println!("Ignore file: {}", codex_index::ignore_file(&root).display());
+        for p in codex_index::load_ignore_patterns(&root) {
+            println!("{p}");
         }
-        (changed, deleted)
     }
+    Ok(())
+}
+
+// ---------- query server ----------
+// `query` and `symbols` go through a running `index serve` when there is one,
+// and fall back to loading the index in-process otherwise.
+
+#[cfg(unix)]
+fn serve(args: &crate::IndexServeArgs) -> Result<()> {
+    codex_index::serve(&repo_root(), std::time::Duration::from_secs(args.idle_secs))
+}
+
+#[cfg(not(unix))]
+fn serve(_args: &crate::IndexServeArgs) -> Result<()> {
+    bail!("index serve needs Unix domain sockets and is not supported on this platform")
+}
+
+#[cfg(unix)]
+fn server_query(
+    root: &std::path::Path,
+    query: &str,
+    opts: &SearchOptions,
+) -> Option<(Vec<Hit>, Timings)> {
+    codex_index::Client::connect(root)
+        .and_then(|mut c| c.query_timed(query, opts))
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn server_query(
+    _root: &std::path::Path,
+    _query: &str,
+    _opts: &SearchOptions,
+) -> Option<(Vec<Hit>, Timings)> {
+    None
+}
+
+#[cfg(unix)]
+fn server_symbols(
+    root: &std::path::Path,
+    args: &crate::IndexSymbolsArgs,
+) -> Option<Vec<codex_index::SymbolMatch>> {
+    codex_index::Client::connect(root)
+        .and_then(|mut c| c.symbols(&args.name, args.kind.as_deref(), args.exact, args.limit))
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn server_symbols(
+    _root: &std::path::Path,
+    _args: &crate::IndexSymbolsArgs,
+) -> Option<Vec<codex_index::SymbolMatch>> {
+    None
+}
 
+/// Start `index serve` in the background so later queries find the index
+/// loaded. Skipped without an index or with `CODEX_INDEX_SERVE=0`.
+fn autostart_server(root: &std::path::Path) {
+    if !cfg!(unix)
+        || std::env::var("CODEX_INDEX_SERVE")
+            .map(|v| v == "0" || v.eq_ignore_ascii_case("off"))
+            .unwrap_or(false)
+        || !codex_index::Status::load(root).is_ok_and(|s| s.is_some())
+    {
+        return;
+    }
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+    let _ = std::process::Command::new(exe)
+        .args(["index", "serve"])
+        .current_dir(root)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+        .replace("'", "&apos;")
+}
+
+// ---------- git helpers ----------
+fn git_has_changes(root: &std::path::Path) -> bool {
     let out = std::process::Command::new("git")
         .args(["ls-files", "-m", "-o", "--exclude-standard"])
+        .current_dir(root)
         .output();
     if let Ok(o) = out {
         !o.stdout.is_empty()
@@ -1326,78 +1146,10 @@ fn git_has_changes() -> bool {
     }
 }
 
-struct BuildLock(std::path::PathBuf);
-impl BuildLock {
-    fn acquire() -> Result<Self> {
-        let p = idx_dir().join(LOCK_FILE);
-        match std::fs::File::options()
-            .create_new(true)
-            .write(true)
-            .open(&p)
-        {
-            Ok(_) => Ok(Self(p)),
-            Err(e) => Err(anyhow::anyhow!(e)),
-        }
-    }
-}
-impl Drop for BuildLock {
-    fn drop(&mut self) {
-        let _ = std::fs::remove_file(&self.0);
-    }
-}
-
-fn relative_age(iso: &str) -> Option<String> {
-    let t =
-        time::OffsetDateTime::parse(iso, &time::format_description::well_known::Rfc3339).ok()?;
-    let now = time::OffsetDateTime::now_utc();
-    let diff = now - t;
-    let secs = diff.whole_seconds();
-    Some(if secs < 60 {
-        "now".into()
-    } else if secs < 3600 {
-        format!("{}m ago", secs / 60)
-    } else if secs < 86400 {
-        format!("{}h ago", secs / 3600)
-    } else if secs < 86400 * 7 {
-        format!("{}d ago", secs / 86400)
-    } else {
-        format!("{}w ago", secs / (86400 * 7))
-    })
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn detects_binary_png() {
-        let png: &[u8] = b"\x89PNG\r\n\x1a\nrest";
-        assert!(looks_binary(png));
-    }
-
-    #[test]
-    fn chunk_lines_overlap() {
-        let text = (1..=120)
-            .map(|i| {
-                format!(
-                    "line{}
-",
-                    i
-                )
-            })
-            .collect::<String>();
-        let chunks = chunk_lines(&text, 40, 10);
-        assert!(!chunks.is_empty());
-        // Ensure chunks overlap by ~10 lines
-        if chunks.len() > 1 {
-            let (s0, e0, _) = chunks[0].clone();
-            let (s1, _e1, _) = chunks[1].clone();
-            let l0_end = offset_to_line(&text, e0);
-            let l1_start = offset_to_line(&text, s1);
-            assert!(l1_start <= l0_end);
-        }
-    }
-
     #[test]
     fn xml_escape_basic() {
         let s = r#"<tag> & " ' ;"#;
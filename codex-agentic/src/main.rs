@@ -116,25 +116,48 @@ enum ModelsCmd {
 
 #[derive(Subcommand, Debug, Clone)]
 enum IndexCmd {
-    /// Build or refresh the local index (default model: bge-small)
+    /// Build or refresh the local index (default: fastembed bge-small)
     Build(IndexBuildArgs),
     /// Query the local index for relevant code
     Query(IndexQueryArgs),
+    /// Find where a symbol (fn, struct, trait, class, ...) is defined
+    Symbols(IndexSymbolsArgs),
+    /// Find code similar to the chunk at <path>:<line>, in other files
+    Similar(IndexSimilarArgs),
+    /// Report clusters of near-identical chunks (refactoring candidates)
+    Dupes(IndexDupesArgs),
+    /// Measure retrieval quality against golden queries (recall@k, MRR, suggested threshold)
+    Eval(IndexEvalArgs),
+    /// Summarize logged queries: miss rate, latency, slowest queries, most retrieved files
+    Stats(IndexStatsArgs),
     /// Show index status
     Status,
-    /// Verify index integrity
-    Verify,
+    /// Verify index integrity (--deep cross-checks every file, --repair fixes what it finds)
+    Verify(IndexVerifyArgs),
     /// Remove on-disk index
     Clean,
     /// Manage ignore patterns used by the indexer (stored in .index-ignore at repo root)
     Ignore(IndexIgnoreArgs),
+    /// Keep the index loaded and answer queries over a local Unix socket
+    Serve(IndexServeArgs),
+    /// Package the index into a portable snapshot keyed by its commit
+    Export(IndexExportArgs),
+    /// Install a snapshot from `index export` and catch up to the working tree
+    Import(IndexImportArgs),
 }
 
 #[derive(Args, Debug, Clone)]
 struct IndexBuildArgs {
-    /// Embedding model preset
-    #[arg(long, value_parser = ["bge-small","bge-large"], default_value = "bge-small")]
-    model: String,
+    /// Embedding provider (ollama reuses the `oss` model provider endpoint)
+    #[arg(long, value_parser = ["fastembed","ollama","openai-compatible"], default_value = "fastembed")]
+    embedder: String,
+    /// Embedding model: bge-small | bge-large for fastembed, otherwise a provider model name
+    /// (defaults: bge-small, nomic-embed-text, text-embedding-3-small)
+    #[arg(long)]
+    model: Option<String>,
+    /// Embeddings endpoint for ollama / openai-compatible (e.g. http://localhost:11434)
+    #[arg(long = "embed-url")]
+    embed_url: Option<String>,
     /// Force full rebuild instead of incremental
     #[arg(long)]
     force: bool,
@@ -147,6 +170,46 @@ struct IndexBuildArgs {
     /// Overlap lines between chunks (lines mode)
     #[arg(long, default_value_t = 32)]
     overlap: usize,
+    /// Chunks embedded per model call
+    #[arg(long = "batch-size", default_value_t = 64)]
+    batch_size: usize,
+    /// Worker threads for reading and chunking files (0 = all cores, or one with --nice)
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+    /// Skip files larger than this (e.g. 512K, 5M, 1G)
+    #[arg(long = "max-file-size", default_value = "5M")]
+    max_file_size: String,
+    /// Index at most this many files, the first in path order (0 = no limit)
+    #[arg(long = "max-files", default_value_t = 0)]
+    max_files: usize,
+    /// Low priority, as background builds run: one worker thread unless --threads is set,
+    /// and a lowered scheduling priority on Linux
+    #[arg(long)]
+    nice: bool,
+    /// Progress output: a bar on stderr (auto: when it is a terminal) or JSON lines on stdout
+    #[arg(long, value_parser = ["auto","bar","json","off"], default_value = "auto")]
+    progress: String,
+    /// Vector store encoding: f32 | f16 (half size) | int8 (quarter size); the HNSW graph stays f32
+    #[arg(long, value_parser = ["f32","f16","int8"], default_value = "f32")]
+    storage: String,
+    /// With f16/int8 storage, also keep exact f32 vectors to re-score the top candidates
+    #[arg(long)]
+    rescore: bool,
+    /// Embed chunks without the file/language/scope/doc-comment header
+    #[arg(long = "no-chunk-header")]
+    no_chunk_header: bool,
+    /// Build every repository of the workspace (.codex/workspace.toml, submodules, opted-in worktrees)
+    #[arg(long)]
+    all: bool,
+    /// Also index commit messages and diff hunks (searched with `index query --scope history`)
+    #[arg(long)]
+    history: bool,
+    /// With --history: only commits after this revision
+    #[arg(long, requires = "history")]
+    since: Option<String>,
+    /// With --history: at most this many commits, newest first (default 1000 without --since)
+    #[arg(long = "max-commits", requires = "history")]
+    max_commits: Option<usize>,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -171,11 +234,167 @@ struct IndexQueryArgs {
     /// Show diff-style "+ " prefix for snippet lines
     #[arg(long = "diff", default_value_t = false)]
     diff: bool,
+    /// Ranking: semantic (vectors) | lexical (BM25) | hybrid (rank fusion of both)
+    #[arg(long = "mode", value_enum, default_value_t = SearchModeArg::Hybrid)]
+    mode: SearchModeArg,
+    /// Only search files matching this glob (repeatable; a directory matches everything under it)
+    #[arg(long = "path")]
+    path: Vec<String>,
+    /// Skip files matching this glob (repeatable)
+    #[arg(long = "exclude")]
+    exclude: Vec<String>,
+    /// Only search chunks in this language, e.g. rust, python, typescript (repeatable)
+    #[arg(long = "lang")]
+    lang: Vec<String>,
+    /// Only search this repository, not the rest of its workspace
+    #[arg(long)]
+    local: bool,
+    /// Re-score the top candidates with a cross-encoder: --rerank or --rerank=<model>
+    /// (bge-reranker-base, bge-reranker-v2-m3, jina-reranker-v1-turbo-en, jina-reranker-v2-base-multilingual)
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "bge-reranker-base")]
+    rerank: Option<String>,
+    /// Diversify results (maximal marginal relevance): 1.0 ranks by relevance only,
+    /// lower values favor chunks unlike those already picked (e.g. 0.7)
+    #[arg(long)]
+    diversity: Option<f32>,
+    /// List each file once with its best score and all matching ranges
+    #[arg(long = "group-by", value_parser = ["file"])]
+    group_by: Option<String>,
+    /// What to search: code (the working tree) | history (commit messages and diffs)
+    #[arg(long, value_parser = ["code","history"], default_value = "code")]
+    scope: String,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexSymbolsArgs {
+    /// Symbol name, optionally qualified (e.g. VectorStore::new); fuzzy unless --exact
+    name: String,
+    /// Only symbols of this kind
+    #[arg(long, value_parser = ["fn","struct","enum","trait","impl","class","interface","type","mod"])]
+    kind: Option<String>,
+    /// Only exact (case-insensitive) name matches
+    #[arg(long)]
+    exact: bool,
+    /// Maximum number of matches
+    #[arg(short = 'k', long = "limit", default_value_t = 20)]
+    limit: usize,
+    /// Output format: text | json | xml
+    #[arg(long = "output", value_enum, default_value_t = OutputFormatArg::Text)]
+    output: OutputFormatArg,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexSimilarArgs {
+    /// Location of the code to match, e.g. src/lib.rs:42 (relative to the repo root)
+    location: String,
+    /// Top-K results
+    #[arg(short = 'k', long = "k", default_value_t = 8)]
+    k: usize,
+    /// Print snippet previews
+    #[arg(long = "show-snippets")]
+    show_snippets: bool,
+    /// Output format: text | json | xml
+    #[arg(long = "output", value_enum, default_value_t = OutputFormatArg::Text)]
+    output: OutputFormatArg,
+    /// Disable line numbers in snippets
+    #[arg(long = "no-line-numbers", default_value_t = false)]
+    no_line_numbers: bool,
+    /// Line number column width
+    #[arg(long = "line-number-width", default_value_t = 6)]
+    line_number_width: usize,
+    /// Only return files matching this glob (repeatable)
+    #[arg(long = "path")]
+    path: Vec<String>,
+    /// Skip files matching this glob (repeatable)
+    #[arg(long = "exclude")]
+    exclude: Vec<String>,
+    /// Only return chunks in this language (repeatable)
+    #[arg(long = "lang")]
+    lang: Vec<String>,
+    /// Diversify results, as for `index query`
+    #[arg(long)]
+    diversity: Option<f32>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexDupesArgs {
+    /// Lowest cosine similarity for two chunks to count as duplicates
+    #[arg(long = "min-score", default_value_t = codex_index::DEFAULT_DUPE_SCORE)]
+    min_score: f32,
+    /// Ignore chunks shorter than this many lines
+    #[arg(long = "min-lines", default_value_t = 5)]
+    min_lines: usize,
+    /// Maximum number of groups, most redundant lines first
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+    /// Only consider files matching this glob (repeatable)
+    #[arg(long = "path")]
+    path: Vec<String>,
+    /// Skip files matching this glob (repeatable)
+    #[arg(long = "exclude")]
+    exclude: Vec<String>,
+    /// Only consider chunks in this language (repeatable)
+    #[arg(long = "lang")]
+    lang: Vec<String>,
+    /// Output format: text | json | xml
+    #[arg(long = "output", value_enum, default_value_t = OutputFormatArg::Text)]
+    output: OutputFormatArg,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexEvalArgs {
+    /// JSONL file, one {"query": "...", "expected": "path[:start-end]" | [...]} per line
+    #[arg(long)]
+    golden: std::path::PathBuf,
+    /// Top-K results scored per query
+    #[arg(short = 'k', long = "k", default_value_t = 10)]
+    k: usize,
+    /// Ranking: semantic (vectors) | lexical (BM25) | hybrid (rank fusion of both)
+    #[arg(long = "mode", value_enum, default_value_t = SearchModeArg::Hybrid)]
+    mode: SearchModeArg,
+    /// Re-rank with a cross-encoder, as for `index query`
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "bge-reranker-base")]
+    rerank: Option<String>,
+    /// Diversify results, as for `index query`
+    #[arg(long)]
+    diversity: Option<f32>,
+    /// Only search this repository, not the rest of its workspace
+    #[arg(long)]
+    local: bool,
+    /// Output format: text | json | xml
+    #[arg(long = "output", value_enum, default_value_t = OutputFormatArg::Text)]
+    output: OutputFormatArg,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexStatsArgs {
+    /// Only queries newer than this age, e.g. 12h, 7d, 2w (default: the whole log)
+    #[arg(long)]
+    since: Option<String>,
+    /// Entries listed per ranking
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+    /// Output format: text | json | xml
+    #[arg(long = "output", value_enum, default_value_t = OutputFormatArg::Text)]
+    output: OutputFormatArg,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexVerifyArgs {
+    /// Cross-check graph, metadata, dims and BM25 index, and re-hash indexed files
+    #[arg(long)]
+    deep: bool,
+    /// Rebuild only the broken parts (implies --deep)
+    #[arg(long)]
+    repair: bool,
+    /// Output format: text | json | xml
+    #[arg(long = "output", value_enum, default_value_t = OutputFormatArg::Text)]
+    output: OutputFormatArg,
 }
 
 #[derive(Args, Debug, Clone)]
 struct IndexIgnoreArgs {
-    /// Add a pattern (glob-like: * and ? supported). Repeat to add multiple.
+    /// Add a pattern (gitignore syntax: **, !negation, /anchored, dir/). Repeat to add multiple.
     #[arg(long = "add")]
     add: Vec<String>,
     /// Remove a pattern. Repeat to remove multiple.
@@ -187,6 +406,38 @@ struct IndexIgnoreArgs {
     /// List the current patterns and the file path.
     #[arg(long = "list")]
     list: bool,
+    /// Print which rule (file:line) includes or excludes PATH.
+    #[arg(long = "explain", value_name = "PATH")]
+    explain: Option<std::path::PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexServeArgs {
+    /// Exit after this many seconds without requests
+    #[arg(long = "idle-secs", default_value_t = 600)]
+    idle_secs: u64,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexExportArgs {
+    /// Snapshot file to write (default: codex-index-<commit>.tar.zst)
+    #[arg(long = "out")]
+    out: Option<std::path::PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct IndexImportArgs {
+    /// Snapshot file written by `index export`
+    snapshot: std::path::PathBuf,
+    /// Replace a local index built with a different embedder, model or dim
+    #[arg(long)]
+    force: bool,
+    /// Embed queries with the snapshot's endpoint instead of the local one
+    #[arg(long = "use-snapshot-endpoint")]
+    use_snapshot_endpoint: bool,
+    /// Install the snapshot as is, without re-indexing files changed since its commit
+    #[arg(long = "no-catch-up")]
+    no_catch_up: bool,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -211,6 +462,32 @@ struct SearchCodeArgs {
     /// Show diff-style "+ " prefix for snippet lines
     #[arg(long = "diff", default_value_t = false)]
     diff: bool,
+    /// Ranking: semantic (vectors) | lexical (BM25) | hybrid (rank fusion of both)
+    #[arg(long = "mode", value_enum, default_value_t = SearchModeArg::Hybrid)]
+    mode: SearchModeArg,
+    /// Only search files matching this glob (repeatable; a directory matches everything under it)
+    #[arg(long = "path")]
+    path: Vec<String>,
+    /// Skip files matching this glob (repeatable)
+    #[arg(long = "exclude")]
+    exclude: Vec<String>,
+    /// Only search chunks in this language, e.g. rust, python, typescript (repeatable)
+    #[arg(long = "lang")]
+    lang: Vec<String>,
+    /// Only search this repository, not the rest of its workspace
+    #[arg(long)]
+    local: bool,
+    /// Re-score the top candidates with a cross-encoder: --rerank or --rerank=<model>
+    /// (bge-reranker-base, bge-reranker-v2-m3, jina-reranker-v1-turbo-en, jina-reranker-v2-base-multilingual)
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "bge-reranker-base")]
+    rerank: Option<String>,
+    /// Diversify results (maximal marginal relevance): 1.0 ranks by relevance only,
+    /// lower values favor chunks unlike those already picked (e.g. 0.7)
+    #[arg(long)]
+    diversity: Option<f32>,
+    /// List each file once with its best score and all matching ranges
+    #[arg(long = "group-by", value_parser = ["file"])]
+    group_by: Option<String>,
 }
 
 #[derive(Clone, Debug, ValueEnum)]
@@ -220,6 +497,13 @@ enum OutputFormatArg {
     Xml,
 }
 
+#[derive(Clone, Debug, ValueEnum)]
+enum SearchModeArg {
+    Semantic,
+    Lexical,
+    Hybrid,
+}
+
 #[derive(Args, Debug, Clone)]
 struct ResumeArgs {
     /// Resume the most recent session
@@ -246,6 +530,14 @@ fn main() -> Result<()> {
                 no_line_numbers,
                 line_number_width,
                 diff,
+                mode,
+                path,
+                exclude,
+                lang,
+                local,
+                rerank,
+                diversity,
+                group_by,
             }) => {
                 let args = IndexQueryArgs {
                     query: query.to_string(),
@@ -255,6 +547,15 @@ fn main() -> Result<()> {
                     no_line_numbers: *no_line_numbers,
                     line_number_width: *line_number_width,
                     diff: *diff,
+                    mode: mode.clone(),
+                    path: path.clone(),
+                    exclude: exclude.clone(),
+                    lang: lang.clone(),
+                    local: *local,
+                    rerank: rerank.clone(),
+                    diversity: *diversity,
+                    group_by: group_by.clone(),
+                    scope: "code".into(),
                 };
                 return indexing::dispatch(IndexCmd::Query(args));
             }
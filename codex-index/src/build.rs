@@ -0,0 +1,1364 @@
+//! Index builds: walk the repository, chunk files on a worker pool, embed new
+//! chunks in batches and write vectors, metadata, BM25 postings and the HNSW graph.
+
+use anyhow::{Context, Result, bail};
+use hnsw_rs::prelude::*;
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::chunk::{
+    CHUNK_HEADER, chunk_auto, chunk_header, chunk_lines, definitions, enclosing_scope,
+    language_for, leading_doc, offset_to_line, read_text_if_textual,
+};
+use crate::embed::{Provider, embedder};
+use crate::ignores::PathFilter;
+use crate::lexical::{self, LEXICAL_FILE, LexicalIndex};
+use crate::progress::{BuildPhase, BuildProgress, ProgressFn, Reporter};
+use crate::secrets::Scrubber;
+use crate::sections::chunk_sections;
+use crate::store::{
+    self, BuildLock, Checksums, ChunkCfg, Counts, EXACT_FILE, HNSW_BASENAME, INDEX_VERSION, Limits,
+    META_FILE, Manifest, MetaRow, RepoInfo, VECTORS_FILE, sha256_hex,
+};
+use crate::symbols::Symbol;
+use crate::vectors::{self, Storage};
+
+/// Settings for [`Builder`]; the defaults match `codex-agentic index build`.
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// Embedding provider.
+    pub embedder: Provider,
+    /// Embedding model: `bge-small`/`bge-large` for fastembed, otherwise a
+    /// model name known to the provider.
+    pub model: String,
+    /// Endpoint for HTTP providers; None uses [`Provider::default_url`].
+    pub embed_url: Option<String>,
+    /// Rebuild everything instead of reusing vectors of unchanged chunks.
+    pub force: bool,
+    /// Chunking mode: `auto` (tree-sitter when available) or `lines`.
+    pub chunk: String,
+    pub lines: usize,
+    pub overlap: usize,
+    /// Chunks per embedding batch.
+    pub batch_size: usize,
+    /// Worker threads for reading and chunking files (0 = all cores, or one
+    /// with `nice`).
+    pub threads: usize,
+    /// Files larger than this many bytes are skipped.
+    pub max_file_size: u64,
+    /// Index at most this many files, the first in path order (0 = no limit).
+    pub max_files: usize,
+    /// Low priority for background builds: a single worker by default and,
+    /// on Linux, a lowered scheduling priority for the build's threads.
+    pub nice: bool,
+    /// Encoding of the vector store.
+    pub storage: Storage,
+    /// With quantized storage, also keep exact f32 vectors to re-score the
+    /// top candidates of each query.
+    pub rescore: bool,
+    /// Embed each chunk behind a header naming its file, language, enclosing
+    /// definitions and doc comment (the preview stays bare).
+    pub chunk_header: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            embedder: Provider::Fastembed,
+            model: "bge-small".into(),
+            embed_url: None,
+            force: false,
+            chunk: "auto".into(),
+            lines: 160,
+            overlap: 32,
+            batch_size: 64,
+            threads: 0,
+            max_file_size: store::DEFAULT_MAX_FILE_SIZE,
+            max_files: 0,
+            nice: false,
+            storage: Storage::F32,
+            rescore: false,
+            chunk_header: true,
+        }
+    }
+}
+
+#[cfg(test)]
+impl BuildOptions {
+    /// Options that embed with [`crate::embed::fake_embedder`] for `model`.
+    pub(crate) fn fake(model: &str) -> Self {
+        Self {
+            embedder: Provider::Ollama,
+            model: model.into(),
+            embed_url: Some(crate::embed::fake_embedder(model)),
+            ..Self::default()
+        }
+    }
+}
+
+impl BuildOptions {
+    // Header format recorded in the manifest.
+    pub(crate) fn header_format(&self) -> &'static str {
+        if self.chunk_header {
+            CHUNK_HEADER
+        } else {
+            "none"
+        }
+    }
+}
+
+/// Outcome of [`Builder::build`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildReport {
+    pub files: usize,
+    pub chunks: usize,
+    /// Chunks that had to be embedded (the rest reused stored vectors).
+    pub embedded: usize,
+    /// False when nothing changed and only the manifest was refreshed.
+    pub changed: bool,
+    /// Files left out by `max_files`.
+    #[serde(default)]
+    pub skipped: usize,
+}
+
+/// Builds or incrementally refreshes the index of one repository.
+pub struct Builder {
+    root: PathBuf,
+    opts: BuildOptions,
+    progress: Option<ProgressFn>,
+}
+
+impl Builder {
+    pub fn new(root: impl Into<PathBuf>, opts: BuildOptions) -> Self {
+        Self {
+            root: root.into(),
+            opts,
+            progress: None,
+        }
+    }
+
+    /// Builder for refreshes: the settings of the existing index (so the
+    /// refresh stays incremental), or the defaults.
+    pub fn refresh(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let mut opts = BuildOptions::default();
+        if let Ok(m) = store::read_manifest(&root) {
+            opts.embedder = m.embedder;
+            opts.model = m.model;
+            opts.embed_url = m.embed_url;
+            opts.chunk = m.chunk_mode;
+            opts.lines = m.chunk.lines;
+            opts.overlap = m.chunk.overlap;
+            opts.storage = m.storage;
+            opts.rescore = m.rescore;
+            opts.chunk_header = m.chunk_header != "none";
+            opts.max_file_size = m.limits.max_file_size;
+            opts.max_files = m.limits.max_files;
+        }
+        Self::new(root, opts)
+    }
+
+    /// [`Builder::refresh`] at low priority (`nice`), for builds that run
+    /// behind an interactive session.
+    pub fn background(root: impl Into<PathBuf>) -> Self {
+        let mut builder = Self::refresh(root);
+        builder.opts.nice = true;
+        builder
+    }
+
+    /// Report [`BuildProgress`] to `f` while building.
+    pub fn on_progress(mut self, f: impl Fn(&BuildProgress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(f));
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn options(&self) -> &BuildOptions {
+        &self.opts
+    }
+
+    pub fn build(&self) -> Result<BuildReport> {
+        self.run(None)
+    }
+
+    /// Incremental update after `changed` paths (files or directories, absolute
+    /// or relative to the root) were modified, created or removed. Other files
+    /// keep their rows without being re-read. Falls back to a full [`build`]
+    /// when there is no compatible previous index.
+    ///
+    /// [`build`]: Builder::build
+    pub fn update(&self, changed: &[PathBuf]) -> Result<BuildReport> {
+        self.run(Some(changed))
+    }
+
+    fn run(&self, changed: Option<&[PathBuf]>) -> Result<BuildReport> {
+        let root = self.root.as_path();
+        let opts = &self.opts;
+        let dir = store::index_dir(root);
+        fs::create_dir_all(&dir).context("create index dir")?;
+        // A concurrent build holds the lock; proceed without it (writes are atomic renames).
+        let guard = BuildLock::acquire(root).ok();
+        let mut reporter = Reporter::new(self.progress.as_ref(), guard.as_ref());
+        reporter.emit(BuildPhase::Scan);
+        // Worker threads spawned below inherit the lowered priority.
+        if opts.nice {
+            lower_priority();
+        }
+
+        // Record an attempt timestamp (manual or scheduled)
+        let _ = store::record_attempt(root);
+
+        // Secrets are scrubbed before anything is embedded or stored.
+        let scrubber = Scrubber::load(root)?;
+
+        // Incremental by default: reuse vectors from the previous index unless --force
+        // or the model/chunking settings changed.
+        let prev = if opts.force {
+            None
+        } else {
+            PrevIndex::load(root, opts, scrubber.fingerprint())
+        };
+
+        // Files to read (apply .index-ignore + sanity limits), plus files carried
+        // over from the previous index unread when only `changed` paths need work.
+        let filter = PathFilter::new(root);
+        let (mut files, carried) = match (changed, &prev) {
+            (Some(changed), Some(prev)) if prev.rows_reusable => {
+                plan_update(root, &filter, changed, prev, opts.max_file_size)
+            }
+            _ => (scan(root, opts.max_file_size), Vec::new()),
+        };
+        // Path order, whatever order the directory walk found files in, so
+        // chunk ids and meta rows are the same from run to run.
+        files.sort();
+        let mut skipped = 0;
+        if opts.max_files > 0 {
+            let room = opts.max_files.saturating_sub(carried.len());
+            if files.len() > room {
+                skipped = files.len() - room;
+                files.truncate(room);
+            }
+        }
+        reporter.progress.files_scanned = files.len();
+        reporter.emit(BuildPhase::Embed);
+
+        // Embedding model is loaded lazily so no-op refreshes stay cheap.
+        let mut model = None;
+        let batch_size = opts.batch_size.max(1);
+        let threads = match (opts.threads, opts.nice) {
+            (0, true) => 1,
+            (n, _) => build_threads(n),
+        };
+
+        // Accumulate chunks, vectors (normalized), ids, and meta rows
+        let mut all_vecs: Vec<Vec<f32>> = Vec::new();
+        let mut all_ids: Vec<u64> = Vec::new();
+        let mut all_terms: Vec<Vec<(String, u32)>> = Vec::new();
+        let mut meta_rows: Vec<MetaRow> = Vec::new();
+        let mut next_id: u64 = 0;
+        let mut file_count: usize = 0;
+        let mut embedded_count: usize = 0;
+        let mut seen_paths: std::collections::HashSet<String> = std::collections::HashSet::new();
+        // A new storage layout rewrites the store even when no file changed.
+        let mut changed = prev
+            .as_ref()
+            .is_none_or(|p| p.storage != opts.storage || p.rescore != self.rescore());
+
+        // Pipeline: a producer thread reads + chunks files in fixed windows on a
+        // worker pool while this thread embeds the previous window in batches.
+        // The channel holds at most one finished window, which bounds memory, and
+        // windows are consumed in file order so ids/meta stay deterministic.
+        let window = (threads * 8).max(batch_size);
+        std::thread::scope(|scope| -> Result<()> {
+            let (tx, rx) = std::sync::mpsc::sync_channel::<Result<Vec<PreparedFile>>>(1);
+            let prev = &prev;
+            let files = &files;
+            let scrubber = &scrubber;
+            scope.spawn(move || {
+                for paths in files.chunks(window) {
+                    let prepared =
+                        prepare_window(paths, root, opts, scrubber, prev.as_ref(), threads);
+                    let failed = prepared.is_err();
+                    if tx.send(prepared).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+
+            for (n, prepared) in std::iter::once(Ok(carried)).chain(rx).enumerate() {
+                let prepared = prepared?;
+
+                // Embed every chunk in this window that has no reusable vector.
+                let pending: Vec<&str> = prepared
+                    .iter()
+                    .flat_map(|f| f.chunks.iter())
+                    .filter_map(|c| c.text.as_deref())
+                    .collect();
+                let mut embedded: Vec<Option<Vec<f32>>> = Vec::with_capacity(pending.len());
+                for batch in pending.chunks(batch_size) {
+                    if model.is_none() {
+                        model = Some(embedder(
+                            opts.embedder,
+                            &opts.model,
+                            opts.embed_url.as_deref(),
+                        )?);
+                    }
+                    let out = model
+                        .as_ref()
+                        .expect("embedder initialized")
+                        .embed(batch)
+                        .context("embed chunks")?;
+                    if out.len() != batch.len() {
+                        bail!(
+                            "embedder returned {} vectors for {} chunks",
+                            out.len(),
+                            batch.len()
+                        );
+                    }
+                    // Normalize vectors (L2); empty or zero vectors are skipped below.
+                    embedded.extend(out.into_iter().map(l2_normalize));
+                }
+                embedded_count += embedded.len();
+                let mut embedded = embedded.into_iter();
+
+                for file in prepared {
+                    file_count += 1;
+                    seen_paths.insert(file.relp.clone());
+
+                    // Unchanged file: carry rows and vectors over as-is (ids are renumbered).
+                    if let Some(prev) = prev
+                        && let Some(rows) = prev.unchanged_rows(&file.relp, &file.file_sha)
+                    {
+                        for (row, pos) in rows {
+                            let id = next_id;
+                            next_id += 1;
+                            all_ids.push(id);
+                            all_vecs.push(prev.vector(*pos).to_vec());
+                            all_terms.push(prev.terms(*pos));
+                            meta_rows.push(MetaRow { id, ..row.clone() });
+                        }
+                        continue;
+                    }
+
+                    let had_rows = prev
+                        .as_ref()
+                        .is_some_and(|prev| prev.rows_by_path.contains_key(&file.relp));
+                    if had_rows || !file.chunks.is_empty() {
+                        changed = true;
+                    }
+                    for chunk in file.chunks {
+                        // Reuse the vector of an identical chunk from the previous index when present.
+                        let vnorm = match chunk.reuse {
+                            Some(pos) => match prev {
+                                Some(prev) => prev.vector(pos).to_vec(),
+                                None => continue,
+                            },
+                            None => match embedded.next().flatten() {
+                                Some(v) => v,
+                                None => continue,
+                            },
+                        };
+                        if let Some(prev) = prev
+                            && vnorm.len() != prev.dim
+                        {
+                            bail!(
+                                "embedding dim {} != index dim {}; rebuild with --force",
+                                vnorm.len(),
+                                prev.dim
+                            );
+                        }
+
+                        let id = next_id;
+                        next_id += 1;
+                        all_ids.push(id);
+                        all_vecs.push(vnorm);
+                        all_terms.push(chunk.terms);
+                        meta_rows.push(MetaRow {
+                            id,
+                            path: file.relp.clone(),
+                            start: chunk.start,
+                            end: chunk.end,
+                            lang: file.lang.clone(),
+                            sha256: chunk.sha256,
+                            file_sha256: file.file_sha.clone(),
+                            symbols: chunk.symbols,
+                            section: chunk.section,
+                            redacted: chunk.redacted,
+                            preview: chunk.preview,
+                        });
+                    }
+                }
+                // The first window holds the carried-over files, which are not read.
+                if n == 0 && all_ids.is_empty() {
+                    continue;
+                }
+                let p = &mut reporter.progress;
+                if n > 0 {
+                    p.files_embedded = (p.files_embedded + window).min(files.len());
+                }
+                p.chunks = all_ids.len();
+                p.chunks_embedded = embedded_count;
+                reporter.emit(BuildPhase::Embed);
+            }
+            Ok(())
+        })?;
+
+        // Files that disappeared since the last build drop their rows.
+        if let Some(prev) = &prev
+            && prev.rows_by_path.keys().any(|p| !seen_paths.contains(p))
+        {
+            changed = true;
+        }
+
+        let report = BuildReport {
+            files: file_count,
+            chunks: all_ids.len(),
+            embedded: embedded_count,
+            changed,
+            skipped,
+        };
+        let created_at = prev
+            .as_ref()
+            .map(|p| p.created_at.clone())
+            .unwrap_or_else(store::now_iso);
+
+        if !changed {
+            // Nothing to re-embed or drop: keep vectors/graph and only bump the manifest.
+            let mut m = store::read_manifest(root)?;
+            m.repo.git_sha = store::read_git_head_sha(root);
+            m.counts.files = file_count;
+            m.last_refresh = store::now_iso();
+            store::write_manifest(root, &m)?;
+            reporter.emit(BuildPhase::Done);
+            return Ok(report);
+        }
+        reporter.emit(BuildPhase::Write);
+
+        if all_vecs.is_empty() {
+            // Nothing to index; create minimal manifest and return Ok
+            for f in [VECTORS_FILE, EXACT_FILE, META_FILE, LEXICAL_FILE] {
+                let _ = fs::remove_file(dir.join(f));
+            }
+            for ext in ["hnsw.graph", "hnsw.data"] {
+                let _ = fs::remove_file(dir.join(format!("{}.{}", HNSW_BASENAME, ext)));
+            }
+            let counts = Counts {
+                files: file_count,
+                chunks: 0,
+                redacted: 0,
+            };
+            let m = self.manifest(0, counts, &scrubber, Checksums::default(), created_at);
+            store::write_manifest(root, &m)?;
+            reporter.emit(BuildPhase::Done);
+            return Ok(report);
+        }
+
+        let dim = all_vecs[0].len();
+        // Flatten vectors for our linear scan file (ids + contiguous rows)
+        let mut flat: Vec<f32> = Vec::with_capacity(dim * all_vecs.len());
+        for v in &all_vecs {
+            flat.extend_from_slice(&v[..]);
+        }
+
+        // Persist vectors + ids atomically, plus the exact copy when re-scoring
+        let vec_tmp = dir.join(format!("{}.tmp", VECTORS_FILE));
+        vectors::write(&vec_tmp, opts.storage, dim, &all_ids, &flat)?;
+        fs::rename(&vec_tmp, dir.join(VECTORS_FILE))?;
+        if self.rescore() {
+            let exact_tmp = dir.join(format!("{}.tmp", EXACT_FILE));
+            vectors::write(&exact_tmp, Storage::F32, dim, &all_ids, &flat)?;
+            fs::rename(&exact_tmp, dir.join(EXACT_FILE))?;
+        } else {
+            let _ = fs::remove_file(dir.join(EXACT_FILE));
+        }
+
+        // Persist meta.jsonl atomically
+        let meta_tmp = dir.join(format!("{}.tmp", META_FILE));
+        store::write_meta(&meta_tmp, &meta_rows)?;
+        fs::rename(&meta_tmp, dir.join(META_FILE))?;
+
+        // Persist the lexical (BM25) index over the same rows
+        LexicalIndex::from_docs(&all_terms).save(&dir.join(LEXICAL_FILE))?;
+
+        // hnsw_rs has no point removal, so the graph is rebuilt from the stored
+        // vectors; only new or modified chunks were re-embedded above.
+        let rows: Vec<&[f32]> = all_vecs.iter().map(|v| &v[..]).collect();
+        write_graph(&dir, &rows)?;
+
+        // Compute checksums for manifest
+        let checksums = Checksums {
+            vectors_hnsw: store::sha256_file(dir.join(VECTORS_FILE))?,
+            meta_jsonl: store::sha256_file(dir.join(META_FILE))?,
+        };
+        let counts = Counts {
+            files: file_count,
+            chunks: all_ids.len(),
+            redacted: meta_rows.iter().filter(|r| r.redacted).count(),
+        };
+        let m = self.manifest(dim, counts, &scrubber, checksums, created_at);
+        store::write_manifest(root, &m)?;
+        reporter.emit(BuildPhase::Done);
+        Ok(report)
+    }
+
+    // Re-scoring only applies to quantized stores.
+    fn rescore(&self) -> bool {
+        self.opts.rescore && self.opts.storage != Storage::F32
+    }
+
+    // Endpoint recorded in the manifest so queries reach the same server.
+    fn embed_url(&self) -> Option<String> {
+        match self.opts.embedder {
+            Provider::Fastembed => None,
+            p => self.opts.embed_url.clone().or_else(|| p.default_url()),
+        }
+    }
+
+    fn manifest(
+        &self,
+        dim: usize,
+        counts: Counts,
+        scrubber: &Scrubber,
+        checksums: Checksums,
+        created_at: String,
+    ) -> Manifest {
+        Manifest {
+            index_version: INDEX_VERSION,
+            engine: format!("{}+hnsw", self.opts.embedder.as_str()),
+            embedder: self.opts.embedder,
+            model: self.opts.model.clone(),
+            embed_url: self.embed_url(),
+            dim,
+            storage: self.opts.storage,
+            rescore: self.rescore(),
+            metric: "cosine".into(),
+            chunk_mode: self.opts.chunk.clone(),
+            chunk_header: self.opts.header_format().to_string(),
+            secrets: scrubber.fingerprint().to_string(),
+            chunk: ChunkCfg {
+                lines: self.opts.lines,
+                overlap: self.opts.overlap,
+            },
+            limits: Limits {
+                max_file_size: self.opts.max_file_size,
+                max_files: self.opts.max_files,
+            },
+            repo: RepoInfo {
+                root: self.root.to_string_lossy().into(),
+                git_sha: store::read_git_head_sha(&self.root),
+            },
+            counts,
+            checksums,
+            created_at,
+            last_refresh: store::now_iso(),
+        }
+    }
+}
+
+/// Build the HNSW graph over `rows` (data id = row position) and dump it as
+/// `vectors.hnsw.graph` + `vectors.hnsw.data` in `dir`. The files are written
+/// under a temporary name and renamed into place, since readers may have the
+/// current ones memory-mapped.
+pub(crate) fn write_graph(dir: &Path, rows: &[&[f32]]) -> Result<()> {
+    let max_points = rows.len();
+    let m = 32usize; // max connections (M)
+    let ef_c = 200usize; // construction ef
+    let max_layer = 16usize;
+    let dist = DistCosine;
+    let hnsw: Hnsw<f32, DistCosine> = Hnsw::new(m, max_points, max_layer, ef_c, dist);
+    let items: Vec<(&[f32], usize)> = rows.iter().enumerate().map(|(i, v)| (*v, i)).collect();
+    hnsw.parallel_insert_slice(&items);
+    let tmp = hnsw
+        .file_dump(dir, &format!("{HNSW_BASENAME}.tmp"))
+        .context("dump HNSW files")?;
+    // Data first: a reader that loads in between sees the old graph, whose
+    // ids all exist in the new data.
+    for ext in ["hnsw.data", "hnsw.graph"] {
+        let to = dir.join(format!("{HNSW_BASENAME}.{ext}"));
+        fs::rename(dir.join(format!("{tmp}.{ext}")), &to)
+            .with_context(|| format!("replace {}", to.display()))?;
+    }
+    Ok(())
+}
+
+// Every indexable file under `dir` (the root or one of its subdirectories),
+// honoring .index-ignore and .gitignore files at any depth.
+fn scan(dir: &Path, max_file_size: u64) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    // Only the ignore files `PathFilter` knows (so `index ignore --explain`
+    // agrees with the scan): no `.ignore` files and no global excludes, and
+    // `.gitignore` files apply outside git repositories too.
+    for dent in WalkBuilder::new(dir)
+        .add_custom_ignore_filename(store::IGNORE_FILE)
+        .ignore(false)
+        .hidden(true)
+        .git_ignore(true)
+        .git_global(false)
+        .git_exclude(true)
+        .require_git(false)
+        .max_depth(None)
+        .build()
+    {
+        let dent = match dent {
+            Ok(d) => d,
+            Err(_) => continue,
+        };
+        let path = dent.path();
+        if !path.is_file() {
+            continue;
+        }
+        if should_skip(path, max_file_size) {
+            continue;
+        }
+        files.push(path.to_path_buf());
+    }
+    files
+}
+
+// Split an update into files to re-read (the changed paths that still exist;
+// directories are scanned) and files whose previous rows are carried over.
+fn plan_update(
+    root: &Path,
+    filter: &PathFilter,
+    changed: &[PathBuf],
+    prev: &PrevIndex,
+    max_file_size: u64,
+) -> (Vec<PathBuf>, Vec<PreparedFile>) {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut touched: Vec<String> = Vec::new();
+    for path in changed {
+        let path = root.join(path);
+        touched.push(store::rel(root, &path));
+        if path.is_dir() {
+            files.extend(
+                scan(&path, max_file_size)
+                    .into_iter()
+                    .filter(|p| filter.allows(p)),
+            );
+        } else if path.is_file() && filter.allows(&path) && !should_skip(&path, max_file_size) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    let is_touched = |relp: &str| {
+        touched.iter().any(|t| {
+            relp == t
+                || relp
+                    .strip_prefix(t.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    };
+    let mut carried: Vec<PreparedFile> = prev
+        .rows_by_path
+        .iter()
+        .filter(|(relp, _)| {
+            let path = root.join(relp);
+            !is_touched(relp)
+                && path.is_file()
+                && filter.allows(&path)
+                && !should_skip(&path, max_file_size)
+        })
+        .filter_map(|(relp, rows)| {
+            let (row, _) = rows.first()?;
+            Some(PreparedFile {
+                relp: relp.clone(),
+                file_sha: row.file_sha256.clone(),
+                lang: row.lang.clone(),
+                chunks: Vec::new(),
+            })
+        })
+        .collect();
+    carried.sort_by(|a, b| a.relp.cmp(&b.relp));
+    (files, carried)
+}
+
+fn should_skip(path: &Path, max_file_size: u64) -> bool {
+    // Skip symlinks to avoid cycles and out-of-tree traversal
+    if let Ok(md) = fs::symlink_metadata(path) {
+        if md.file_type().is_symlink() {
+            return true;
+        }
+    }
+    // Conservative Windows path handling: skip very long paths
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        let wlen = path.as_os_str().encode_wide().count();
+        if wlen > 245 {
+            // ~MAX_PATH minus buffer
+            return true;
+        }
+    }
+    if let Ok(md) = fs::metadata(path) {
+        if md.len() > max_file_size {
+            return true;
+        }
+    }
+    false
+}
+
+/// Previously built index, loaded so unchanged files/chunks can reuse their vectors.
+struct PrevIndex {
+    dim: usize,
+    storage: Storage,
+    rescore: bool,
+    created_at: String,
+    data: Vec<f32>,
+    // Rows per relative path (in id order) paired with their row position in `data`.
+    rows_by_path: std::collections::HashMap<String, Vec<(MetaRow, usize)>>,
+    // Chunk text sha256 -> row position, so moved/unchanged chunks skip re-embedding.
+    by_chunk: std::collections::HashMap<String, usize>,
+    // Per-row term counts from the lexical index; None when it is missing.
+    doc_terms: Option<Vec<Vec<(String, u32)>>>,
+    // Whole-file row reuse needs the current chunking format and lexical data;
+    // otherwise files are re-chunked and only chunk vectors are reused.
+    rows_reusable: bool,
+}
+
+impl PrevIndex {
+    /// Load the on-disk index when it is compatible with `opts` (same model and
+    /// chunking settings, and vectors at least as precise as the new storage
+    /// needs). Returns None when a full rebuild is required.
+    fn load(root: &Path, opts: &BuildOptions, secrets: &str) -> Option<Self> {
+        let m = store::read_manifest(root).ok()?;
+        let dir = store::index_dir(root);
+        if m.embedder != opts.embedder
+            || m.model != opts.model
+            || m.chunk_mode != opts.chunk
+            || m.chunk.lines != opts.lines
+            || m.chunk.overlap != opts.overlap
+            || m.dim == 0
+            || (m.storage != opts.storage && m.storage != Storage::F32 && !m.rescore)
+        {
+            return None;
+        }
+        let (ids, data) = vectors::load_exact(&dir).ok()?;
+        if data.len() != ids.len() * m.dim {
+            return None;
+        }
+        let mut meta = store::load_meta(dir.join(META_FILE)).ok()?;
+        let mut rows_by_path: std::collections::HashMap<String, Vec<(MetaRow, usize)>> =
+            std::collections::HashMap::new();
+        let mut by_chunk = std::collections::HashMap::new();
+        for (pos, id) in ids.iter().enumerate() {
+            let row = meta.remove(id)?;
+            by_chunk.entry(row.sha256.clone()).or_insert(pos);
+            rows_by_path
+                .entry(row.path.clone())
+                .or_default()
+                .push((row, pos));
+        }
+        let doc_terms = LexicalIndex::load(&dir.join(LEXICAL_FILE))
+            .ok()
+            .filter(|l| l.docs() == ids.len())
+            .map(|l| l.doc_terms());
+        Some(Self {
+            dim: m.dim,
+            storage: m.storage,
+            rescore: m.rescore,
+            created_at: m.created_at,
+            data,
+            rows_by_path,
+            by_chunk,
+            rows_reusable: m.index_version == INDEX_VERSION
+                && m.chunk_header == opts.header_format()
+                && m.secrets == secrets
+                && doc_terms.is_some(),
+            doc_terms,
+        })
+    }
+
+    fn vector(&self, pos: usize) -> &[f32] {
+        &self.data[pos * self.dim..(pos + 1) * self.dim]
+    }
+
+    fn terms(&self, pos: usize) -> Vec<(String, u32)> {
+        self.doc_terms
+            .as_ref()
+            .and_then(|d| d.get(pos).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Rows for `path` when the file content hash matches what was indexed.
+    fn unchanged_rows(&self, path: &str, file_sha: &str) -> Option<&[(MetaRow, usize)]> {
+        if !self.rows_reusable {
+            return None;
+        }
+        let rows = self.rows_by_path.get(path)?;
+        if rows
+            .iter()
+            .all(|(r, _)| !r.file_sha256.is_empty() && r.file_sha256 == file_sha)
+        {
+            Some(rows)
+        } else {
+            None
+        }
+    }
+}
+
+pub(crate) fn l2_normalize(mut v: Vec<f32>) -> Option<Vec<f32>> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return None;
+    }
+    for x in v.iter_mut() {
+        *x /= norm;
+    }
+    Some(v)
+}
+
+/// A file read and chunked by a pipeline worker, ready for embedding.
+struct PreparedFile {
+    relp: String,
+    file_sha: String,
+    lang: String,
+    // Empty when the file is unchanged and its previous rows are reused.
+    chunks: Vec<PreparedChunk>,
+}
+
+struct PreparedChunk {
+    start: usize,
+    end: usize,
+    sha256: String,
+    preview: String,
+    // Row position of an identical chunk in the previous index.
+    reuse: Option<usize>,
+    // Chunk text to embed when nothing can be reused.
+    text: Option<String>,
+    // Term counts for the lexical index.
+    terms: Vec<(String, u32)>,
+    symbols: Vec<Symbol>,
+    // Heading/key path of a Markdown or config chunk, prepended to its text.
+    section: String,
+    // Secrets were redacted from the chunk.
+    redacted: bool,
+}
+
+// Lower the scheduling priority of the calling thread, and of the threads it
+// spawns from now on, for the rest of its life: unprivileged processes cannot
+// raise it again, and background builds run on threads of their own. Linux
+// only, where the nice value is per thread; elsewhere it would slow the whole
+// process, UI included.
+fn lower_priority() {
+    #[cfg(target_os = "linux")]
+    // SAFETY: setpriority only changes the nice value of the calling thread
+    // (`who` 0 is the caller's thread id on Linux).
+    unsafe {
+        libc::setpriority(libc::PRIO_PROCESS, 0, 10);
+    }
+}
+
+fn build_threads(requested: usize) -> usize {
+    if requested > 0 {
+        return requested;
+    }
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+fn prepare_file(
+    path: &Path,
+    root: &Path,
+    opts: &BuildOptions,
+    scrubber: &Scrubber,
+    prev: Option<&PrevIndex>,
+) -> Result<Option<PreparedFile>> {
+    let text = match read_text_if_textual(path)? {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    let relp = store::rel(root, path);
+    let file_sha = sha256_hex(&text);
+    let lang = language_for(path, &text);
+    if prev.is_some_and(|prev| prev.unchanged_rows(&relp, &file_sha).is_some()) {
+        return Ok(Some(PreparedFile {
+            relp,
+            file_sha,
+            lang,
+            chunks: Vec::new(),
+        }));
+    }
+    // Offsets and lines below refer to the scrubbed text, which keeps every
+    // line of the file.
+    let (text, redactions) = scrubber.scrub(&text);
+    let defs = definitions(&text, path, &lang);
+    let mut sections = Vec::new();
+    let ranges = match opts.chunk.as_str() {
+        "lines" => chunk_lines(&text, opts.lines.max(8), opts.overlap.min(opts.lines / 2)),
+        _ => match chunk_sections(&text, &lang, opts.lines.max(80)) {
+            Some(found) => found
+                .into_iter()
+                .map(|s| {
+                    sections.push(s.path);
+                    (s.start, s.end, s.preview)
+                })
+                .collect(),
+            None => chunk_auto(
+                &text,
+                defs.as_deref(),
+                opts.lines.max(80),
+                opts.overlap.min(opts.lines / 2),
+            ),
+        },
+    };
+    sections.resize(ranges.len(), String::new());
+    let defs = defs.unwrap_or_default();
+    let headers: Vec<String> = ranges
+        .iter()
+        .zip(&sections)
+        .map(|((s, e, _), section)| {
+            if !opts.chunk_header {
+                return match section.as_str() {
+                    "" => String::new(),
+                    path => format!("{path}\n"),
+                };
+            }
+            let doc = defs
+                .iter()
+                .find(|d| d.symbol.is_some() && *s <= d.start && d.start < *e)
+                .map(|d| leading_doc(&text, d.start))
+                .unwrap_or_default();
+            chunk_header(&relp, &lang, &enclosing_scope(&defs, *s), section, &doc)
+        })
+        .collect();
+    // Each symbol belongs to the first chunk containing the start of its definition.
+    let mut chunk_symbols: Vec<Vec<Symbol>> = vec![Vec::new(); ranges.len()];
+    for def in defs {
+        if let Some(sym) = def.symbol
+            && let Some(i) = ranges
+                .iter()
+                .position(|(s, e, _)| *s <= def.start && def.start < *e)
+        {
+            chunk_symbols[i].push(sym);
+        }
+    }
+    let chunks = ranges
+        .into_iter()
+        .zip(chunk_symbols)
+        .zip(sections)
+        .zip(headers)
+        .map(|((((s, e, preview), symbols), section), header)| {
+            // The header is embedded and keyword-indexed but not previewed.
+            let chunk = format!("{header}{}", &text[s..e]);
+            let sha256 = sha256_hex(&chunk);
+            let reuse = prev.and_then(|prev| prev.by_chunk.get(&sha256).copied());
+            let redacted = redactions.iter().any(|r| r.start < e && s < r.end);
+            PreparedChunk {
+                start: offset_to_line(&text, s),
+                end: offset_to_line(&text, e),
+                terms: lexical::term_counts(&chunk),
+                text: reuse.is_none().then_some(chunk),
+                symbols,
+                section,
+                redacted,
+                sha256,
+                preview,
+                reuse,
+            }
+        })
+        .collect();
+    Ok(Some(PreparedFile {
+        relp,
+        file_sha,
+        lang,
+        chunks,
+    }))
+}
+
+const INDEX_USAGE: &str = "Usage: /index status | build [--model bge-small|bge-large] [--force] | symbols <name> [--kind fn] [--exact] | verify [--deep|--repair] | clean";
+
+/// Text reply for the `/index` slash command of the ACP agent and TUI (see
+/// `INDEX_USAGE`); blocking, so run it off the async runtime.
+pub fn index_command(root: &Path, input: &str) -> String {
+    let mut args = input.split_whitespace();
+    let sub = args.next().unwrap_or("status");
+    let flags: Vec<&str> = args.collect();
+    let value = |flag: &str| {
+        let i = flags.iter().position(|f| *f == flag)?;
+        flags.get(i + 1).copied()
+    };
+    let out = match sub {
+        "status" => Ok(store::Status::describe(root)),
+        "build" => {
+            let mut opts = Builder::refresh(root).options().clone();
+            opts.force = flags.contains(&"--force");
+            if let Some(model) = value("--model") {
+                opts.model = model.to_string();
+            }
+            Builder::new(root, opts).build().map(|r| {
+                let mut out = format!(
+                    "Index built: {} files, {} chunks ({} embedded)",
+                    r.files, r.chunks, r.embedded
+                );
+                if r.skipped > 0 {
+                    out.push_str(&format!("\n{} files not indexed (max files)", r.skipped));
+                }
+                out
+            })
+        }
+        "verify" if flags.contains(&"--repair") => crate::verify::repair(root).map(|r| {
+            let mut lines = Vec::new();
+            if r.repaired.is_empty() {
+                lines.push("Index verify: OK (nothing to repair)".to_string());
+            }
+            lines.extend(r.actions.iter().map(|a| format!("Repaired: {a}")));
+            lines.extend(issue_lines(&r.remaining));
+            lines.join("\n")
+        }),
+        "verify" if flags.contains(&"--deep") => crate::verify::verify_deep(root).map(|r| {
+            if r.ok() {
+                "Index verify: OK".to_string()
+            } else {
+                let mut lines = issue_lines(&r.issues);
+                lines.push(format!(
+                    "Index verify FAILED ({} issue(s); run `/index verify --repair`)",
+                    r.issues.len()
+                ));
+                lines.join("\n")
+            }
+        }),
+        "verify" => store::verify(root).map(|ok| {
+            if ok {
+                "Index verify: OK".to_string()
+            } else {
+                "Index verify FAILED (checksums mismatch)".to_string()
+            }
+        }),
+        "symbols" => match flags.first().filter(|f| !f.starts_with("--")) {
+            Some(name) => crate::symbols::find_symbols(
+                root,
+                name,
+                value("--kind"),
+                flags.contains(&"--exact"),
+                20,
+            )
+            .map(|found| {
+                if found.is_empty() {
+                    return format!("No symbols match `{name}`.");
+                }
+                let lines: Vec<String> = found
+                    .iter()
+                    .map(|m| {
+                        let sym = &m.symbol;
+                        let qualified = match &sym.container {
+                            Some(c) => format!("{c}::{}", sym.name),
+                            None => sym.name.clone(),
+                        };
+                        format!("{}:{} {} {qualified}", m.path, sym.line, sym.kind)
+                    })
+                    .collect();
+                lines.join("\n")
+            }),
+            None => Ok("Usage: /index symbols <name> [--kind fn] [--exact]".to_string()),
+        },
+        "clean" => store::clean(root).map(|()| "Index removed".to_string()),
+        _ => Ok(INDEX_USAGE.to_string()),
+    };
+    out.unwrap_or_else(|e| format!("Error: {e:#}"))
+}
+
+fn issue_lines(issues: &[crate::verify::Issue]) -> Vec<String> {
+    issues
+        .iter()
+        .map(|i| format!("{:<12} {}", i.kind.as_str(), i.detail))
+        .collect()
+}
+
+/// Read and chunk `paths` on up to `threads` workers, returning results in input order.
+fn prepare_window(
+    paths: &[PathBuf],
+    root: &Path,
+    opts: &BuildOptions,
+    scrubber: &Scrubber,
+    prev: Option<&PrevIndex>,
+    threads: usize,
+) -> Result<Vec<PreparedFile>> {
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let joined: Vec<_> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(paths.len()).max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut out = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        if i >= paths.len() {
+                            break;
+                        }
+                        out.push((i, prepare_file(&paths[i], root, opts, scrubber, prev)));
+                    }
+                    out
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join()).collect()
+    });
+    // A panicked worker's files would otherwise look deleted to the incremental
+    // update, so fail the whole build before anything is committed.
+    let mut results: Vec<(usize, Result<Option<PreparedFile>>)> = Vec::with_capacity(paths.len());
+    for worker in joined {
+        match worker {
+            Ok(out) => results.extend(out),
+            Err(_) => bail!("file preparation worker panicked; index left unchanged"),
+        }
+    }
+    results.sort_by_key(|(i, _)| *i);
+    let mut prepared = Vec::with_capacity(results.len());
+    for (_, r) in results {
+        if let Some(f) = r? {
+            prepared.push(f);
+        }
+    }
+    Ok(prepared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prev_index_reuses_only_unchanged_files() {
+        let row = |id: u64, path: &str, file_sha: &str| MetaRow {
+            id,
+            sha256: format!("chunk{id}"),
+            file_sha256: file_sha.into(),
+            ..MetaRow::fixture(path, 1, 2)
+        };
+        let mut rows_by_path = std::collections::HashMap::new();
+        rows_by_path.insert("a.rs".to_string(), vec![(row(0, "a.rs", "sha-a"), 0)]);
+        rows_by_path.insert("b.rs".to_string(), vec![(row(1, "b.rs", ""), 1)]);
+        let prev = PrevIndex {
+            dim: 2,
+            storage: Storage::F32,
+            rescore: false,
+            created_at: String::new(),
+            data: vec![1.0, 0.0, 0.0, 1.0],
+            rows_by_path,
+            by_chunk: std::collections::HashMap::new(),
+            doc_terms: Some(vec![Vec::new(); 2]),
+            rows_reusable: true,
+        };
+        assert!(prev.unchanged_rows("a.rs", "sha-a").is_some());
+        assert!(prev.unchanged_rows("a.rs", "sha-a2").is_none());
+        // Rows from indexes built before file hashes were recorded never match.
+        assert!(prev.unchanged_rows("b.rs", "").is_none());
+        assert_eq!(prev.vector(1), &[0.0, 1.0]);
+    }
+
+    #[test]
+    fn prepare_window_keeps_input_order() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let paths: Vec<PathBuf> = (0..23)
+            .map(|i| {
+                let p = dir.path().join(format!("f{i}.txt"));
+                fs::write(&p, format!("file {i}\n")).expect("write");
+                p
+            })
+            .collect();
+        let opts = BuildOptions {
+            force: true,
+            chunk: "lines".into(),
+            batch_size: 8,
+            threads: 4,
+            ..BuildOptions::default()
+        };
+        let scrubber = Scrubber::new(&[]).expect("scrubber");
+        let prepared =
+            prepare_window(&paths, dir.path(), &opts, &scrubber, None, 4).expect("prepare");
+        let got: Vec<String> = prepared.iter().map(|f| f.relp.clone()).collect();
+        let want: Vec<String> = (0..23).map(|i| format!("f{i}.txt")).collect();
+        assert_eq!(got, want);
+        assert!(prepared.iter().all(|f| f.chunks.len() == 1));
+    }
+
+    #[test]
+    fn plan_update_rereads_only_touched_paths() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("gone")).expect("mkdir");
+        for f in ["a.rs", "b.rs", "c.rs"] {
+            fs::write(root.join(f), "fn x() {}\n").expect("write");
+        }
+        let row = |id: u64, path: &str| MetaRow {
+            id,
+            sha256: format!("chunk{id}"),
+            file_sha256: format!("sha-{path}"),
+            ..MetaRow::fixture(path, 1, 1)
+        };
+        let mut rows_by_path = std::collections::HashMap::new();
+        for (id, path) in ["a.rs", "b.rs", "gone/d.rs"].into_iter().enumerate() {
+            rows_by_path.insert(path.to_string(), vec![(row(id as u64, path), id)]);
+        }
+        let prev = PrevIndex {
+            dim: 1,
+            storage: Storage::F32,
+            rescore: false,
+            created_at: String::new(),
+            data: vec![1.0; 3],
+            rows_by_path,
+            by_chunk: std::collections::HashMap::new(),
+            doc_terms: Some(vec![Vec::new(); 3]),
+            rows_reusable: true,
+        };
+        let changed = vec![root.join("b.rs"), root.join("c.rs"), PathBuf::from("gone")];
+        let (files, carried) = plan_update(
+            root,
+            &PathFilter::new(root),
+            &changed,
+            &prev,
+            store::DEFAULT_MAX_FILE_SIZE,
+        );
+        assert_eq!(files, vec![root.join("b.rs"), root.join("c.rs")]);
+        let carried: Vec<(&str, &str)> = carried
+            .iter()
+            .map(|f| (f.relp.as_str(), f.file_sha.as_str()))
+            .collect();
+        assert_eq!(carried, vec![("a.rs", "sha-a.rs")]);
+    }
+
+    #[test]
+    fn index_command_runs_every_advertised_subcommand() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        fs::write(root.join("lib.rs"), "pub fn run_server() {}\n").expect("write");
+        Builder::new(root, BuildOptions::fake("fake-small"))
+            .build()
+            .expect("build");
+        BuildOptions::fake("fake-large");
+        let run = |input: &str| index_command(root, input);
+        assert!(run("status").starts_with("Index:"));
+        assert_eq!(
+            run("build --force"),
+            "Index built: 1 files, 1 chunks (1 embedded)"
+        );
+        assert_eq!(
+            run("build --model fake-large"),
+            "Index built: 1 files, 1 chunks (1 embedded)"
+        );
+        assert_eq!(
+            store::read_manifest(root).expect("manifest").model,
+            "fake-large"
+        );
+        assert_eq!(
+            run("symbols run_server --kind fn"),
+            "lib.rs:1 fn run_server"
+        );
+        assert_eq!(
+            run("symbols nothing --exact"),
+            "No symbols match `nothing`."
+        );
+        assert_eq!(run("verify"), "Index verify: OK");
+        assert_eq!(run("verify --deep"), "Index verify: OK");
+        assert_eq!(
+            run("verify --repair"),
+            "Index verify: OK (nothing to repair)"
+        );
+        assert_eq!(run("clean"), "Index removed");
+        assert_eq!(run("status"), "Index: Missing");
+        assert!(run("symbols").starts_with("Usage: /index symbols"));
+        assert!(run("bogus").starts_with("Usage: /index status"));
+    }
+
+    #[test]
+    fn builds_assign_ids_in_path_order() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("m")).expect("mkdir");
+        let names: Vec<String> = (0..12)
+            .flat_map(|i| [format!("f{}.rs", 11 - i), format!("m/g{i}.rs")])
+            .collect();
+        for name in &names {
+            fs::write(root.join(name), format!("fn f() {{}} // {name}\n")).expect("write");
+        }
+        let meta_file = store::index_dir(root).join(store::META_FILE);
+        let build = |force: bool| {
+            let opts = BuildOptions {
+                force,
+                ..BuildOptions::fake("fake-small")
+            };
+            Builder::new(root, opts).build().expect("build");
+            fs::read_to_string(&meta_file).expect("meta")
+        };
+        let first = build(false);
+        assert_eq!(build(true), first);
+        let rows = store::load_meta(&meta_file).expect("meta");
+        let mut ids: Vec<u64> = rows.keys().copied().collect();
+        ids.sort();
+        let paths: Vec<&str> = ids.iter().map(|id| rows[id].path.as_str()).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(paths, sorted);
+    }
+
+    #[test]
+    fn scan_agrees_with_path_filter_outside_git() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let filter = PathFilter::new(root);
+        for dir in ["src/gen", "build", ".hidden"] {
+            fs::create_dir_all(root.join(dir)).expect("mkdir");
+        }
+        fs::write(root.join(".gitignore"), "build/\n*.log\n").expect("write");
+        fs::write(root.join("src/.gitignore"), "gen/\n!keep.log\n").expect("write");
+        for f in [
+            "a.rs",
+            "app.log",
+            "build/out.rs",
+            "src/lib.rs",
+            "src/keep.log",
+            "src/gen/x.rs",
+            ".hidden/h.rs",
+        ] {
+            fs::write(root.join(f), "fn x() {}\n").expect("write");
+        }
+        fn walk(dir: &Path, out: &mut Vec<PathBuf>) {
+            for entry in fs::read_dir(dir).expect("read_dir").flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    walk(&path, out);
+                } else {
+                    out.push(path);
+                }
+            }
+        }
+        let mut all = Vec::new();
+        walk(root, &mut all);
+        let mut allowed: Vec<PathBuf> = all.into_iter().filter(|p| filter.allows(p)).collect();
+        allowed.sort();
+        let mut scanned = scan(root, store::DEFAULT_MAX_FILE_SIZE);
+        scanned.sort();
+        assert_eq!(scanned, allowed);
+        let rel: Vec<String> = scanned.iter().map(|p| store::rel(root, p)).collect();
+        assert_eq!(rel, ["a.rs", "src/keep.log", "src/lib.rs"]);
+    }
+
+    #[test]
+    fn max_file_size_skips_larger_files() {
+        assert_eq!(
+            store::parse_size("5M").unwrap(),
+            store::DEFAULT_MAX_FILE_SIZE
+        );
+        assert_eq!(store::parse_size("512KiB").unwrap(), 512 * 1024);
+        assert_eq!(store::parse_size("2048").unwrap(), 2048);
+        assert!(store::parse_size("5X").is_err());
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        fs::write(dir.path().join("small.rs"), "fn a() {}\n").expect("write");
+        fs::write(dir.path().join("big.rs"), "x".repeat(2048)).expect("write");
+        let found = |max_file_size| {
+            let mut files = scan(dir.path(), max_file_size);
+            files.sort();
+            files
+        };
+        assert_eq!(found(1024), [dir.path().join("small.rs")]);
+        assert_eq!(found(4096).len(), 2);
+    }
+}
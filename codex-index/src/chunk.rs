@@ -0,0 +1,600 @@
+//! Language detection and chunking: tree-sitter definitions for supported
+//! grammars, blank-line blocks otherwise, or fixed line windows.
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::symbols::{self, Symbol};
+
+pub(crate) fn language_for(path: &Path, text: &str) -> String {
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let by_name = match name {
+        "Dockerfile" | "Containerfile" => Some("dockerfile"),
+        "Makefile" | "makefile" | "GNUmakefile" => Some("make"),
+        "CMakeLists.txt" => Some("cmake"),
+        "Rakefile" | "Gemfile" => Some("ruby"),
+        "Jenkinsfile" => Some("groovy"),
+        _ if name.starts_with("Dockerfile.") => Some("dockerfile"),
+        _ => None,
+    };
+    if let Some(lang) = by_name {
+        return lang.into();
+    }
+    let by_ext = match path.extension().and_then(|s| s.to_str()).unwrap_or("") {
+        "rs" => "rust",
+        "ts" | "tsx" | "mts" | "cts" => "typescript",
+        "js" | "jsx" | "mjs" | "cjs" => "javascript",
+        "py" | "pyi" => "python",
+        "go" => "go",
+        "java" => "java",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" | "hxx" => "cpp",
+        "sh" | "bash" | "zsh" => "shell",
+        "rb" => "ruby",
+        "md" | "markdown" => "markdown",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "json" => "json",
+        "mk" => "make",
+        _ => "text",
+    };
+    if by_ext != "text" {
+        return by_ext.into();
+    }
+    language_from_shebang(text).unwrap_or(by_ext).into()
+}
+
+/// Language from a `#!` interpreter line, e.g. `#!/usr/bin/env python3`.
+fn language_from_shebang(text: &str) -> Option<&'static str> {
+    let line = text.lines().next()?.strip_prefix("#!")?;
+    let mut parts = line.split_whitespace();
+    let mut interp = parts.next()?.rsplit('/').next()?;
+    if interp == "env" {
+        interp = parts.find(|p| !p.starts_with('-') && !p.contains('='))?;
+    }
+    let interp = interp.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    Some(match interp {
+        "python" | "pypy" => "python",
+        "node" | "nodejs" | "deno" | "bun" => "javascript",
+        "ts-node" | "tsx" => "typescript",
+        "sh" | "bash" | "zsh" | "dash" | "ksh" => "shell",
+        "ruby" => "ruby",
+        "perl" => "perl",
+        "php" => "php",
+        "lua" => "lua",
+        _ => return None,
+    })
+}
+
+pub(crate) fn read_text_if_textual(path: &Path) -> Result<Option<String>> {
+    let mut f = File::open(path)?;
+    let mut buf = Vec::new();
+    f.read_to_end(&mut buf)?;
+    if looks_binary(&buf) {
+        return Ok(None);
+    }
+    Ok(String::from_utf8(buf).ok())
+}
+
+fn looks_binary(buf: &[u8]) -> bool {
+    if buf.len() >= 4 {
+        let magic = &buf[..8.min(buf.len())];
+        const PDF: &[u8] = b"%PDF-";
+        const ZIP: &[u8] = b"PK\x03\x04";
+        const ELF: &[u8] = b"\x7FELF";
+        const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+        const MZ: &[u8] = b"MZ";
+        if magic.starts_with(PDF)
+            || magic.starts_with(ZIP)
+            || magic.starts_with(ELF)
+            || magic.starts_with(PNG)
+            || magic.starts_with(MZ)
+        {
+            return true;
+        }
+    }
+    // if >10% NUL bytes or invalid UTF-8
+    let nul = buf.iter().filter(|b| **b == 0).count();
+    if nul * 10 > buf.len().max(1) {
+        return true;
+    }
+    std::str::from_utf8(buf).is_err()
+}
+
+pub(crate) fn chunk_auto(
+    text: &str,
+    defs: Option<&[Definition]>,
+    target: usize,
+    overlap: usize,
+) -> Vec<(usize, usize, String)> {
+    if let Some(defs) = defs {
+        if let Some(chunks) = chunk_definitions(text, defs, target, overlap) {
+            return chunks;
+        }
+    }
+    // fallback
+    chunk_blanklines(text, target, overlap)
+}
+
+type DefinitionKinds = &'static [(&'static str, &'static str)];
+
+/// A definition node found by tree-sitter: byte range and the named symbol, if any.
+pub(crate) struct Definition {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) symbol: Option<Symbol>,
+}
+
+/// Parse `text` with the grammar for `lang` and collect definition nodes in
+/// document order. None when there is no grammar or nothing was found.
+pub(crate) fn definitions(text: &str, path: &Path, lang: &str) -> Option<Vec<Definition>> {
+    let (grammar, kinds) = grammar_for(lang, path)?;
+    let mut parser = tree_sitter::Parser::new();
+    parser.set_language(&grammar).ok()?;
+    let tree = parser.parse(text, None)?;
+    fn collect(
+        node: tree_sitter::Node,
+        text: &str,
+        kinds: DefinitionKinds,
+        container: Option<&str>,
+        out: &mut Vec<Definition>,
+    ) {
+        let mut inner = container.map(str::to_string);
+        // Specifiers without a body (`struct foo *p;`) are references, not definitions.
+        let def = kinds.iter().find(|(k, _)| *k == node.kind()).filter(|_| {
+            !node.kind().ends_with("_specifier") || node.child_by_field_name("body").is_some()
+        });
+        if let Some((_, sym_kind)) = def {
+            let symbol = (!sym_kind.is_empty())
+                .then(|| symbols::definition_name(node, text))
+                .flatten()
+                .map(|name| Symbol {
+                    name,
+                    kind: symbols::definition_kind(node, sym_kind),
+                    container: container.map(str::to_string),
+                    line: node.start_position().row + 1,
+                });
+            if let Some(sym) = &symbol
+                && symbols::is_container_kind(&sym.kind)
+            {
+                inner = Some(sym.name.clone());
+            }
+            let r = node.range();
+            out.push(Definition {
+                start: r.start_byte,
+                end: r.end_byte,
+                symbol,
+            });
+        }
+        for i in 0..node.child_count() {
+            if let Some(ch) = node.child(i) {
+                collect(ch, text, kinds, inner.as_deref(), out);
+            }
+        }
+    }
+    let mut out = Vec::new();
+    collect(tree.root_node(), text, kinds, None, &mut out);
+    if out.is_empty() { None } else { Some(out) }
+}
+
+/// Tree-sitter grammar plus the definition node kinds chunks are cut around,
+/// each paired with the symbol kind it defines (empty: range only).
+fn grammar_for(lang: &str, path: &Path) -> Option<(tree_sitter::Language, DefinitionKinds)> {
+    let tsx = path.extension().and_then(|s| s.to_str()) == Some("tsx");
+    Some(match lang {
+        "rust" => (
+            tree_sitter_rust::LANGUAGE.into(),
+            &[
+                ("function_item", "fn"),
+                ("impl_item", "impl"),
+                ("trait_item", "trait"),
+                ("struct_item", "struct"),
+                ("enum_item", "enum"),
+                ("mod_item", "mod"),
+            ][..],
+        ),
+        "python" => (
+            tree_sitter_python::LANGUAGE.into(),
+            &[("function_definition", "fn"), ("class_definition", "class")][..],
+        ),
+        "typescript" => (
+            if tsx {
+                tree_sitter_typescript::LANGUAGE_TSX.into()
+            } else {
+                tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into()
+            },
+            &[
+                ("function_declaration", "fn"),
+                ("generator_function_declaration", "fn"),
+                ("class_declaration", "class"),
+                ("abstract_class_declaration", "class"),
+                ("method_definition", "fn"),
+                ("interface_declaration", "interface"),
+                ("type_alias_declaration", "type"),
+                ("enum_declaration", "enum"),
+                ("internal_module", "mod"),
+            ][..],
+        ),
+        "javascript" => (
+            tree_sitter_javascript::LANGUAGE.into(),
+            &[
+                ("function_declaration", "fn"),
+                ("generator_function_declaration", "fn"),
+                ("class_declaration", "class"),
+                ("method_definition", "fn"),
+            ][..],
+        ),
+        "go" => (
+            tree_sitter_go::LANGUAGE.into(),
+            &[
+                ("function_declaration", "fn"),
+                ("method_declaration", "fn"),
+                ("type_declaration", "type"),
+            ][..],
+        ),
+        "java" => (
+            tree_sitter_java::LANGUAGE.into(),
+            &[
+                ("class_declaration", "class"),
+                ("interface_declaration", "interface"),
+                ("enum_declaration", "enum"),
+                ("record_declaration", "class"),
+                ("method_declaration", "fn"),
+                ("constructor_declaration", "fn"),
+            ][..],
+        ),
+        "c" => (
+            tree_sitter_c::LANGUAGE.into(),
+            &[
+                ("function_definition", "fn"),
+                ("struct_specifier", "struct"),
+                ("enum_specifier", "enum"),
+                ("union_specifier", "struct"),
+                ("type_definition", "type"),
+            ][..],
+        ),
+        "cpp" => (
+            tree_sitter_cpp::LANGUAGE.into(),
+            &[
+                ("function_definition", "fn"),
+                ("class_specifier", "class"),
+                ("struct_specifier", "struct"),
+                ("enum_specifier", "enum"),
+                ("namespace_definition", "mod"),
+                ("template_declaration", ""),
+            ][..],
+        ),
+        _ => return None,
+    })
+}
+
+pub(crate) fn chunk_lines(
+    text: &str,
+    target: usize,
+    overlap: usize,
+) -> Vec<(usize, usize, String)> {
+    // Build line start offsets
+    let mut starts = vec![0usize];
+    for (i, _) in text.match_indices("\n") {
+        starts.push(i + 1);
+    }
+    let line_count = starts.len();
+    let mut res = Vec::new();
+    let mut cur = 1usize; // 1-based line index
+    while cur <= line_count {
+        let end_line = (cur + target - 1).min(line_count);
+        let s = starts[cur - 1];
+        let e = if end_line < line_count {
+            starts[end_line]
+        } else {
+            text.len()
+        };
+        if e > s {
+            res.push((s, e, preview(&text[s..e])));
+        }
+        if end_line == line_count {
+            break;
+        }
+        let next = end_line.saturating_sub(overlap) + 1;
+        if next <= cur {
+            break;
+        }
+        cur = next;
+    }
+    res
+}
+
+pub(crate) fn chunk_blanklines(
+    text: &str,
+    target: usize,
+    overlap: usize,
+) -> Vec<(usize, usize, String)> {
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    let mut start = 0usize;
+    let mut i = 0usize;
+    let bytes = text.as_bytes();
+    while i < bytes.len() {
+        if bytes[i] == b'\n' && i + 1 < bytes.len() && bytes[i + 1] == b'\n' {
+            blocks.push((start, i + 1));
+            start = i + 2;
+            i += 2;
+            continue;
+        }
+        i += 1;
+    }
+    blocks.push((start, text.len()));
+    let mut res = Vec::new();
+    let mut cur_start = blocks[0].0;
+    let mut cur_end = blocks[0].1;
+    for b in blocks.into_iter().skip(1) {
+        if line_span(text, cur_start, b.1) < target {
+            cur_end = b.1;
+        } else {
+            res.push((cur_start, cur_end, preview(&text[cur_start..cur_end])));
+            cur_start = b.0;
+            cur_end = b.1;
+        }
+    }
+    res.push((cur_start, cur_end, preview(&text[cur_start..cur_end])));
+    if overlap > 0 && res.len() > 1 {
+        for i in 1..res.len() {
+            let (s, e, pr) = res[i].clone();
+            let new_s = back_n_lines(text, s, overlap);
+            res[i] = (new_s, e, pr);
+        }
+    }
+    res
+}
+
+fn back_n_lines(text: &str, mut off: usize, n: usize) -> usize {
+    let mut k = 0;
+    while off > 0 && k < n {
+        off -= 1;
+        if text.as_bytes()[off] == b'\n' {
+            k += 1;
+        }
+    }
+    off
+}
+
+fn line_span(text: &str, s: usize, e: usize) -> usize {
+    text[s..e].bytes().filter(|b| *b == b'\n').count() + 1
+}
+
+pub(crate) fn offset_to_line(text: &str, off: usize) -> usize {
+    text[..off].bytes().filter(|b| *b == b'\n').count() + 1
+}
+
+/// Format of the header embedded ahead of each chunk, recorded in the
+/// manifest. Bump it whenever [`chunk_header`] changes.
+pub(crate) const CHUNK_HEADER: &str = "path+lang+scope+section+doc/1";
+
+// Doc comments are cut to this many bytes in the header.
+const MAX_DOC_BYTES: usize = 300;
+
+/// Header embedded with a chunk but never stored in its preview: the file and
+/// language, the definitions enclosing the chunk, its Markdown/config section
+/// and the doc comment of its first definition.
+pub(crate) fn chunk_header(
+    path: &str,
+    lang: &str,
+    scope: &[String],
+    section: &str,
+    doc: &str,
+) -> String {
+    let mut header = format!("File: {path} ({lang})\n");
+    if !scope.is_empty() {
+        header.push_str(&format!("Scope: {}\n", scope.join(" > ")));
+    }
+    if !section.is_empty() {
+        header.push_str(&format!("Section: {section}\n"));
+    }
+    if !doc.is_empty() {
+        header.push_str(&format!("Doc: {doc}\n"));
+    }
+    header.push('\n');
+    header
+}
+
+/// `impl VectorStore`, `mod tests`, ... for the definitions that contain byte
+/// `start` without starting there, outermost first.
+pub(crate) fn enclosing_scope(defs: &[Definition], start: usize) -> Vec<String> {
+    defs.iter()
+        .filter(|d| d.start < start && start < d.end)
+        .filter_map(|d| d.symbol.as_ref())
+        .map(|s| format!("{} {}", s.kind, s.name))
+        .collect()
+}
+
+/// The comment block right above the line holding byte `start`, without
+/// comment markers; attributes and decorators in between are skipped.
+pub(crate) fn leading_doc(text: &str, start: usize) -> String {
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let mut lines = Vec::new();
+    for line in text[..line_start].lines().rev() {
+        let t = line.trim();
+        if t.starts_with("#[") || t.starts_with('@') {
+            continue;
+        }
+        let marker = ["///", "//!", "//", "/**", "/*", "*/", "*", "# ", "--"]
+            .into_iter()
+            .find(|m| t.starts_with(m) || (t == "#" && *m == "# "));
+        match marker {
+            Some(m) => lines.push(t.get(m.len()..).unwrap_or("").trim_end_matches("*/").trim()),
+            None => break,
+        }
+    }
+    let mut doc = lines
+        .into_iter()
+        .rev()
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if doc.len() > MAX_DOC_BYTES {
+        let mut end = MAX_DOC_BYTES;
+        while !doc.is_char_boundary(end) {
+            end -= 1;
+        }
+        doc.truncate(end);
+    }
+    doc
+}
+
+pub(crate) fn preview(chunk: &str) -> String {
+    let mut p = chunk.lines().take(8).collect::<Vec<_>>().join("\n");
+    if p.len() > 800 {
+        p.truncate(800);
+    }
+    p
+}
+
+fn chunk_definitions(
+    text: &str,
+    defs: &[Definition],
+    target: usize,
+    overlap: usize,
+) -> Option<Vec<(usize, usize, String)>> {
+    let mut ranges: Vec<(usize, usize)> = defs.iter().map(|d| (d.start, d.end)).collect();
+    if ranges.is_empty() {
+        return None;
+    }
+    ranges.sort_by_key(|r| r.0);
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    let mut cur = ranges[0];
+    for r in ranges.into_iter().skip(1) {
+        if line_span(text, cur.0, r.1) < target {
+            cur.1 = r.1;
+        } else {
+            merged.push(cur);
+            cur = r;
+        }
+    }
+    merged.push(cur);
+    let mut res = Vec::new();
+    for (s, e) in merged {
+        res.push((s, e, preview(&text[s..e])));
+    }
+    if overlap > 0 && res.len() > 1 {
+        for i in 1..res.len() {
+            let (s, e, pr) = res[i].clone();
+            let new_s = back_n_lines(text, s, overlap);
+            res[i] = (new_s, e, pr);
+        }
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_binary_png() {
+        let png: &[u8] = b"\x89PNG\r\n\x1a\nrest";
+        assert!(looks_binary(png));
+    }
+
+    #[test]
+    fn chunk_lines_overlap() {
+        let text = (1..=120)
+            .map(|i| {
+                format!(
+                    "line{}
+",
+                    i
+                )
+            })
+            .collect::<String>();
+        let chunks = chunk_lines(&text, 40, 10);
+        assert!(!chunks.is_empty());
+        // Ensure chunks overlap by ~10 lines
+        if chunks.len() > 1 {
+            let (s0, e0, _) = chunks[0].clone();
+            let (s1, _e1, _) = chunks[1].clone();
+            let l0_end = offset_to_line(&text, e0);
+            let l1_start = offset_to_line(&text, s1);
+            assert!(l1_start <= l0_end);
+        }
+    }
+
+    #[test]
+    fn language_detection_by_name_and_shebang() {
+        assert_eq!(language_for(Path::new("svc/main.go"), ""), "go");
+        assert_eq!(language_for(Path::new("web/App.tsx"), ""), "typescript");
+        assert_eq!(language_for(Path::new("Dockerfile"), ""), "dockerfile");
+        assert_eq!(language_for(Path::new("Dockerfile.ci"), ""), "dockerfile");
+        assert_eq!(language_for(Path::new("Makefile"), ""), "make");
+        assert_eq!(
+            language_for(Path::new("bin/tool"), "#!/usr/bin/env python3\nprint(1)\n"),
+            "python"
+        );
+        assert_eq!(language_for(Path::new("run"), "#!/bin/bash\n"), "shell");
+        assert_eq!(language_for(Path::new("notes"), "plain\n"), "text");
+    }
+
+    #[test]
+    fn treesitter_chunks_go_and_typescript() {
+        let go = "package main\n\nfunc a() {\n}\n\nfunc b() {\n}\n";
+        let defs = definitions(go, Path::new("m.go"), "go");
+        let chunks = chunk_auto(go, defs.as_deref(), 1, 0);
+        assert_eq!(chunks.len(), 2);
+        assert!(go[chunks[1].0..chunks[1].1].starts_with("func b"));
+
+        let ts = "export class A {\n  run(): void {}\n}\n\nfunction f() {}\n";
+        let defs = definitions(ts, Path::new("a.ts"), "typescript");
+        let chunks = chunk_auto(ts, defs.as_deref(), 1, 0);
+        assert!(
+            chunks
+                .iter()
+                .any(|c| ts[c.0..c.1].starts_with("function f"))
+        );
+    }
+
+    #[test]
+    fn header_names_scope_and_doc() {
+        let rs = "impl VectorStore {\n    /// Creates an empty store.\n    #[must_use]\n    fn new() -> Self {\n        VectorStore\n    }\n}\n";
+        let defs = definitions(rs, Path::new("v.rs"), "rust").expect("defs");
+        let start = rs.find("fn new").expect("fn new");
+        let scope = enclosing_scope(&defs, start);
+        assert_eq!(scope, ["impl VectorStore"]);
+        let doc = leading_doc(rs, start);
+        assert_eq!(doc, "Creates an empty store.");
+        assert_eq!(
+            chunk_header("src/v.rs", "rust", &scope, "", &doc),
+            "File: src/v.rs (rust)\nScope: impl VectorStore\nDoc: Creates an empty store.\n\n"
+        );
+        assert_eq!(leading_doc("x = 1\n\ndef f():\n", 7), "");
+    }
+
+    #[test]
+    fn definitions_record_symbols_with_containers() {
+        let rs = "struct VectorStore;\n\nimpl VectorStore {\n    fn new() -> Self {\n        VectorStore\n    }\n}\n";
+        let syms: Vec<Symbol> = definitions(rs, Path::new("v.rs"), "rust")
+            .expect("defs")
+            .into_iter()
+            .filter_map(|d| d.symbol)
+            .collect();
+        let new = syms.iter().find(|s| s.name == "new").expect("fn new");
+        assert_eq!(new.kind, "fn");
+        assert_eq!(new.container.as_deref(), Some("VectorStore"));
+        assert_eq!(new.line, 4);
+        assert!(
+            syms.iter()
+                .any(|s| s.name == "VectorStore" && s.kind == "struct")
+        );
+
+        let go = "package m\n\ntype Server struct{}\n\nfunc (s *Server) Run() {}\n";
+        let syms: Vec<Symbol> = definitions(go, Path::new("s.go"), "go")
+            .expect("defs")
+            .into_iter()
+            .filter_map(|d| d.symbol)
+            .collect();
+        assert!(
+            syms.iter()
+                .any(|s| s.name == "Server" && s.kind == "struct")
+        );
+        assert!(syms.iter().any(|s| s.name == "Run" && s.kind == "fn"));
+    }
+}
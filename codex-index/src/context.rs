@@ -0,0 +1,138 @@
+//! Retrieval context injected ahead of a chat turn: references to the chunks
+//! that best match the user's message, gated by confidence and trimmed to a
+//! token budget.
+
+use std::path::Path;
+use std::time::Instant;
+
+use crate::lexical::SearchMode;
+use crate::querylog::{self, QueryRecord, QuerySource};
+use crate::rerank::rerank_from_env;
+use crate::search::{
+    Hit, Retriever, SearchOptions, diversity_from_env, group_by_file, member_prefix, ms,
+    score_threshold,
+};
+use crate::store;
+use crate::workspace::Workspace;
+
+/// References block for the model plus a compact summary for the UI.
+#[derive(Debug, Clone)]
+pub struct RetrievalContext {
+    /// Instructions and one `- [member] @path:start-end[, start-end...] (lang)`
+    /// line per file for the model input (the member only when federated).
+    pub context: String,
+    /// `> {top}% -- {found} items found`, shown in the footer or as a notice.
+    pub summary: String,
+    /// Highest confidence among the candidates.
+    pub top: f32,
+    /// Candidates at or above the threshold.
+    pub found: usize,
+}
+
+const HEADER: &str = concat!(
+    "Local code references — read these first.\n\n",
+    "Instructions:\n",
+    "1) Treat the files below as the primary sources for answering. Read them carefully before any grep/other searches.\n",
+    "2) Only if these sources are insufficient, you may run additional searches.\n",
+    "3) Do not include low-confidence references (< threshold) in your reasoning.\n",
+    "4) Cite file paths and line ranges when you reference code.\n\n",
+    "References:\n",
+);
+
+fn token_budget() -> usize {
+    std::env::var("CODEX_INDEX_CONTEXT_TOKENS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(800)
+}
+
+// `CODEX_INDEX_FEDERATED=1` also searches the rest of the workspace.
+fn federated() -> bool {
+    std::env::var("CODEX_INDEX_FEDERATED")
+        .map(|v| matches!(v.as_str(), "1" | "true" | "on"))
+        .unwrap_or(false)
+}
+
+fn est_tokens(s: &str) -> usize {
+    s.len().div_ceil(4)
+}
+
+/// Search the index of `root` (or its whole workspace with
+/// `CODEX_INDEX_FEDERATED=1`) for `query` (up to `k` candidates, re-ranked
+/// with `CODEX_INDEX_RERANK`, diversified with `CODEX_INDEX_DIVERSITY`) and
+/// build the context block. None when there is
+/// no index or the top match is below `CODEX_INDEX_RETRIEVAL_THRESHOLD`
+/// (default 0.65). The query is logged as coming from `source`.
+pub fn retrieval_context(
+    root: &Path,
+    query: &str,
+    k: usize,
+    source: QuerySource,
+) -> Option<RetrievalContext> {
+    if query.trim().is_empty() {
+        return None;
+    }
+    let opts = SearchOptions {
+        k,
+        mode: SearchMode::from_env(),
+        rerank: rerank_from_env(),
+        diversity: diversity_from_env(),
+        ..SearchOptions::default()
+    };
+    let workspace = federated()
+        .then(|| Workspace::load(root).ok())
+        .flatten()
+        .filter(Workspace::is_federated);
+    let search = |opts: &SearchOptions| match &workspace {
+        Some(ws) => ws.search_timed(query, opts),
+        None => Retriever::shared(root).and_then(|r| r.search_timed(query, opts)),
+    };
+    // A reranker that cannot load (e.g. offline) falls back to plain ranking.
+    let (hits, timings) = match search(&opts) {
+        Err(_) if opts.rerank.is_some() => search(&SearchOptions {
+            rerank: None,
+            ..opts.clone()
+        }),
+        searched => searched,
+    }
+    .ok()?;
+    let threshold = score_threshold(0.65);
+    let top = hits.iter().map(|h| h.score).fold(0.0f32, f32::max);
+    let found = hits.iter().filter(|h| h.score >= threshold).count();
+    let _ = store::record_query(root, top >= threshold);
+    let mut record = QueryRecord::new(source, query, &hits, threshold, timings).automatic();
+    if top < threshold {
+        let _ = querylog::log_query(root, &record);
+        return None;
+    }
+
+    // File references only (no snippets), one line per file with all its
+    // ranges, best first, within the token budget.
+    let started = Instant::now();
+    let budget = token_budget();
+    let mut context = HEADER.to_string();
+    let confident: Vec<Hit> = hits.into_iter().filter(|h| h.score >= threshold).collect();
+    for file in group_by_file(&confident) {
+        let line = format!(
+            "- {}@{}:{} ({})\n",
+            member_prefix(file.repo.as_deref()),
+            file.path,
+            file.line_ranges(),
+            file.lang
+        );
+        if est_tokens(&context) + est_tokens(&line) > budget {
+            break;
+        }
+        context.push_str(&line);
+    }
+    let summary = format!("> {:.0}% -- {} items found", (top * 100.0).round(), found);
+    record.format_ms = ms(started);
+    record.injected = true;
+    let _ = querylog::log_query(root, &record);
+    Some(RetrievalContext {
+        context,
+        summary,
+        top,
+        found,
+    })
+}
@@ -0,0 +1,305 @@
+//! Embedding providers. The provider, model and endpoint used for a build are
+//! recorded in the manifest, and queries embed with the same ones, so query
+//! and chunk vectors always come from one model.
+
+use anyhow::{Context, Result, bail};
+use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+/// Where embeddings come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provider {
+    /// In-process ONNX models (bge-small, bge-large).
+    #[default]
+    Fastembed,
+    /// A local Ollama server (`/api/embeddings`).
+    Ollama,
+    /// Any server implementing the OpenAI `/embeddings` endpoint.
+    OpenaiCompatible,
+}
+
+impl Provider {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "fastembed" => Some(Self::Fastembed),
+            "ollama" => Some(Self::Ollama),
+            "openai-compatible" | "openai" => Some(Self::OpenaiCompatible),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Fastembed => "fastembed",
+            Self::Ollama => "ollama",
+            Self::OpenaiCompatible => "openai-compatible",
+        }
+    }
+
+    /// Model used when `index build` gets no `--model`.
+    pub fn default_model(self) -> &'static str {
+        match self {
+            Self::Fastembed => "bge-small",
+            Self::Ollama => "nomic-embed-text",
+            Self::OpenaiCompatible => "text-embedding-3-small",
+        }
+    }
+
+    /// Endpoint used when none is configured. Ollama follows the `oss` model
+    /// provider defaults (`CODEX_OSS_BASE_URL`, `CODEX_OSS_PORT`).
+    pub fn default_url(self) -> Option<String> {
+        match self {
+            Self::Fastembed => None,
+            Self::Ollama => Some(match std::env::var("CODEX_OSS_BASE_URL") {
+                Ok(url) if !url.trim().is_empty() => ollama_base(&url),
+                _ => {
+                    let port = std::env::var("CODEX_OSS_PORT")
+                        .ok()
+                        .and_then(|p| p.parse::<u16>().ok())
+                        .unwrap_or(11434);
+                    format!("http://localhost:{port}")
+                }
+            }),
+            Self::OpenaiCompatible => Some("https://api.openai.com/v1".into()),
+        }
+    }
+}
+
+/// Ollama server root from a provider base URL, which usually points at the
+/// OpenAI-compatible `/v1` API.
+pub fn ollama_base(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    url.strip_suffix("/v1").unwrap_or(url).to_string()
+}
+
+/// Turns texts into vectors (not normalized).
+pub trait Embedder: Send + Sync {
+    fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>>;
+}
+
+pub(crate) type SharedEmbedder = Arc<dyn Embedder>;
+
+type EmbedderKey = (Provider, String, Option<String>);
+
+fn embedders() -> Result<MutexGuard<'static, HashMap<EmbedderKey, SharedEmbedder>>> {
+    static EMBEDDERS: OnceLock<Mutex<HashMap<EmbedderKey, SharedEmbedder>>> = OnceLock::new();
+    EMBEDDERS
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|_| anyhow::anyhow!("embedder cache poisoned"))
+}
+
+/// Process-wide embedder for a provider/model/endpoint; local models are
+/// loaded once and shared by builds and queries.
+pub(crate) fn embedder(
+    provider: Provider,
+    model: &str,
+    url: Option<&str>,
+) -> Result<SharedEmbedder> {
+    let url = url.map(str::to_string).or_else(|| provider.default_url());
+    let key = (provider, model.to_string(), url.clone());
+    let mut cache = embedders()?;
+    if let Some(e) = cache.get(&key) {
+        return Ok(e.clone());
+    }
+    let e: SharedEmbedder = match provider {
+        Provider::Fastembed => Arc::new(Fastembed::new(model)?),
+        Provider::Ollama => Arc::new(Ollama {
+            url: url.unwrap_or_default(),
+            model: model.to_string(),
+            agent: http_agent(),
+        }),
+        Provider::OpenaiCompatible => {
+            let url = url.unwrap_or_default();
+            Arc::new(OpenAiCompatible {
+                api_key: api_key(&url),
+                url,
+                model: model.to_string(),
+                agent: http_agent(),
+            })
+        }
+    };
+    cache.insert(key, e.clone());
+    Ok(e)
+}
+
+// `CODEX_INDEX_EMBED_API_KEY` goes to any endpoint, `OPENAI_API_KEY` only to
+// OpenAI itself: the endpoint comes from the manifest, which may have been
+// built elsewhere.
+fn api_key(url: &str) -> Option<String> {
+    let var = |name| std::env::var(name).ok().filter(|k: &String| !k.is_empty());
+    var("CODEX_INDEX_EMBED_API_KEY").or_else(|| is_openai(url).then(|| var("OPENAI_API_KEY"))?)
+}
+
+fn is_openai(url: &str) -> bool {
+    let Some(rest) = url.trim().strip_prefix("https://") else {
+        return false;
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.strip_suffix(":443").unwrap_or(authority);
+    host.eq_ignore_ascii_case("api.openai.com")
+}
+
+fn http_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(120))
+        .build()
+}
+
+struct Fastembed(Mutex<TextEmbedding>);
+
+impl Fastembed {
+    fn new(model: &str) -> Result<Self> {
+        let model = match model {
+            "bge-small" | "bge-small-en-v1.5" => EmbeddingModel::BGESmallENV15,
+            "bge-large" | "bge-large-en-v1.5" => EmbeddingModel::BGELargeENV15,
+            other => bail!("unknown fastembed model `{other}` (expected bge-small or bge-large)"),
+        };
+        let e = TextEmbedding::try_new(InitOptions::new(model)).context("init fastembed")?;
+        Ok(Self(Mutex::new(e)))
+    }
+}
+
+impl Embedder for Fastembed {
+    fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let texts: Vec<String> = texts.iter().map(|t| t.to_string()).collect();
+        let batch = texts.len().max(1);
+        self.0
+            .lock()
+            .map_err(|_| anyhow::anyhow!("embedder lock poisoned"))?
+            .embed(texts, Some(batch))
+            .context("embed")
+    }
+}
+
+struct Ollama {
+    url: String,
+    model: String,
+    agent: ureq::Agent,
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbedding {
+    embedding: Vec<f32>,
+}
+
+impl Embedder for Ollama {
+    fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let endpoint = format!("{}/api/embeddings", self.url.trim_end_matches('/'));
+        texts
+            .iter()
+            .map(|text| {
+                let out: OllamaEmbedding = self
+                    .agent
+                    .post(&endpoint)
+                    .send_json(serde_json::json!({ "model": self.model, "prompt": text }))
+                    .with_context(|| format!("ollama embeddings at {endpoint}"))?
+                    .into_json()
+                    .context("parse ollama embeddings response")?;
+                Ok(out.embedding)
+            })
+            .collect()
+    }
+}
+
+struct OpenAiCompatible {
+    url: String,
+    model: String,
+    api_key: Option<String>,
+    agent: ureq::Agent,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbeddings {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl Embedder for OpenAiCompatible {
+    fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let endpoint = format!("{}/embeddings", self.url.trim_end_matches('/'));
+        let mut req = self.agent.post(&endpoint);
+        if let Some(key) = &self.api_key {
+            req = req.set("Authorization", &format!("Bearer {key}"));
+        }
+        let mut out: OpenAiEmbeddings = req
+            .send_json(serde_json::json!({ "model": self.model, "input": texts }))
+            .with_context(|| format!("embeddings at {endpoint}"))?
+            .into_json()
+            .context("parse embeddings response")?;
+        out.data.sort_by_key(|d| d.index);
+        Ok(out.data.into_iter().map(|d| d.embedding).collect())
+    }
+}
+
+/// Registers a deterministic in-process embedder for `model` under the
+/// Ollama provider and returns its (unreachable) endpoint, so tests can build
+/// an index without a model or server.
+#[cfg(test)]
+pub(crate) fn fake_embedder(model: &str) -> String {
+    struct Fake;
+    impl Embedder for Fake {
+        fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+            Ok(texts
+                .iter()
+                .map(|t| {
+                    let mut v = vec![0.0f32; 8];
+                    v[0] = 1.0;
+                    for word in t.split(|c: char| !c.is_alphanumeric()) {
+                        let h = word
+                            .bytes()
+                            .fold(0usize, |h, b| h.wrapping_mul(31) + b as usize);
+                        v[h % 8] += 1.0;
+                    }
+                    v
+                })
+                .collect())
+        }
+    }
+    let url = "http://fake-embedder.invalid".to_string();
+    let key = (Provider::Ollama, model.to_string(), Some(url.clone()));
+    embedders().expect("embedders").insert(key, Arc::new(Fake));
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_names_round_trip() {
+        for p in [
+            Provider::Fastembed,
+            Provider::Ollama,
+            Provider::OpenaiCompatible,
+        ] {
+            assert_eq!(Provider::parse(p.as_str()), Some(p));
+            let json = serde_json::to_string(&p).expect("serialize");
+            assert_eq!(json, format!("\"{}\"", p.as_str()));
+        }
+        assert_eq!(Provider::parse("bogus"), None);
+        assert_eq!(
+            ollama_base("http://localhost:11434/v1/"),
+            "http://localhost:11434"
+        );
+    }
+
+    #[test]
+    fn openai_key_only_goes_to_openai() {
+        assert!(is_openai("https://api.openai.com/v1"));
+        assert!(is_openai("https://API.openai.com:443/v1/"));
+        assert!(!is_openai("http://api.openai.com/v1"));
+        assert!(!is_openai("https://api.openai.com.evil.example/v1"));
+        assert!(!is_openai("https://evil.example/api.openai.com"));
+        assert!(!is_openai("https://user@api.openai.com/v1"));
+    }
+}
@@ -0,0 +1,388 @@
+//! Retrieval evaluation against a golden set: each JSONL line holds a query
+//! and the `path[:lines]` it should find. Queries run through the same search
+//! as `index query`; the report gives recall@k, MRR, the score distribution of
+//! relevant vs. irrelevant hits and the threshold that separates them best.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::search::{Hit, Retriever, SearchOptions};
+use crate::workspace::Workspace;
+
+/// One golden query. `expected` holds `path`, `path:N` or `path:A-B` targets
+/// (a single string or a list); a hit is relevant when it is in one of the
+/// files and overlaps the lines, if given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalCase {
+    pub query: String,
+    #[serde(deserialize_with = "one_or_many")]
+    pub expected: Vec<String>,
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(d)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+/// Parse a golden file (blank lines and `#` comments are skipped).
+pub fn load_golden(path: &Path) -> Result<Vec<EvalCase>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let mut cases = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let case: EvalCase = serde_json::from_str(line).with_context(|| {
+            format!(
+                "{}:{}: expected {{\"query\", \"expected\"}}",
+                path.display(),
+                n + 1
+            )
+        })?;
+        if case.expected.is_empty() {
+            bail!("{}:{}: no expected targets", path.display(), n + 1);
+        }
+        cases.push(case);
+    }
+    Ok(cases)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Target {
+    path: String,
+    lines: Option<(usize, usize)>,
+}
+
+impl Target {
+    fn parse(s: &str) -> Self {
+        let s = s.trim().trim_start_matches("./");
+        if let Some((path, lines)) = s.rsplit_once(':') {
+            let range = match lines.split_once('-') {
+                Some((a, b)) => a.parse().ok().zip(b.parse().ok()),
+                None => lines.parse().ok().map(|n| (n, n)),
+            };
+            if let Some(range) = range {
+                return Self {
+                    path: path.to_string(),
+                    lines: Some(range),
+                };
+            }
+        }
+        Self {
+            path: s.to_string(),
+            lines: None,
+        }
+    }
+
+    fn matches(&self, hit: &Hit) -> bool {
+        hit.path == self.path
+            && self
+                .lines
+                .is_none_or(|(a, b)| hit.start <= b && a <= hit.end)
+    }
+}
+
+/// Quartiles of a set of scores.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScoreStats {
+    pub count: usize,
+    pub min: f32,
+    pub p25: f32,
+    pub median: f32,
+    pub p75: f32,
+    pub max: f32,
+}
+
+impl ScoreStats {
+    fn of(mut scores: Vec<f32>) -> Self {
+        if scores.is_empty() {
+            return Self::default();
+        }
+        scores.sort_by(f32::total_cmp);
+        let at = |q: f32| scores[((scores.len() - 1) as f32 * q).round() as usize];
+        Self {
+            count: scores.len(),
+            min: scores[0],
+            p25: at(0.25),
+            median: at(0.5),
+            p75: at(0.75),
+            max: scores[scores.len() - 1],
+        }
+    }
+}
+
+/// Outcome of one golden query.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaseResult {
+    pub query: String,
+    pub expected: Vec<String>,
+    /// 1-based rank of the first relevant hit.
+    pub first_rank: Option<usize>,
+    /// Expected targets found in the top k.
+    pub found: usize,
+    pub top_score: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Aggregate metrics of [`evaluate`].
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    pub queries: usize,
+    pub k: usize,
+    /// `(n, recall@n)`: share of expected targets found in the top n, averaged over queries.
+    pub recall: Vec<(usize, f32)>,
+    /// Mean reciprocal rank of the first relevant hit.
+    pub mrr: f32,
+    /// Scores of relevant hits.
+    pub hits: ScoreStats,
+    /// Scores of irrelevant hits.
+    pub misses: ScoreStats,
+    /// Score threshold with the best F1 at telling relevant from irrelevant hits.
+    pub suggested_threshold: Option<f32>,
+    pub cases: Vec<CaseResult>,
+}
+
+/// Run `cases` against the index of `root` (and its workspace, unless `local`)
+/// with `opts`, as `index query` would.
+pub fn evaluate(
+    root: &Path,
+    cases: &[EvalCase],
+    opts: &SearchOptions,
+    local: bool,
+) -> Result<EvalReport> {
+    let workspace = if local {
+        None
+    } else {
+        Some(Workspace::load(root)?).filter(Workspace::is_federated)
+    };
+    if workspace.is_none() {
+        // Fail up front rather than once per query.
+        Retriever::shared(root)?;
+    }
+    Ok(score_cases(cases, opts.k, |query| {
+        let mut opts = opts.clone();
+        let query = opts.take_inline_filters(query);
+        match &workspace {
+            Some(ws) => ws.search(&query, &opts),
+            None => Retriever::shared(root).and_then(|r| r.search(&query, &opts)),
+        }
+    }))
+}
+
+fn score_cases(
+    cases: &[EvalCase],
+    k: usize,
+    mut search: impl FnMut(&str) -> Result<Vec<Hit>>,
+) -> EvalReport {
+    let mut cutoffs: Vec<usize> = [1, 3, 5, k].into_iter().filter(|n| *n <= k).collect();
+    cutoffs.dedup();
+    let mut recall_sums = vec![0f32; cutoffs.len()];
+    let mut rr_sum = 0f32;
+    let mut labeled: Vec<(f32, bool)> = Vec::new();
+    let mut results = Vec::with_capacity(cases.len());
+    for case in cases {
+        let targets: Vec<Target> = case.expected.iter().map(|t| Target::parse(t)).collect();
+        let (hits, error) = match search(&case.query) {
+            Ok(hits) => (hits, None),
+            Err(e) => (Vec::new(), Some(format!("{e:#}"))),
+        };
+        let hits = &hits[..hits.len().min(k)];
+        // Rank at which each target is first found.
+        let found_at: Vec<Option<usize>> = targets
+            .iter()
+            .map(|t| hits.iter().position(|h| t.matches(h)).map(|i| i + 1))
+            .collect();
+        for (sum, n) in recall_sums.iter_mut().zip(&cutoffs) {
+            let found = found_at
+                .iter()
+                .filter(|r| r.is_some_and(|r| r <= *n))
+                .count();
+            *sum += found as f32 / targets.len() as f32;
+        }
+        let first_rank = hits
+            .iter()
+            .position(|h| targets.iter().any(|t| t.matches(h)))
+            .map(|i| i + 1);
+        if let Some(rank) = first_rank {
+            rr_sum += 1.0 / rank as f32;
+        }
+        labeled.extend(
+            hits.iter()
+                .map(|h| (h.score, targets.iter().any(|t| t.matches(h)))),
+        );
+        results.push(CaseResult {
+            query: case.query.clone(),
+            expected: case.expected.clone(),
+            first_rank,
+            found: found_at.iter().filter(|r| r.is_some()).count(),
+            top_score: hits.first().map(|h| h.score),
+            error,
+        });
+    }
+    let n = cases.len().max(1) as f32;
+    EvalReport {
+        queries: cases.len(),
+        k,
+        recall: cutoffs
+            .into_iter()
+            .zip(recall_sums)
+            .map(|(c, s)| (c, s / n))
+            .collect(),
+        mrr: rr_sum / n,
+        hits: ScoreStats::of(labeled.iter().filter(|l| l.1).map(|l| l.0).collect()),
+        misses: ScoreStats::of(labeled.iter().filter(|l| !l.1).map(|l| l.0).collect()),
+        suggested_threshold: best_threshold(&labeled),
+        cases: results,
+    }
+}
+
+// Threshold (one of the observed scores) maximizing F1 of `score >= t` as a
+// relevance classifier; ties go to the higher threshold.
+fn best_threshold(labeled: &[(f32, bool)]) -> Option<f32> {
+    let relevant = labeled.iter().filter(|l| l.1).count();
+    if relevant == 0 {
+        return None;
+    }
+    let mut sorted = labeled.to_vec();
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let (mut tp, mut fp) = (0usize, 0usize);
+    let mut best: Option<(f32, f32)> = None;
+    for (i, &(score, rel)) in sorted.iter().enumerate() {
+        if rel {
+            tp += 1;
+        } else {
+            fp += 1;
+        }
+        // Only cut between distinct scores.
+        if sorted.get(i + 1).is_some_and(|next| next.0 == score) {
+            continue;
+        }
+        let f1 = 2.0 * tp as f32 / (2 * tp + fp + (relevant - tp)) as f32;
+        if best.is_none_or(|(_, b)| f1 > b) {
+            best = Some((score, f1));
+        }
+    }
+    best.map(|(t, _)| t)
+}
+
+impl std::fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Queries: {} (k={})", self.queries, self.k)?;
+        let recall: Vec<String> = self
+            .recall
+            .iter()
+            .map(|(n, r)| format!("@{n}={r:.3}"))
+            .collect();
+        writeln!(f, "Recall: {}", recall.join(" "))?;
+        writeln!(f, "MRR: {:.3}", self.mrr)?;
+        for (label, s) in [("Hit scores", &self.hits), ("Miss scores", &self.misses)] {
+            if s.count == 0 {
+                writeln!(f, "{label}: none")?;
+            } else {
+                writeln!(
+                    f,
+                    "{label}: n={} min={:.3} p25={:.3} median={:.3} p75={:.3} max={:.3}",
+                    s.count, s.min, s.p25, s.median, s.p75, s.max
+                )?;
+            }
+        }
+        match self.suggested_threshold {
+            Some(t) => writeln!(
+                f,
+                "Suggested threshold: {t:.3} (CODEX_INDEX_RETRIEVAL_THRESHOLD)"
+            )?,
+            None => writeln!(f, "Suggested threshold: n/a (no relevant hits)")?,
+        }
+        let missed: Vec<&CaseResult> = self
+            .cases
+            .iter()
+            .filter(|c| c.first_rank.is_none())
+            .collect();
+        if !missed.is_empty() {
+            writeln!(f, "Missed ({}):", missed.len())?;
+            for c in missed {
+                match &c.error {
+                    Some(e) => writeln!(f, "  {} -- error: {e}", c.query)?,
+                    None => writeln!(f, "  {} -- expected {}", c.query, c.expected.join(", "))?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(path: &str, start: usize, end: usize, score: f32) -> Hit {
+        Hit {
+            path: path.into(),
+            start,
+            end,
+            lang: "rust".into(),
+            score,
+            semantic: score,
+            lexical: 0.0,
+            preview: String::new(),
+            section: None,
+            rerank: None,
+            repo: None,
+        }
+    }
+
+    #[test]
+    fn golden_targets_accept_paths_and_line_ranges() {
+        let case: EvalCase =
+            serde_json::from_str(r#"{"query":"q","expected":"src/a.rs:10-20"}"#).expect("parse");
+        assert_eq!(case.expected, vec!["src/a.rs:10-20".to_string()]);
+        let t = Target::parse("./src/a.rs:10-20");
+        assert!(t.matches(&hit("src/a.rs", 18, 40, 0.5)));
+        assert!(!t.matches(&hit("src/a.rs", 21, 40, 0.5)));
+        assert_eq!(Target::parse("src/a.rs:7").lines, Some((7, 7)));
+        assert_eq!(Target::parse("C:foo.rs").lines, None);
+        assert!(Target::parse("src/b.rs").matches(&hit("src/b.rs", 1, 2, 0.1)));
+    }
+
+    #[test]
+    fn metrics_and_threshold() {
+        let cases = vec![
+            EvalCase {
+                query: "first".into(),
+                expected: vec!["a.rs".into()],
+            },
+            EvalCase {
+                query: "second".into(),
+                expected: vec!["b.rs".into(), "c.rs".into()],
+            },
+            EvalCase {
+                query: "missing".into(),
+                expected: vec!["z.rs".into()],
+            },
+        ];
+        let report = score_cases(&cases, 3, |q| {
+            Ok(match q {
+                "first" => vec![hit("a.rs", 1, 5, 0.9), hit("x.rs", 1, 5, 0.5)],
+                "second" => vec![hit("x.rs", 1, 5, 0.6), hit("b.rs", 1, 5, 0.8)],
+                _ => vec![hit("y.rs", 1, 5, 0.4)],
+            })
+        });
+        assert_eq!(report.recall, vec![(1, 1.0 / 3.0), (3, 0.5)]);
+        assert!((report.mrr - 0.5).abs() < 1e-6);
+        assert_eq!((report.hits.count, report.misses.count), (2, 3));
+        assert_eq!(report.suggested_threshold, Some(0.8));
+        assert_eq!(report.cases[1].first_rank, Some(2));
+        assert_eq!(report.cases[2].first_rank, None);
+    }
+}
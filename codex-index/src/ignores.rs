@@ -0,0 +1,270 @@
+//! Which files the index covers. `.index-ignore` files use gitignore syntax
+//! (`**`, `!negation`, `/anchored`, `dir/`) and may sit in any directory;
+//! they take precedence over `.gitignore` files and `.git/info/exclude`, and
+//! hidden paths are skipped unless a rule re-includes them. Within each kind
+//! the deepest file with a matching rule decides, as in git.
+
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::store::{self, IGNORE_FILE};
+
+/// An ignore-file rule, as reported by [`explain_ignore`].
+#[derive(Debug, Clone, Serialize)]
+pub struct IgnoreRule {
+    /// Ignore file, relative to the repo root.
+    pub file: String,
+    /// 1-based line of the rule in `file`.
+    pub line: usize,
+    pub pattern: String,
+}
+
+/// Whether a path is indexed, and why.
+#[derive(Debug, Clone, Serialize)]
+pub struct IgnoreVerdict {
+    pub path: String,
+    pub indexed: bool,
+    /// The rule that decided, when one matched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<IgnoreRule>,
+    /// Why no rule decided, e.g. `hidden path`; empty when one did.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+}
+
+impl std::fmt::Display for IgnoreVerdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = if self.indexed { "included" } else { "excluded" };
+        match &self.rule {
+            Some(r) => write!(
+                f,
+                "{}: {verb} by {}:{} `{}`",
+                self.path, r.file, r.line, r.pattern
+            ),
+            None => write!(f, "{}: {verb} ({})", self.path, self.reason),
+        }
+    }
+}
+
+/// Why `path` (absolute, or relative to `root`) is or is not indexed: the
+/// deciding rule with its file and line, or the default that applied.
+pub fn explain_ignore(root: &Path, path: &Path) -> IgnoreVerdict {
+    // Compare canonical forms so a root reached through a symlink still
+    // contains paths spelled through its real location, and vice versa.
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    PathFilter::new(&root).verdict(&canonical(&root.join(path)))
+}
+
+// `path` with its parent directories resolved. The last component is kept as
+// written, so a symlink is explained as itself, and so are missing ones.
+fn canonical(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => match parent.canonicalize() {
+            Ok(real) => real.join(name),
+            Err(_) => canonical(parent).join(name),
+        },
+        _ => path.to_path_buf(),
+    }
+}
+
+// An ignore file compiled for matching, with its lines for reporting.
+struct IgnoreFile {
+    rules: Gitignore,
+    lines: Vec<String>,
+}
+
+/// Decides whether a path belongs in the index, mirroring what a full scan
+/// picks up. Ignore files are read once per filter.
+pub(crate) struct PathFilter {
+    root: PathBuf,
+    files: Mutex<HashMap<PathBuf, Option<Arc<IgnoreFile>>>>,
+}
+
+impl PathFilter {
+    pub(crate) fn new(root: &Path) -> Self {
+        store::ensure_ignore_file(root);
+        Self {
+            root: root.to_path_buf(),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn allows(&self, path: &Path) -> bool {
+        self.verdict(path).indexed
+    }
+
+    pub(crate) fn verdict(&self, path: &Path) -> IgnoreVerdict {
+        let verdict = |indexed: bool, rule: Option<IgnoreRule>, reason: &str| IgnoreVerdict {
+            path: store::rel(&self.root, path),
+            indexed,
+            rule,
+            reason: reason.to_string(),
+        };
+        let Ok(relp) = path.strip_prefix(&self.root) else {
+            return verdict(false, None, "outside the repository");
+        };
+        if relp.as_os_str().is_empty() {
+            return verdict(false, None, "repository root");
+        }
+        let is_dir = path.is_dir();
+        let decided = self
+            .deepest(IGNORE_FILE, path, is_dir)
+            .or_else(|| self.deepest(".gitignore", path, is_dir))
+            .or_else(|| {
+                let exclude = self.root.join(".git/info/exclude");
+                self.matched(&exclude, &self.root, path, is_dir)
+            });
+        match decided {
+            Some((rule, whitelist)) => verdict(whitelist, Some(rule), ""),
+            None if relp
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.')) =>
+            {
+                verdict(false, None, "hidden path")
+            }
+            None => verdict(true, None, "no rule matches"),
+        }
+    }
+
+    // The rule of the deepest `name` file between `path` and the root that
+    // matches `path` or one of its parents; true for a `!` rule.
+    fn deepest(&self, name: &str, path: &Path, is_dir: bool) -> Option<(IgnoreRule, bool)> {
+        path.ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root))
+            .find_map(|dir| self.matched(&dir.join(name), dir, path, is_dir))
+    }
+
+    fn matched(
+        &self,
+        file: &Path,
+        dir: &Path,
+        path: &Path,
+        is_dir: bool,
+    ) -> Option<(IgnoreRule, bool)> {
+        let ignore = self.load(file, dir)?;
+        let glob = match ignore.rules.matched_path_or_any_parents(path, is_dir) {
+            Match::None => return None,
+            Match::Ignore(glob) | Match::Whitelist(glob) => glob,
+        };
+        // The last rule with this text is the one that matched.
+        let line = ignore
+            .lines
+            .iter()
+            .rposition(|l| l.trim_end() == glob.original())
+            .map_or(0, |i| i + 1);
+        let rule = IgnoreRule {
+            file: store::rel(&self.root, file),
+            line,
+            pattern: glob.original().to_string(),
+        };
+        Some((rule, glob.is_whitelist()))
+    }
+
+    fn load(&self, file: &Path, dir: &Path) -> Option<Arc<IgnoreFile>> {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files
+            .entry(file.to_path_buf())
+            .or_insert_with(|| {
+                let text = std::fs::read_to_string(file).ok()?;
+                let mut builder = GitignoreBuilder::new(dir);
+                for line in text.lines() {
+                    let _ = builder.add_line(Some(file.to_path_buf()), line);
+                }
+                Some(Arc::new(IgnoreFile {
+                    rules: builder.build().ok()?,
+                    lines: text.lines().map(str::to_string).collect(),
+                }))
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn path_filter_honors_nested_gitignore_and_index_ignore() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/gen")).expect("mkdir");
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").expect("write");
+        fs::write(root.join("src/.gitignore"), "gen/\n!keep.log\n").expect("write");
+        fs::write(root.join(store::IGNORE_FILE), "**/*.snap\n").expect("write");
+        let filter = PathFilter::new(root);
+        assert!(filter.allows(&root.join("src/lib.rs")));
+        assert!(filter.allows(&root.join("src/keep.log")));
+        assert!(!filter.allows(&root.join("target/debug/x.rs")));
+        assert!(!filter.allows(&root.join("app.log")));
+        assert!(!filter.allows(&root.join("src/gen/out.rs")));
+        assert!(!filter.allows(&root.join("src/a.snap")));
+        assert!(!filter.allows(&root.join(".codex/index/manifest.json")));
+        assert!(!filter.allows(Path::new("/elsewhere/lib.rs")));
+    }
+
+    #[test]
+    fn index_ignore_uses_gitignore_syntax_and_explains_rules() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("vendor/lib")).expect("mkdir");
+        fs::create_dir_all(root.join("docs/api")).expect("mkdir");
+        fs::write(
+            root.join(store::IGNORE_FILE),
+            "# generated\nvendor/**\n!vendor/lib/keep.rs\n/docs/*.md\n",
+        )
+        .expect("write");
+        fs::write(root.join("docs/.index-ignore"), "api/\n").expect("write");
+        fs::write(root.join(".gitignore"), "*.md\n").expect("write");
+        let filter = PathFilter::new(root);
+        assert!(!filter.allows(&root.join("vendor/lib/x.rs")));
+        assert!(filter.allows(&root.join("vendor/lib/keep.rs")));
+        assert!(!filter.allows(&root.join("docs/guide.md")));
+        assert!(!filter.allows(&root.join("docs/api/index.rs")));
+        assert!(filter.allows(&root.join("docs/api.rs")));
+
+        let shown = |p: &str| explain_ignore(root, Path::new(p)).to_string();
+        assert_eq!(
+            shown("vendor/lib/keep.rs"),
+            "vendor/lib/keep.rs: included by .index-ignore:3 `!vendor/lib/keep.rs`"
+        );
+        assert_eq!(
+            shown("docs/api/index.rs"),
+            "docs/api/index.rs: excluded by docs/.index-ignore:1 `api/`"
+        );
+        assert_eq!(
+            shown("docs/guide.md"),
+            "docs/guide.md: excluded by .index-ignore:4 `/docs/*.md`"
+        );
+        // `/docs/*.md` is anchored, so deeper Markdown falls through to .gitignore.
+        assert_eq!(
+            shown("docs/howto/notes.md"),
+            "docs/howto/notes.md: excluded by .gitignore:1 `*.md`"
+        );
+        assert_eq!(
+            shown("src/lib.rs"),
+            "src/lib.rs: included (no rule matches)"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn explain_resolves_a_root_behind_a_symlink() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let real = dir.path().join("real");
+        fs::create_dir_all(real.join("src")).expect("mkdir");
+        fs::write(real.join(".gitignore"), "*.log\n").expect("write");
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).expect("symlink");
+        // The path names the real location while the root goes through the link.
+        let verdict = explain_ignore(&link, &real.join("src/app.log"));
+        assert_eq!(verdict.path, "src/app.log");
+        assert!(!verdict.indexed);
+        assert!(explain_ignore(&link, Path::new("src/lib.rs")).indexed);
+    }
+}
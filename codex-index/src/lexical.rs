@@ -0,0 +1,379 @@
+//! BM25 keyword index stored next to the vector store, plus the rank fusion
+//! used by `index query` and chat retrieval.
+//!
+//! Document positions are row positions in `vectors.hnsw` (and HNSW data ids),
+//! so lexical and vector rankings refer to the same chunks.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const LEXICAL_FILE: &str = "lexical.json";
+const LEXICAL_VERSION: u32 = 1;
+
+// BM25 parameters (standard defaults).
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+/// Reciprocal rank fusion damping constant.
+const RRF_K: f32 = 60.0;
+
+/// Which rankings a search combines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    Semantic,
+    Lexical,
+    #[default]
+    Hybrid,
+}
+
+impl SearchMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "semantic" => Some(Self::Semantic),
+            "lexical" => Some(Self::Lexical),
+            "hybrid" => Some(Self::Hybrid),
+            _ => None,
+        }
+    }
+
+    /// Mode from `CODEX_INDEX_SEARCH_MODE`, defaulting to hybrid.
+    pub fn from_env() -> Self {
+        std::env::var("CODEX_INDEX_SEARCH_MODE")
+            .ok()
+            .and_then(|s| Self::parse(&s))
+            .unwrap_or_default()
+    }
+}
+
+/// Split text into lowercase terms. Identifiers are kept whole and also split
+/// on `_` and camelCase boundaries, so `spawnPeriodic` matches `spawn`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    for word in text.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+        let word = word.trim_matches('_');
+        if word.len() < 2 {
+            continue;
+        }
+        let parts = split_identifier(word);
+        out.push(word.to_lowercase());
+        if parts.len() > 1 {
+            out.extend(parts.into_iter().filter(|p| p.len() >= 2));
+        }
+    }
+    out
+}
+
+fn split_identifier(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    for piece in word.split('_').filter(|p| !p.is_empty()) {
+        let chars: Vec<char> = piece.chars().collect();
+        let mut cur = String::new();
+        for (i, &c) in chars.iter().enumerate() {
+            let boundary = i > 0
+                && c.is_uppercase()
+                && (chars[i - 1].is_lowercase()
+                    || chars.get(i + 1).is_some_and(|n| n.is_lowercase()));
+            if boundary && !cur.is_empty() {
+                parts.push(std::mem::take(&mut cur).to_lowercase());
+            }
+            cur.push(c);
+        }
+        if !cur.is_empty() {
+            parts.push(cur.to_lowercase());
+        }
+    }
+    parts
+}
+
+/// Term frequencies for one chunk, sorted by term.
+pub fn term_counts(text: &str) -> Vec<(String, u32)> {
+    let mut counts: HashMap<String, u32> = HashMap::new();
+    for t in tokenize(text) {
+        *counts.entry(t).or_insert(0) += 1;
+    }
+    let mut v: Vec<(String, u32)> = counts.into_iter().collect();
+    v.sort();
+    v
+}
+
+/// Inverted index: term -> postings of (row position, term frequency).
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct LexicalIndex {
+    pub version: u32,
+    pub doc_len: Vec<u32>,
+    pub terms: HashMap<String, Vec<(u32, u32)>>,
+}
+
+/// A BM25 match. `coverage` is the idf-weighted share of query terms found in
+/// the chunk, scaled down when the matched terms are all common words.
+#[derive(Debug, Clone, Copy)]
+pub struct LexicalHit {
+    pub pos: usize,
+    pub score: f32,
+    pub coverage: f32,
+}
+
+impl LexicalIndex {
+    /// Build from per-chunk term counts, in row order.
+    pub fn from_docs(docs: &[Vec<(String, u32)>]) -> Self {
+        let mut terms: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+        let mut doc_len = Vec::with_capacity(docs.len());
+        for (pos, doc) in docs.iter().enumerate() {
+            doc_len.push(doc.iter().map(|(_, tf)| *tf).sum());
+            for (term, tf) in doc {
+                terms
+                    .entry(term.clone())
+                    .or_default()
+                    .push((pos as u32, *tf));
+            }
+        }
+        Self {
+            version: LEXICAL_VERSION,
+            doc_len,
+            terms,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let idx: Self = serde_json::from_slice(
+            &std::fs::read(path).with_context(|| format!("read {}", path.display()))?,
+        )
+        .context("parse lexical index")?;
+        if idx.version != LEXICAL_VERSION {
+            anyhow::bail!("unsupported lexical index version {}", idx.version);
+        }
+        Ok(idx)
+    }
+
+    /// Write atomically (temp file + rename).
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?).context("write lexical index")?;
+        std::fs::rename(&tmp, path).context("rename lexical index")?;
+        Ok(())
+    }
+
+    pub fn docs(&self) -> usize {
+        self.doc_len.len()
+    }
+
+    /// Per-row term counts (the forward index), for carrying rows across builds.
+    pub fn doc_terms(&self) -> Vec<Vec<(String, u32)>> {
+        let mut docs: Vec<Vec<(String, u32)>> = vec![Vec::new(); self.docs()];
+        for (term, postings) in &self.terms {
+            for &(pos, tf) in postings {
+                if let Some(d) = docs.get_mut(pos as usize) {
+                    d.push((term.clone(), tf));
+                }
+            }
+        }
+        for d in &mut docs {
+            d.sort();
+        }
+        docs
+    }
+
+    fn idf(&self, df: usize) -> f32 {
+        let n = self.docs() as f32;
+        let df = df as f32;
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+
+    /// Top `k` rows by BM25 for `query`.
+    pub fn search(&self, query: &str, k: usize) -> Vec<LexicalHit> {
+        self.search_filtered(query, k, |_| true)
+    }
+
+    /// Like [`search`](Self::search), but only documents for which `keep` returns
+    /// true are ranked, so the top `k` is filled from the allowed set.
+    pub fn search_filtered(
+        &self,
+        query: &str,
+        k: usize,
+        keep: impl Fn(usize) -> bool,
+    ) -> Vec<LexicalHit> {
+        let mut qterms = tokenize(query);
+        qterms.sort();
+        qterms.dedup();
+        if qterms.is_empty() || self.docs() == 0 {
+            return Vec::new();
+        }
+        let avg_len =
+            (self.doc_len.iter().map(|l| *l as f32).sum::<f32>() / self.docs() as f32).max(1.0);
+        // A term that occurs nowhere is as specific as a term can be.
+        let max_idf = self.idf(0);
+        let total_idf: f32 = qterms
+            .iter()
+            .map(|t| self.terms.get(t).map_or(max_idf, |p| self.idf(p.len())))
+            .sum();
+
+        // pos -> (bm25, matched idf)
+        let mut acc: HashMap<usize, (f32, f32)> = HashMap::new();
+        for t in &qterms {
+            let Some(postings) = self.terms.get(t) else {
+                continue;
+            };
+            let idf = self.idf(postings.len());
+            for &(pos, tf) in postings {
+                let tf = tf as f32;
+                let len = self.doc_len[pos as usize] as f32;
+                let s = idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg_len));
+                let e = acc.entry(pos as usize).or_insert((0.0, 0.0));
+                e.0 += s;
+                e.1 += idf;
+            }
+        }
+        let mut hits: Vec<LexicalHit> = acc
+            .into_iter()
+            .filter(|(pos, _)| keep(*pos))
+            .map(|(pos, (score, matched))| {
+                let share = if total_idf > 0.0 {
+                    matched / total_idf
+                } else {
+                    0.0
+                };
+                let specificity = if max_idf > 0.0 {
+                    (matched / max_idf).min(1.0)
+                } else {
+                    0.0
+                };
+                LexicalHit {
+                    pos,
+                    score,
+                    coverage: share * specificity,
+                }
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.pos.cmp(&b.pos))
+        });
+        hits.truncate(k);
+        hits
+    }
+}
+
+/// A search result after fusion. `score` is the confidence used for threshold
+/// gating: cosine similarity, lexical coverage, or the larger of the two in
+/// hybrid mode.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoredHit {
+    pub pos: usize,
+    pub score: f32,
+    pub semantic: f32,
+    pub lexical: f32,
+}
+
+/// Combine semantic candidates (row position, cosine; best first) and lexical
+/// hits into the final top `k`. Hybrid mode orders by reciprocal rank fusion.
+/// `cosine_of` provides the exact similarity for rows only found lexically.
+pub fn fuse(
+    mode: SearchMode,
+    semantic: &[(usize, f32)],
+    lexical: &[LexicalHit],
+    k: usize,
+    cosine_of: impl Fn(usize) -> f32,
+) -> Vec<ScoredHit> {
+    let coverage: HashMap<usize, f32> = lexical.iter().map(|h| (h.pos, h.coverage)).collect();
+    let cosine: HashMap<usize, f32> = semantic.iter().copied().collect();
+    let hit = |pos: usize, rank_score: f32| {
+        let sem = cosine.get(&pos).copied().unwrap_or_else(|| cosine_of(pos));
+        let lex = coverage.get(&pos).copied().unwrap_or(0.0);
+        let score = match mode {
+            SearchMode::Semantic => sem,
+            SearchMode::Lexical => lex,
+            SearchMode::Hybrid => sem.max(lex),
+        };
+        (
+            rank_score,
+            ScoredHit {
+                pos,
+                score,
+                semantic: sem,
+                lexical: lex,
+            },
+        )
+    };
+    let mut ranked: Vec<(f32, ScoredHit)> = match mode {
+        SearchMode::Semantic => semantic.iter().map(|&(p, s)| hit(p, s)).collect(),
+        SearchMode::Lexical => lexical.iter().map(|h| hit(h.pos, h.score)).collect(),
+        SearchMode::Hybrid => {
+            let mut fused: HashMap<usize, f32> = HashMap::new();
+            for (rank, &(pos, _)) in semantic.iter().enumerate() {
+                *fused.entry(pos).or_insert(0.0) += 1.0 / (RRF_K + rank as f32 + 1.0);
+            }
+            for (rank, h) in lexical.iter().enumerate() {
+                *fused.entry(h.pos).or_insert(0.0) += 1.0 / (RRF_K + rank as f32 + 1.0);
+            }
+            fused.into_iter().map(|(p, f)| hit(p, f)).collect()
+        }
+    };
+    ranked.sort_by(|a, b| {
+        b.0.partial_cmp(&a.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.1.pos.cmp(&b.1.pos))
+    });
+    ranked.into_iter().take(k).map(|(_, h)| h).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_splits_identifiers() {
+        let t = tokenize("fn spawn_periodic_maintenance() { loadVectorsMmap(x) }");
+        for want in [
+            "fn",
+            "spawn_periodic_maintenance",
+            "spawn",
+            "periodic",
+            "maintenance",
+            "loadvectorsmmap",
+            "load",
+            "vectors",
+            "mmap",
+        ] {
+            assert!(t.iter().any(|x| x == want), "missing {want} in {t:?}");
+        }
+        assert!(!t.iter().any(|x| x == "x"));
+    }
+
+    #[test]
+    fn exact_identifier_ranks_first_with_full_coverage() {
+        let docs = [
+            "fn build() { let x = 1; }",
+            "pub fn spawn_periodic_maintenance() { loop { sleep(); } }",
+            "// maintenance notes for the periodic job",
+        ]
+        .iter()
+        .map(|d| term_counts(d))
+        .collect::<Vec<_>>();
+        let idx = LexicalIndex::from_docs(&docs);
+        let hits = idx.search("spawn_periodic_maintenance", 3);
+        assert_eq!(hits[0].pos, 1);
+        assert!(hits[0].coverage > hits.get(1).map_or(0.0, |h| h.coverage));
+        assert_eq!(idx.doc_terms()[1], docs[1]);
+    }
+
+    #[test]
+    fn hybrid_fusion_keeps_lexical_only_hits() {
+        let semantic = vec![(0, 0.55), (2, 0.50)];
+        let lexical = vec![LexicalHit {
+            pos: 1,
+            score: 7.0,
+            coverage: 0.9,
+        }];
+        let hits = fuse(SearchMode::Hybrid, &semantic, &lexical, 3, |_| 0.3);
+        assert_eq!(hits.len(), 3);
+        let lex = hits.iter().find(|h| h.pos == 1).expect("lexical hit");
+        assert_eq!(lex.semantic, 0.3);
+        assert_eq!(lex.score, 0.9);
+        let sem_only = fuse(SearchMode::Semantic, &semantic, &lexical, 3, |_| 0.0);
+        assert_eq!(sem_only.iter().map(|h| h.pos).collect::<Vec<_>>(), [0, 2]);
+    }
+}
@@ -0,0 +1,75 @@
+//! Local code index shared by the `codex-agentic` CLI, the ACP agent and the TUI.
+//!
+//! The index lives in `<repo>/.codex/index`. [`Builder`] creates or
+//! incrementally refreshes it; [`Retriever`] answers filtered hybrid
+//! (HNSW + BM25) queries in-process, keeping the embedding model and graph
+//! loaded between calls; [`spawn_watcher`] keeps it current as files change;
+//! `serve` answers queries for short-lived processes over a Unix socket;
+//! [`Status`] summarizes what is on disk.
+#![allow(
+    clippy::collapsible_if,
+    clippy::collapsible_else_if,
+    clippy::needless_range_loop
+)]
+
+mod build;
+mod chunk;
+mod context;
+pub mod embed;
+mod eval;
+mod history;
+mod ignores;
+pub mod lexical;
+mod progress;
+mod querylog;
+mod rerank;
+mod search;
+mod secrets;
+mod sections;
+#[cfg(unix)]
+mod serve;
+mod similar;
+mod snapshot;
+mod store;
+pub mod symbols;
+mod vectors;
+mod verify;
+mod watch;
+mod workspace;
+
+pub use build::{BuildOptions, BuildReport, Builder, index_command};
+pub use context::{RetrievalContext, retrieval_context};
+pub use embed::{Embedder, Provider};
+pub use eval::{CaseResult, EvalCase, EvalReport, ScoreStats, evaluate, load_golden};
+pub use history::{
+    CommitHit, DEFAULT_MAX_COMMITS, HistoryManifest, HistoryOptions, HistoryReport, build_history,
+    search_history, search_history_timed,
+};
+pub use ignores::{IgnoreRule, IgnoreVerdict, explain_ignore};
+pub use lexical::SearchMode;
+pub use progress::{BuildPhase, BuildProgress, ProgressFn, format_progress_line};
+pub use querylog::{QueryRecord, QuerySource, QueryStats, log_query, parse_since, query_stats};
+pub use rerank::{DEFAULT_RERANKER, rerank_from_env};
+pub use search::{
+    FileHits, Hit, Index, NO_MATCH, Retriever, SearchOptions, TextFormat, Timings,
+    diversity_from_env, format_files_text, format_hits_text, group_by_file, score_threshold,
+    search_command,
+};
+#[cfg(unix)]
+pub use serve::{Client, Request, Response, serve, socket_path};
+pub use similar::{
+    DEFAULT_DUPE_SCORE, DupeGroup, DupesOptions, Similar, format_dupes_text, format_similar_text,
+    parse_location, similar_command,
+};
+pub use snapshot::{ImportReport, SnapshotInfo, export, import};
+pub use store::{
+    Analytics, Checksums, ChunkCfg, Counts, DEFAULT_IGNORE, DEFAULT_MAX_FILE_SIZE, INDEX_DIR,
+    Limits, Manifest, RepoInfo, Status, clean, ignore_file, index_dir, indexing,
+    load_ignore_patterns, parse_size, read_analytics, record_attempt, record_query, relative_age,
+    repo_root, reset_ignore_patterns, save_ignore_patterns, verify,
+};
+pub use symbols::{Symbol, SymbolMatch, find_symbols};
+pub use vectors::Storage;
+pub use verify::{Issue, IssueKind, RepairReport, VerifyReport, repair, verify_deep};
+pub use watch::{spawn_watcher, watch_debounce};
+pub use workspace::{Member, MemberProgressFn, WORKSPACE_FILE, Workspace};
@@ -0,0 +1,191 @@
+//! Build progress: snapshots reported while a [`Builder`] runs, rendered as
+//! a terminal progress bar or emitted as JSON lines (`--progress json`). The
+//! build lock also tracks the files left so [`indexing`] reflects progress of
+//! background builds.
+//!
+//! [`Builder`]: crate::Builder
+//! [`indexing`]: crate::indexing
+
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::store::BuildLock;
+
+/// Callback receiving each [`BuildProgress`] of a build.
+pub type ProgressFn = Arc<dyn Fn(&BuildProgress) + Send + Sync>;
+
+/// Stage of a build.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildPhase {
+    /// Walking the repository for files to read.
+    #[default]
+    Scan,
+    /// Reading, chunking and embedding files.
+    Embed,
+    /// Writing vectors, metadata, postings and the graph.
+    Write,
+    /// Finished.
+    Done,
+}
+
+impl BuildPhase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Scan => "scan",
+            Self::Embed => "embed",
+            Self::Write => "write",
+            Self::Done => "done",
+        }
+    }
+}
+
+/// Snapshot of a running build: sent when the scan starts and ends, after
+/// each window of files, before writing and when done.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuildProgress {
+    pub phase: BuildPhase,
+    /// Files the scan found to read (all of them, or only the changed ones
+    /// of an update).
+    pub files_scanned: usize,
+    /// Of those, files read, chunked and embedded so far.
+    pub files_embedded: usize,
+    /// Chunks in the index so far, reused or embedded.
+    pub chunks: usize,
+    /// Chunks embedded so far (the rest reused stored vectors).
+    pub chunks_embedded: usize,
+    pub elapsed_ms: u64,
+    /// Estimated time until all scanned files are embedded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_ms: Option<u64>,
+}
+
+impl BuildProgress {
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.elapsed_ms)
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        self.eta_ms.map(Duration::from_millis)
+    }
+}
+
+/// One-line rendering for a terminal, e.g.
+/// `embed [#######.............] 120/400 files, 1530 chunks (812 embedded), 12s, ETA 28s`.
+pub fn format_progress_line(p: &BuildProgress) -> String {
+    const WIDTH: usize = 20;
+    let filled = match p.phase {
+        BuildPhase::Write | BuildPhase::Done => WIDTH,
+        _ if p.files_scanned == 0 => 0,
+        _ => WIDTH * p.files_embedded / p.files_scanned,
+    };
+    let mut line = format!(
+        "{:<5} [{}{}] {}/{} files, {} chunks ({} embedded), {}",
+        p.phase.as_str(),
+        "#".repeat(filled),
+        ".".repeat(WIDTH - filled),
+        p.files_embedded,
+        p.files_scanned,
+        p.chunks,
+        p.chunks_embedded,
+        format_secs(p.elapsed()),
+    );
+    if let Some(eta) = p.eta() {
+        line.push_str(&format!(", ETA {}", format_secs(eta)));
+    }
+    line
+}
+
+// `42s`, `3m05s` or `1h02m`.
+fn format_secs(d: Duration) -> String {
+    let s = d.as_secs();
+    match s {
+        0..60 => format!("{s}s"),
+        60..3600 => format!("{}m{:02}s", s / 60, s % 60),
+        _ => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+    }
+}
+
+/// Tracks the counters of one build and hands snapshots to the callback and
+/// the build lock.
+pub(crate) struct Reporter<'a> {
+    callback: Option<&'a ProgressFn>,
+    lock: Option<&'a BuildLock>,
+    started: Instant,
+    // When embedding started, for the ETA.
+    embedding: Option<Instant>,
+    pub(crate) progress: BuildProgress,
+}
+
+impl<'a> Reporter<'a> {
+    pub(crate) fn new(callback: Option<&'a ProgressFn>, lock: Option<&'a BuildLock>) -> Self {
+        Self {
+            callback,
+            lock,
+            started: Instant::now(),
+            embedding: None,
+            progress: BuildProgress::default(),
+        }
+    }
+
+    /// Send the current counters as `phase`.
+    pub(crate) fn emit(&mut self, phase: BuildPhase) {
+        let p = &mut self.progress;
+        p.phase = phase;
+        p.elapsed_ms = self.started.elapsed().as_millis() as u64;
+        p.eta_ms = None;
+        if phase == BuildPhase::Embed {
+            let since = *self.embedding.get_or_insert_with(Instant::now);
+            let left = p.files_scanned.saturating_sub(p.files_embedded);
+            if p.files_embedded > 0 && left > 0 {
+                let per_file = since.elapsed().as_millis() as f64 / p.files_embedded as f64;
+                p.eta_ms = Some((per_file * left as f64) as u64);
+            }
+            if let Some(lock) = self.lock {
+                lock.set_pending(left);
+            }
+        }
+        if let Some(callback) = self.callback {
+            callback(p);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn reporter_estimates_remaining_time() {
+        let seen: Arc<Mutex<Vec<BuildProgress>>> = Arc::default();
+        let sink = seen.clone();
+        let callback: ProgressFn = Arc::new(move |p| sink.lock().unwrap().push(p.clone()));
+        let mut reporter = Reporter::new(Some(&callback), None);
+        reporter.emit(BuildPhase::Scan);
+        reporter.progress.files_scanned = 40;
+        reporter.emit(BuildPhase::Embed);
+        std::thread::sleep(Duration::from_millis(20));
+        reporter.progress.files_embedded = 10;
+        reporter.emit(BuildPhase::Embed);
+        reporter.progress.files_embedded = 40;
+        reporter.emit(BuildPhase::Done);
+
+        let seen = seen.lock().unwrap();
+        let phases: Vec<_> = seen.iter().map(|p| p.phase).collect();
+        use BuildPhase::*;
+        assert_eq!(phases, [Scan, Embed, Embed, Done]);
+        assert_eq!(seen[1].eta_ms, None);
+        // 10 files took ~20ms, so the other 30 should take ~60ms.
+        assert!(seen[2].eta_ms.is_some_and(|eta| eta >= 50));
+        assert_eq!(seen[3].eta_ms, None);
+
+        let line = format_progress_line(&seen[2]);
+        assert!(
+            line.starts_with("embed [#####...............] 10/40 files"),
+            "{line}"
+        );
+        assert_eq!(format_secs(Duration::from_secs(185)), "3m05s");
+    }
+}
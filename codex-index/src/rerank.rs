@@ -0,0 +1,94 @@
+//! Optional cross-encoder re-ranking: a local fastembed reranker scores each
+//! (query, chunk) pair jointly, which separates near-misses that embed close
+//! to the query better than cosine similarity does.
+
+use anyhow::{Context, Result, bail};
+use fastembed::{RerankInitOptions, RerankerModel, TextRerank};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Model used by `--rerank` without a value and `CODEX_INDEX_RERANK=1`.
+pub const DEFAULT_RERANKER: &str = "bge-reranker-base";
+
+/// Candidates re-ranked per requested result (at least [`MIN_CANDIDATES`]).
+pub(crate) const DEPTH: usize = 4;
+pub(crate) const MIN_CANDIDATES: usize = 20;
+
+/// Reranker for chat retrieval and `/search` from `CODEX_INDEX_RERANK`: a
+/// model name, or `1`/`true`/`on` for [`DEFAULT_RERANKER`]. Unset, empty or
+/// `0`/`false`/`off` disables re-ranking.
+pub fn rerank_from_env() -> Option<String> {
+    let v = std::env::var("CODEX_INDEX_RERANK").ok()?;
+    match v.trim() {
+        "" | "0" | "false" | "off" => None,
+        "1" | "true" | "on" => Some(DEFAULT_RERANKER.to_string()),
+        model => Some(model.to_string()),
+    }
+}
+
+fn reranker_model(name: &str) -> Result<RerankerModel> {
+    Ok(match name {
+        "bge-reranker-base" => RerankerModel::BGERerankerBase,
+        "bge-reranker-v2-m3" => RerankerModel::BGERerankerV2M3,
+        "jina-reranker-v1-turbo-en" => RerankerModel::JINARerankerV1TurboEn,
+        "jina-reranker-v2-base-multilingual" => RerankerModel::JINARerankerV2BaseMultiligual,
+        other => bail!(
+            "unknown reranker `{other}` (expected bge-reranker-base, bge-reranker-v2-m3, \
+             jina-reranker-v1-turbo-en or jina-reranker-v2-base-multilingual)"
+        ),
+    })
+}
+
+// Cross-encoders emit logits; squash them into [0, 1] so the score threshold applies.
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Relevance of each of `docs` to `query` in [0, 1], in input order. Models
+/// are loaded once per process.
+pub(crate) fn rerank(model: &str, query: &str, docs: &[&str]) -> Result<Vec<f32>> {
+    type Shared = Arc<Mutex<TextRerank>>;
+    static RERANKERS: OnceLock<Mutex<HashMap<String, Shared>>> = OnceLock::new();
+    let reranker = {
+        let mut cache = RERANKERS
+            .get_or_init(Default::default)
+            .lock()
+            .map_err(|_| anyhow::anyhow!("reranker cache poisoned"))?;
+        match cache.get(model) {
+            Some(r) => r.clone(),
+            None => {
+                let r = TextRerank::try_new(RerankInitOptions::new(reranker_model(model)?))
+                    .with_context(|| format!("init reranker {model}"))?;
+                let r: Shared = Arc::new(Mutex::new(r));
+                cache.insert(model.to_string(), r.clone());
+                r
+            }
+        }
+    };
+    let results = reranker
+        .lock()
+        .map_err(|_| anyhow::anyhow!("reranker lock poisoned"))?
+        .rerank(query, docs.to_vec(), false, Some(docs.len().max(1)))
+        .with_context(|| format!("rerank with {model}"))?;
+    let mut scores = vec![0.0; docs.len()];
+    for r in results {
+        if let Some(s) = scores.get_mut(r.index) {
+            *s = sigmoid(r.score);
+        }
+    }
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reranker_names_and_scores() {
+        assert!(reranker_model(DEFAULT_RERANKER).is_ok());
+        assert!(reranker_model("jina-reranker-v2-base-multilingual").is_ok());
+        assert!(reranker_model("bge-small").is_err());
+        assert_eq!(sigmoid(0.0), 0.5);
+        assert!(sigmoid(-4.0) < 0.05 && sigmoid(4.0) > 0.95);
+    }
+}
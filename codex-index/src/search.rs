@@ -0,0 +1,1061 @@
+//! Query side of the index: [`Index`] maps the vector store and the HNSW point
+//! data and holds chunk metadata, BM25 postings and the graph links in memory;
+//! [`Retriever`] runs filtered hybrid searches over it. Embedding models and
+//! opened indexes are cached per process so repeated queries skip the model
+//! load and graph reload.
+
+use anyhow::{Context, Result, bail};
+use hnsw_rs::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+use crate::embed::embedder;
+use crate::lexical::{self, LEXICAL_FILE, LexicalIndex, SearchMode};
+use crate::querylog::{self, QueryRecord, QuerySource};
+use crate::rerank::{self, rerank_from_env};
+use crate::secrets::Scrubber;
+use crate::store::{self, EXACT_FILE, HNSW_BASENAME, META_FILE, Manifest, MetaRow, VECTORS_FILE};
+use crate::vectors::VectorStore;
+use crate::workspace::Workspace;
+
+/// Candidates per result re-scored with exact vectors after a quantized scan.
+const RESCORE_DEPTH: usize = 4;
+
+/// Candidates per result considered when diversifying (at least
+/// [`rerank::MIN_CANDIDATES`]). Without diversification, twice k leaves room
+/// for merged chunks.
+const DIVERSITY_DEPTH: usize = 4;
+
+/// Shown instead of results when nothing passes the score threshold.
+pub const NO_MATCH: &str = "No information exists that matches the request.";
+
+/// `CODEX_INDEX_RETRIEVAL_THRESHOLD` clamped to [0, 1], or `default`.
+pub fn score_threshold(default: f32) -> f32 {
+    std::env::var("CODEX_INDEX_RETRIEVAL_THRESHOLD")
+        .ok()
+        .and_then(|s| s.parse::<f32>().ok())
+        .map(|v| v.clamp(0.0, 1.0))
+        .unwrap_or(default)
+}
+
+// Embed with the provider and model the index was built with.
+fn embed_query(m: &Manifest, text: &str) -> Result<Vec<f32>> {
+    let out = embedder(m.embedder, &m.model, m.embed_url.as_deref())?
+        .embed(&[text])
+        .with_context(|| format!("embed query with {} {}", m.embedder.as_str(), m.model))?;
+    let mut qv = out.into_iter().next().unwrap_or_default();
+    let qn = qv.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if qn > 0.0 {
+        for x in qv.iter_mut() {
+            *x /= qn;
+        }
+    }
+    Ok(qv)
+}
+
+// ---------- index ----------
+
+/// An opened index. Rows are addressed by position, which is also the HNSW
+/// data id and the BM25 document number.
+pub struct Index {
+    root: PathBuf,
+    manifest: Manifest,
+    vectors: Option<VectorStore>,
+    exact: Option<VectorStore>,
+    rows: Vec<MetaRow>,
+    lexical: Option<LexicalIndex>,
+    hnsw: Option<Graph>,
+    // Loaded on first use, by `span_preview`.
+    scrubber: OnceLock<Option<Scrubber>>,
+}
+
+impl Index {
+    pub fn open(root: &Path) -> Result<Self> {
+        let manifest = store::read_manifest(root)?;
+        let dir = store::index_dir(root);
+        if manifest.dim == 0 || manifest.counts.chunks == 0 {
+            // A build that found nothing to index writes only the manifest.
+            return Ok(Self {
+                root: root.to_path_buf(),
+                manifest,
+                vectors: None,
+                exact: None,
+                rows: Vec::new(),
+                lexical: None,
+                hnsw: None,
+                scrubber: OnceLock::new(),
+            });
+        }
+        let vectors = VectorStore::open(&dir.join(VECTORS_FILE))?;
+        let ids = vectors.ids();
+        let mut meta = store::load_meta(dir.join(META_FILE))?;
+        let rows = ids
+            .iter()
+            .map(|id| {
+                meta.remove(id)
+                    .with_context(|| format!("meta row {id} missing; rebuild the index"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Lexical (BM25) index sits next to the vectors; older indexes may lack it.
+        let lexical = LexicalIndex::load(&dir.join(LEXICAL_FILE))
+            .ok()
+            .filter(|l| l.docs() == rows.len());
+        let hnsw = load_hnsw(&dir);
+        // Without the exact copy, scores come from the quantized rows.
+        let exact = manifest
+            .rescore
+            .then(|| VectorStore::open(&dir.join(EXACT_FILE)).ok())
+            .flatten()
+            .filter(|e| e.len() == vectors.len() && e.dim() == vectors.dim());
+        Ok(Self {
+            root: root.to_path_buf(),
+            manifest,
+            vectors: Some(vectors),
+            exact,
+            rows,
+            lexical,
+            hnsw,
+            scrubber: OnceLock::new(),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    // Similarity from the (possibly quantized) store that linear scans read.
+    fn approx_similarity(&self, pos: usize, qv: &[f32]) -> f32 {
+        self.vectors.as_ref().map_or(0.0, |v| v.cosine(pos, qv))
+    }
+
+    // Similarity from the exact f32 copy when one is kept, else the store.
+    fn similarity(&self, pos: usize, qv: &[f32]) -> f32 {
+        match &self.exact {
+            Some(exact) => exact.cosine(pos, qv),
+            None => self.approx_similarity(pos, qv),
+        }
+    }
+
+    // Similarity between two rows, for diversification.
+    pub(crate) fn row_similarity(&self, a: usize, b: usize) -> f32 {
+        self.exact
+            .as_ref()
+            .or(self.vectors.as_ref())
+            .map_or(0.0, |v| v.cosine(a, &v.row(b)))
+    }
+
+    pub(crate) fn rows(&self) -> &[MetaRow] {
+        &self.rows
+    }
+
+    /// Stored vector of row `pos` (from the exact copy when one is kept).
+    pub(crate) fn row_vector(&self, pos: usize) -> Vec<f32> {
+        self.exact
+            .as_ref()
+            .or(self.vectors.as_ref())
+            .map_or_else(Vec::new, |v| v.row(pos))
+    }
+
+    /// Up to `n` rows nearest to `qv` among those `keep` accepts, best first.
+    /// Searches the graph with `keep` as its filter when there is one, and
+    /// scans every row when there is none or the graph search came up short.
+    pub(crate) fn nearest(
+        &self,
+        qv: &[f32],
+        n: usize,
+        keep: impl Fn(usize) -> bool,
+    ) -> Vec<(usize, f32)> {
+        let want = n.min(self.len());
+        let filter = |id: &usize| keep(*id);
+        let graph: Vec<(usize, f32)> = self.hnsw.as_ref().map_or_else(Vec::new, |hnsw| {
+            hnsw.search_filter(qv, want, (want * 2).max(64), Some(&filter))
+                .iter()
+                .map(|x| (x.d_id, 1.0f32 - x.distance))
+                .collect()
+        });
+        let mut found = if graph.len() >= want {
+            graph
+        } else {
+            (0..self.len())
+                .filter(|pos| keep(*pos))
+                .map(|pos| (pos, self.similarity(pos, qv)))
+                .collect()
+        };
+        found.sort_by(|a, b| b.1.total_cmp(&a.1));
+        found.truncate(n);
+        found
+    }
+
+    /// Lines `hit.start..=hit.end` of the file as it is now, with secrets
+    /// scrubbed as at build time; the preview of a merged hit. None when the
+    /// file cannot be read or no longer has those lines.
+    pub(crate) fn span_preview(&self, hit: &Hit) -> Option<String> {
+        let text = std::fs::read_to_string(self.root.join(&hit.path)).ok()?;
+        let scrubber = self
+            .scrubber
+            .get_or_init(|| Scrubber::load(&self.root).ok())
+            .as_ref()?;
+        // Scrub the whole file: some rules span lines (e.g. private keys).
+        let (text, _) = scrubber.scrub(&text);
+        let count = hit.end.saturating_sub(hit.start) + 1;
+        let lines: Vec<&str> = text
+            .lines()
+            .skip(hit.start.saturating_sub(1))
+            .take(count)
+            .collect();
+        (lines.len() == count).then(|| lines.join("\n"))
+    }
+
+    /// Row `pos` as a search result.
+    pub(crate) fn hit(&self, pos: usize, score: f32, semantic: f32, lexical: f32) -> Hit {
+        let row = &self.rows[pos];
+        Hit {
+            path: row.path.clone(),
+            start: row.start,
+            end: row.end,
+            lang: row.lang.clone(),
+            score,
+            semantic,
+            lexical,
+            preview: row.preview.clone(),
+            section: (!row.section.is_empty()).then(|| row.section.clone()),
+            rerank: None,
+            repo: None,
+        }
+    }
+}
+
+/// HNSW graph loaded with its data file memory-mapped, so the full-precision
+/// rows it keeps are paged in on demand instead of read into memory.
+pub(crate) struct Graph {
+    hnsw: std::mem::ManuallyDrop<Hnsw<'static, f32, DistCosine>>,
+    // The loader owns the mapping the graph's points borrow.
+    io: *mut HnswIo,
+}
+
+// SAFETY: the loader is only reached through the graph's read-only point
+// data once loading is done, and both are freed together in `Drop`.
+unsafe impl Send for Graph {}
+unsafe impl Sync for Graph {}
+
+impl std::ops::Deref for Graph {
+    type Target = Hnsw<'static, f32, DistCosine>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.hnsw
+    }
+}
+
+impl Drop for Graph {
+    fn drop(&mut self) {
+        // SAFETY: the graph goes first since it borrows the loader, which was
+        // leaked from a Box in `load_hnsw` and is not used afterwards.
+        unsafe {
+            std::mem::ManuallyDrop::drop(&mut self.hnsw);
+            drop(Box::from_raw(self.io));
+        }
+    }
+}
+
+// Prefer the HNSW graph when its files exist; searches fall back to a linear scan.
+pub(crate) fn load_hnsw(dir: &Path) -> Option<Graph> {
+    let graph = dir.join(format!("{}.hnsw.graph", HNSW_BASENAME));
+    let data = dir.join(format!("{}.hnsw.data", HNSW_BASENAME));
+    if !graph.exists() || !data.exists() {
+        return None;
+    }
+    let mut io = HnswIo::new(dir, HNSW_BASENAME);
+    io.set_options(ReloadOptions::default().set_mmap(true));
+    let io = Box::into_raw(Box::new(io));
+    // SAFETY: `io` stays allocated until the returned `Graph` (or the error
+    // path below) frees it, after the graph that borrows it.
+    match unsafe { (*io).load_hnsw::<f32, DistCosine>() } {
+        Ok(hnsw) => Some(Graph {
+            hnsw: std::mem::ManuallyDrop::new(hnsw),
+            io,
+        }),
+        Err(_) => {
+            drop(unsafe { Box::from_raw(io) });
+            None
+        }
+    }
+}
+
+// ---------- search ----------
+
+/// Options for [`Retriever::search`]. Path globs are anchored at the repo root
+/// and a directory also matches everything below it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub k: usize,
+    pub mode: SearchMode,
+    pub paths: Vec<String>,
+    pub excludes: Vec<String>,
+    pub langs: Vec<String>,
+    /// Cross-encoder model that re-scores the top candidates; None skips re-ranking.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerank: Option<String>,
+    /// MMR trade-off in [0, 1] between relevance (1) and novelty (0); None
+    /// ranks by relevance alone.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diversity: Option<f32>,
+}
+
+/// MMR lambda for chat retrieval and `/search` from `CODEX_INDEX_DIVERSITY`
+/// (e.g. `0.7`); unset or unparsable disables diversification.
+pub fn diversity_from_env() -> Option<f32> {
+    std::env::var("CODEX_INDEX_DIVERSITY")
+        .ok()
+        .and_then(|v| v.trim().parse::<f32>().ok())
+        .map(|l| l.clamp(0.0, 1.0))
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            k: 8,
+            mode: SearchMode::default(),
+            paths: Vec::new(),
+            excludes: Vec::new(),
+            langs: Vec::new(),
+            rerank: None,
+            diversity: None,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Move inline `path:`, `exclude:` (or `-path:`) and `lang:` terms of
+    /// `query` into the options; returns the remaining query text.
+    pub fn take_inline_filters(&mut self, query: &str) -> String {
+        let mut rest: Vec<&str> = Vec::new();
+        for word in query.split_whitespace() {
+            match word.split_once(':') {
+                Some(("path", v)) if !v.is_empty() => self.paths.push(v.to_string()),
+                Some(("exclude" | "-path", v)) if !v.is_empty() => {
+                    self.excludes.push(v.to_string())
+                }
+                Some(("lang", v)) if !v.is_empty() => self.langs.push(v.to_string()),
+                _ => rest.push(word),
+            }
+        }
+        rest.join(" ")
+    }
+
+    /// Parse a slash-command line: `-k N` sets `k` and inline filters move
+    /// into the options; returns the remaining text.
+    pub(crate) fn take_command_flags(&mut self, input: &str) -> String {
+        let mut words: Vec<&str> = Vec::new();
+        let mut it = input.split_whitespace();
+        while let Some(w) = it.next() {
+            match w {
+                "-k" | "--k" => {
+                    if let Some(k) = it.next().and_then(|v| v.parse::<usize>().ok()) {
+                        self.k = k.max(1);
+                    }
+                }
+                _ => words.push(w),
+            }
+        }
+        self.take_inline_filters(&words.join(" "))
+    }
+}
+
+/// One search result. `score` is the confidence used for threshold gating
+/// (see [`lexical::ScoredHit`]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hit {
+    pub path: String,
+    pub start: usize,
+    pub end: usize,
+    pub lang: String,
+    pub score: f32,
+    pub semantic: f32,
+    pub lexical: f32,
+    pub preview: String,
+    /// Heading or key path of a Markdown/config chunk (`Install > From
+    /// source`, `profiles.dev`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    /// Cross-encoder relevance when the query was re-ranked; `score` then
+    /// holds it too, while `semantic` keeps the raw cosine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank: Option<f32>,
+    /// Workspace member the hit came from (federated searches only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+}
+
+/// Path/language restrictions for a query.
+#[derive(Debug, Default)]
+pub(crate) struct QueryFilter {
+    paths: Vec<Regex>,
+    excludes: Vec<Regex>,
+    langs: Vec<String>,
+}
+
+impl QueryFilter {
+    pub(crate) fn new(opts: &SearchOptions) -> Self {
+        QueryFilter {
+            paths: opts.paths.iter().map(|p| filter_glob(p)).collect(),
+            excludes: opts.excludes.iter().map(|p| filter_glob(p)).collect(),
+            langs: opts.langs.iter().map(|l| normalize_lang(l)).collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.excludes.is_empty() && self.langs.is_empty()
+    }
+
+    pub(crate) fn matches(&self, row: &MetaRow) -> bool {
+        (self.langs.is_empty() || self.langs.contains(&row.lang))
+            && (self.paths.is_empty() || self.paths.iter().any(|re| re.is_match(&row.path)))
+            && !self.excludes.iter().any(|re| re.is_match(&row.path))
+    }
+}
+
+// Filter globs are anchored at the repo root; a pattern that names a directory
+// also matches everything below it (`codex-acp` == `codex-acp/**`).
+fn filter_glob(glob: &str) -> Regex {
+    let glob = glob.trim_start_matches("./").trim_end_matches('/');
+    let r = format!("^(?:{})(?:/.*)?$", store::glob_pattern(glob));
+    Regex::new(&r).unwrap_or_else(|_| Regex::new(r"^$\b").unwrap())
+}
+
+// Accept common short names for the languages stored in meta rows.
+fn normalize_lang(lang: &str) -> String {
+    let lang = lang.to_ascii_lowercase();
+    match lang.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "ts" | "tsx" => "typescript",
+        "js" | "jsx" => "javascript",
+        "golang" => "go",
+        "c++" | "cc" | "cxx" | "hpp" => "cpp",
+        "sh" | "bash" | "zsh" => "shell",
+        "md" => "markdown",
+        "yml" => "yaml",
+        _ => return lang,
+    }
+    .to_string()
+}
+
+/// Runs searches against an opened [`Index`].
+pub struct Retriever {
+    index: Index,
+}
+
+impl Retriever {
+    pub fn new(index: Index) -> Self {
+        Self { index }
+    }
+
+    pub fn open(root: &Path) -> Result<Self> {
+        Ok(Self::new(Index::open(root)?))
+    }
+
+    /// Process-wide retriever for `root`, reopened when a build has replaced
+    /// the vectors or metadata since it was loaded.
+    pub fn shared(root: &Path) -> Result<Arc<Self>> {
+        static SHARED: OnceLock<Mutex<HashMap<PathBuf, Arc<Retriever>>>> = OnceLock::new();
+        let current = store::read_manifest(root)?;
+        let mut cache = SHARED
+            .get_or_init(Default::default)
+            .lock()
+            .map_err(|_| anyhow::anyhow!("retriever cache poisoned"))?;
+        if let Some(r) = cache.get(root) {
+            let m = &r.index.manifest;
+            if m.checksums.vectors_hnsw == current.checksums.vectors_hnsw
+                && m.checksums.meta_jsonl == current.checksums.meta_jsonl
+                && m.counts.chunks == current.counts.chunks
+            {
+                return Ok(r.clone());
+            }
+        }
+        let r = Arc::new(Self::open(root)?);
+        cache.insert(root.to_path_buf(), r.clone());
+        Ok(r)
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Top `opts.k` chunks for `query`, best first. Filters are applied inside
+    /// the searches so top-k is filled from matching chunks.
+    pub fn search(&self, query: &str, opts: &SearchOptions) -> Result<Vec<Hit>> {
+        Ok(self.search_timed(query, opts)?.0)
+    }
+
+    /// [`Retriever::search`] plus where the time went.
+    pub fn search_timed(&self, query: &str, opts: &SearchOptions) -> Result<(Vec<Hit>, Timings)> {
+        let started = Instant::now();
+        let mut timings = Timings::default();
+        let index = &self.index;
+        if query.trim().is_empty() {
+            bail!("empty query (only filters given)");
+        }
+        let filter = QueryFilter::new(opts);
+        // Positions allowed by the filter (sorted).
+        let allowed: Option<Vec<usize>> = (!filter.is_empty()).then(|| {
+            (0..index.len())
+                .filter(|pos| filter.matches(&index.rows[*pos]))
+                .collect()
+        });
+        let mut mask = vec![allowed.is_none(); index.len()];
+        for pos in allowed.iter().flatten() {
+            mask[*pos] = true;
+        }
+        let pool = allowed.as_ref().map_or(index.len(), |a| a.len());
+
+        let mut mode = opts.mode;
+        let lexical = match mode {
+            SearchMode::Semantic => None,
+            _ => index.lexical.as_ref(),
+        };
+        if lexical.is_none() {
+            if mode == SearchMode::Lexical {
+                bail!("lexical index missing; run `codex-agentic index build` to create it");
+            }
+            mode = SearchMode::Semantic;
+        }
+        // Re-ranking and diversification score a deeper list than the final
+        // top-k; merging overlapping chunks needs a few spare candidates too.
+        let wanted = if opts.rerank.is_some() || opts.diversity.is_some() {
+            (opts.k * rerank::DEPTH.max(DIVERSITY_DEPTH)).max(rerank::MIN_CANDIDATES)
+        } else {
+            opts.k * 2
+        };
+        // Hybrid fuses deeper candidate lists than the final top-k.
+        let candidates = if mode == SearchMode::Hybrid {
+            (wanted * 4).max(32)
+        } else {
+            wanted
+        };
+
+        let mut qv: Vec<f32> = Vec::new();
+        let mut semantic: Vec<(usize, f32)> = Vec::new();
+        if mode != SearchMode::Lexical && pool > 0 {
+            let m = &index.manifest;
+            let embed_started = Instant::now();
+            qv = embed_query(m, query)?;
+            timings.embed_ms = ms(embed_started);
+            if qv.len() != m.dim {
+                bail!(
+                    "{} model `{}` returned {}-D query vectors but the index holds {}-D vectors; \
+                     the model changed since the index was built, rebuild with `index build --force`",
+                    m.embedder.as_str(),
+                    m.model,
+                    qv.len(),
+                    m.dim
+                );
+            }
+            let max_k = candidates.min(pool);
+            if let Some(hnsw) = &index.hnsw {
+                let ef_s = 256usize.max(candidates);
+                let result = match &allowed {
+                    Some(allowed) => hnsw.search_filter(&qv, max_k, ef_s, Some(allowed)),
+                    None => hnsw.search(&qv, max_k, ef_s),
+                };
+                // DistCosine distance in [0,2]; similarity ~ 1 - d. d_id is the row position.
+                // The graph holds full-precision rows (mapped, not loaded), so these
+                // scores are already exact.
+                semantic = result
+                    .iter()
+                    .map(|n| (n.d_id, 1.0f32 - n.distance))
+                    .collect();
+            } else {
+                semantic = (0..index.len())
+                    .filter(|i| mask[*i])
+                    .map(|i| (i, index.approx_similarity(i, &qv)))
+                    .collect();
+                semantic.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+                if index.exact.is_some() {
+                    // Quantized scan: re-score a deeper candidate list exactly.
+                    semantic.truncate(max_k * RESCORE_DEPTH);
+                    for (pos, score) in semantic.iter_mut() {
+                        *score = index.similarity(*pos, &qv);
+                    }
+                    semantic
+                        .sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+                }
+                semantic.truncate(max_k);
+            }
+            // Sort scores (desc) to ensure top-first regardless of path
+            semantic.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        }
+
+        let lexical_hits = lexical
+            .map(|l| l.search_filtered(query, candidates, |pos| mask[pos]))
+            .unwrap_or_default();
+        let scores = lexical::fuse(mode, &semantic, &lexical_hits, wanted, |pos| {
+            if qv.is_empty() {
+                0.0
+            } else {
+                index.similarity(pos, &qv)
+            }
+        });
+        let mut hits: Vec<(usize, Hit)> = scores
+            .into_iter()
+            .take(wanted)
+            .map(|h| (h.pos, index.hit(h.pos, h.score, h.semantic, h.lexical)))
+            .collect();
+        if let Some(model) = &opts.rerank
+            && !hits.is_empty()
+        {
+            let docs: Vec<String> = hits
+                .iter()
+                .map(|(_, h)| format!("{}\n{}", h.path, h.preview))
+                .collect();
+            let docs: Vec<&str> = docs.iter().map(String::as_str).collect();
+            for ((_, hit), score) in hits.iter_mut().zip(rerank::rerank(model, query, &docs)?) {
+                hit.rerank = Some(score);
+                hit.score = score;
+            }
+            hits.sort_by(|a, b| b.1.score.total_cmp(&a.1.score));
+        }
+        let hits = select(
+            hits,
+            opts.k,
+            opts.diversity,
+            |a, b| index.row_similarity(a, b),
+            |hit| index.span_preview(hit),
+        );
+        timings.search_ms = ms(started) - timings.embed_ms;
+        Ok((hits, timings))
+    }
+}
+
+/// Pick up to `k` results from `candidates` (row position and hit, best
+/// first). A chunk that overlaps or touches a picked range of the same file
+/// is merged into it instead of taking a slot. With `diversity` = λ, each pick
+/// maximizes λ·score − (1−λ)·(highest `similarity` to a row picked so far),
+/// i.e. maximal marginal relevance.
+///
+/// A merged hit takes its preview from `span_preview` (the text of the whole
+/// merged range) so the snippet matches the reported lines; when that yields
+/// None it keeps the preview of the chunk that starts first.
+pub(crate) fn select(
+    mut candidates: Vec<(usize, Hit)>,
+    k: usize,
+    diversity: Option<f32>,
+    similarity: impl Fn(usize, usize) -> f32,
+    span_preview: impl Fn(&Hit) -> Option<String>,
+) -> Vec<Hit> {
+    let mut picked: Vec<Hit> = Vec::new();
+    // Whether each pick absorbed another chunk.
+    let mut merged: Vec<bool> = Vec::new();
+    let mut rows: Vec<usize> = Vec::new();
+    while picked.len() < k && !candidates.is_empty() {
+        let next = match diversity {
+            None => 0,
+            Some(lambda) => {
+                let lambda = lambda.clamp(0.0, 1.0);
+                let mmr = |(pos, hit): &(usize, Hit)| {
+                    let redundancy = rows
+                        .iter()
+                        .map(|r| similarity(*pos, *r))
+                        .fold(0.0f32, f32::max);
+                    lambda * hit.score - (1.0 - lambda) * redundancy
+                };
+                (0..candidates.len())
+                    .max_by(|a, b| {
+                        mmr(&candidates[*a])
+                            .total_cmp(&mmr(&candidates[*b]))
+                            // Earlier (better ranked) candidates win ties.
+                            .then(b.cmp(a))
+                    })
+                    .unwrap_or(0)
+            }
+        };
+        let (pos, hit) = candidates.remove(next);
+        rows.push(pos);
+        match picked.iter().position(|p| touches(p, &hit)) {
+            Some(mut i) => {
+                merge(&mut picked[i], hit);
+                merged[i] = true;
+                // The wider range may now reach another pick of the file.
+                while let Some(j) =
+                    (0..picked.len()).find(|&j| j != i && touches(&picked[i], &picked[j]))
+                {
+                    let other = picked.remove(j);
+                    merged.remove(j);
+                    if j < i {
+                        i -= 1;
+                    }
+                    merge(&mut picked[i], other);
+                }
+            }
+            None => {
+                picked.push(hit);
+                merged.push(false);
+            }
+        }
+    }
+    for (hit, merged) in picked.iter_mut().zip(merged) {
+        if merged && let Some(preview) = span_preview(hit) {
+            hit.preview = preview;
+        }
+    }
+    picked
+}
+
+// Same file and overlapping or adjacent lines.
+fn touches(a: &Hit, b: &Hit) -> bool {
+    a.path == b.path && a.repo == b.repo && a.start <= b.end + 1 && b.start <= a.end + 1
+}
+
+// Widen `into` to cover `other`, keeping the best scores and the preview of
+// whichever starts first (previews are the first lines of a chunk) until
+// `select` rebuilds it for the merged range.
+fn merge(into: &mut Hit, other: Hit) {
+    if other.start < into.start {
+        into.preview = other.preview;
+        into.start = other.start;
+    }
+    into.end = into.end.max(other.end);
+    into.score = into.score.max(other.score);
+    into.semantic = into.semantic.max(other.semantic);
+    into.lexical = into.lexical.max(other.lexical);
+    into.rerank = match (into.rerank, other.rerank) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    };
+}
+
+/// Latency of one search in milliseconds: embedding the query, and the rest
+/// (graph or scan, BM25, fusion, re-ranking).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+    pub embed_ms: f64,
+    pub search_ms: f64,
+}
+
+impl std::ops::AddAssign for Timings {
+    fn add_assign(&mut self, other: Self) {
+        self.embed_ms += other.embed_ms;
+        self.search_ms += other.search_ms;
+    }
+}
+
+/// Milliseconds elapsed since `start`.
+pub(crate) fn ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+// ---------- text output ----------
+
+/// Layout of [`format_hits_text`], matching `index query` text output.
+#[derive(Debug, Clone)]
+pub struct TextFormat {
+    pub snippets: bool,
+    pub line_numbers: bool,
+    pub line_number_width: usize,
+    /// Prefix snippet lines with `+ ` (diff-style).
+    pub diff: bool,
+}
+
+impl Default for TextFormat {
+    fn default() -> Self {
+        Self {
+            snippets: false,
+            line_numbers: true,
+            line_number_width: 6,
+            diff: false,
+        }
+    }
+}
+
+/// `[rank] score path:start-end (lang)` per hit (re-ranked hits add the raw
+/// cosine), each followed by its numbered snippet when requested.
+pub fn format_hits_text(hits: &[Hit], fmt: &TextFormat) -> String {
+    let mut out = String::new();
+    for (rank, hit) in hits.iter().enumerate() {
+        let cosine = match hit.rerank {
+            Some(_) => format!(" (cosine {:.3})", hit.semantic),
+            None => String::new(),
+        };
+        let section = match &hit.section {
+            Some(s) => format!(" [{s}]"),
+            None => String::new(),
+        };
+        out.push_str(&format!(
+            "[{rank}] {:.3}{cosine} {}{}:{}-{} ({}){section}\n",
+            hit.score,
+            member_prefix(hit.repo.as_deref()),
+            hit.path,
+            hit.start,
+            hit.end,
+            hit.lang
+        ));
+        if !fmt.snippets {
+            continue;
+        }
+        push_snippet(&mut out, hit, fmt);
+        out.push_str("---\n");
+    }
+    out
+}
+
+/// `[member] ` ahead of a federated hit's path, so hits of different
+/// workspace members stay distinguishable; empty for a single repository.
+pub(crate) fn member_prefix(repo: Option<&str>) -> String {
+    repo.map(|r| format!("[{r}] ")).unwrap_or_default()
+}
+
+fn push_snippet(out: &mut String, hit: &Hit, fmt: &TextFormat) {
+    let prefix = if fmt.diff { "+ " } else { "" };
+    for (i, line) in hit.preview.lines().enumerate() {
+        if fmt.line_numbers {
+            let ln = hit.start.saturating_add(i);
+            out.push_str(&format!(
+                "{ln:>width$} | {prefix}{line}\n",
+                width = fmt.line_number_width
+            ));
+        } else {
+            out.push_str(&format!("{prefix}{line}\n"));
+        }
+    }
+}
+
+/// The hits of one file, for `--group-by file` and the chat reference block.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileHits {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    pub lang: String,
+    /// Best score among the ranges.
+    pub score: f32,
+    /// Matching ranges in line order.
+    pub ranges: Vec<Hit>,
+}
+
+impl FileHits {
+    /// `10-40, 80-120`.
+    pub fn line_ranges(&self) -> String {
+        let ranges: Vec<String> = self
+            .ranges
+            .iter()
+            .map(|h| format!("{}-{}", h.start, h.end))
+            .collect();
+        ranges.join(", ")
+    }
+}
+
+/// Group `hits` by file, best file first.
+pub fn group_by_file(hits: &[Hit]) -> Vec<FileHits> {
+    let mut files: Vec<FileHits> = Vec::new();
+    for hit in hits {
+        match files
+            .iter_mut()
+            .find(|f| f.path == hit.path && f.repo == hit.repo)
+        {
+            Some(f) => {
+                f.score = f.score.max(hit.score);
+                f.ranges.push(hit.clone());
+            }
+            None => files.push(FileHits {
+                path: hit.path.clone(),
+                repo: hit.repo.clone(),
+                lang: hit.lang.clone(),
+                score: hit.score,
+                ranges: vec![hit.clone()],
+            }),
+        }
+    }
+    for f in &mut files {
+        f.ranges.sort_by_key(|h| h.start);
+    }
+    files.sort_by(|a, b| b.score.total_cmp(&a.score));
+    files
+}
+
+/// `[rank] score path (lang) start-end, ...` per file, followed by the
+/// numbered snippet of each range when requested.
+pub fn format_files_text(files: &[FileHits], fmt: &TextFormat) -> String {
+    let mut out = String::new();
+    for (rank, file) in files.iter().enumerate() {
+        out.push_str(&format!(
+            "[{rank}] {:.3} {}{} ({}) {}\n",
+            file.score,
+            member_prefix(file.repo.as_deref()),
+            file.path,
+            file.lang,
+            file.line_ranges()
+        ));
+        if !fmt.snippets {
+            continue;
+        }
+        for (i, hit) in file.ranges.iter().enumerate() {
+            if i > 0 {
+                out.push_str("...\n");
+            }
+            push_snippet(&mut out, hit, fmt);
+        }
+        out.push_str("---\n");
+    }
+    out
+}
+
+/// Run a `/search` command line (`<query> [lang:L] [path:GLOB] [exclude:GLOB]
+/// [-k N]`) against the index of `root` (and the rest of its workspace, when
+/// federated) and render the result as text. The query is logged as coming
+/// from `source`.
+pub fn search_command(root: &Path, input: &str, source: QuerySource) -> String {
+    let mut opts = SearchOptions {
+        mode: SearchMode::from_env(),
+        rerank: rerank_from_env(),
+        diversity: diversity_from_env(),
+        ..SearchOptions::default()
+    };
+    let query = opts.take_command_flags(input);
+    let searched = match Workspace::load(root) {
+        Ok(ws) if ws.is_federated() => ws.search_timed(&query, &opts),
+        _ => Retriever::shared(root).and_then(|r| r.search_timed(&query, &opts)),
+    };
+    let (hits, timings) = match searched {
+        Ok(h) => h,
+        Err(e) => return format!("Error: {e:#}"),
+    };
+    let threshold = score_threshold(0.60);
+    let top = hits.iter().map(|h| h.score).fold(0.0f32, f32::max);
+    let _ = store::record_query(root, top >= threshold);
+    let mut record = QueryRecord::new(source, &query, &hits, threshold, timings);
+    let started = Instant::now();
+    let out = if hits.is_empty() || top < threshold {
+        NO_MATCH.to_string()
+    } else {
+        let fmt = TextFormat {
+            snippets: true,
+            ..TextFormat::default()
+        };
+        format_hits_text(&hits, &fmt)
+    };
+    record.format_ms = ms(started);
+    let _ = querylog::log_query(root, &record);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_filter_inline_terms_and_globs() {
+        let mut opts = SearchOptions {
+            paths: vec!["codex-acp".into()],
+            ..SearchOptions::default()
+        };
+        let text =
+            opts.take_inline_filters("lang:rs path:codex-tui/** resume  picker exclude:*tests*");
+        assert_eq!(text, "resume picker");
+        let filter = QueryFilter::new(&opts);
+        let row = |path: &str, lang: &str| MetaRow {
+            lang: lang.into(),
+            ..MetaRow::fixture(path, 1, 1)
+        };
+        assert!(filter.matches(&row("codex-tui/src/resume_picker.rs", "rust")));
+        assert!(filter.matches(&row("codex-acp/src/agent.rs", "rust")));
+        assert!(!filter.matches(&row("codex-acp-extra/src/a.rs", "rust")));
+        assert!(!filter.matches(&row("codex-tui/tests/resume.rs", "rust")));
+        assert!(!filter.matches(&row("codex-tui/src/app.py", "python")));
+    }
+
+    fn hit(path: &str, start: usize, end: usize, score: f32) -> Hit {
+        Hit {
+            path: path.into(),
+            start,
+            end,
+            lang: "rust".into(),
+            score,
+            semantic: score,
+            lexical: 0.0,
+            preview: format!("{path}:{start}"),
+            section: None,
+            rerank: None,
+            repo: None,
+        }
+    }
+
+    #[test]
+    fn selection_merges_overlapping_chunks_and_diversifies() {
+        let candidates = vec![
+            (0, hit("a.rs", 1, 40, 0.9)),
+            (1, hit("a.rs", 33, 70, 0.85)),
+            (2, hit("a.rs", 71, 100, 0.8)),
+            (3, hit("b.rs", 1, 20, 0.7)),
+            (4, hit("c.rs", 1, 20, 0.6)),
+        ];
+        // Rows 0-3 are near duplicates of each other; row 4 is unrelated.
+        let similarity = |a: usize, b: usize| if a.max(b) < 4 { 0.95 } else { 0.0 };
+
+        let span = |h: &Hit| Some(format!("lines {}-{}", h.start, h.end));
+        let picked = select(candidates.clone(), 2, None, similarity, span);
+        let spans: Vec<_> = picked
+            .iter()
+            .map(|h| (h.path.as_str(), h.start, h.end))
+            .collect();
+        assert_eq!(spans, vec![("a.rs", 1, 100), ("b.rs", 1, 20)]);
+        assert_eq!(picked[0].score, 0.9);
+        // Only the merged hit gets a preview of its whole range.
+        assert_eq!(picked[0].preview, "lines 1-100");
+        assert_eq!(picked[1].preview, "b.rs:1");
+
+        let picked = select(candidates, 2, Some(0.5), similarity, |_| None);
+        let paths: Vec<_> = picked.iter().map(|h| h.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.rs", "c.rs"]);
+
+        // A chunk bridging two picks of a file folds them into one.
+        let bridged = select(
+            vec![
+                (0, hit("a.rs", 20, 30, 0.9)),
+                (1, hit("a.rs", 1, 10, 0.8)),
+                (2, hit("a.rs", 11, 19, 0.7)),
+            ],
+            3,
+            None,
+            similarity,
+            |_| None,
+        );
+        assert_eq!(bridged.len(), 1);
+        assert_eq!((bridged[0].start, bridged[0].end), (1, 30));
+        assert_eq!(bridged[0].preview, "a.rs:1");
+    }
+
+    #[test]
+    fn hits_group_by_file() {
+        let files = group_by_file(&[
+            hit("a.rs", 50, 60, 0.7),
+            hit("b.rs", 1, 9, 0.8),
+            hit("a.rs", 1, 10, 0.6),
+        ]);
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].path.as_str(), files[0].score), ("b.rs", 0.8));
+        assert_eq!(files[1].line_ranges(), "1-10, 50-60");
+        let text = format_files_text(&files, &TextFormat::default());
+        assert!(text.contains("[1] 0.700 a.rs (rust) 1-10, 50-60"));
+
+        let mut federated = hit("../api/lib.rs", 1, 9, 0.8);
+        federated.repo = Some("api".into());
+        let text = format_hits_text(&[federated], &TextFormat::default());
+        assert!(text.starts_with("[0] 0.800 [api] ../api/lib.rs:1-9 (rust)"));
+    }
+}
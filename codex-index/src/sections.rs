@@ -0,0 +1,540 @@
+//! Structure-aware chunking for docs and configs: Markdown by heading
+//! hierarchy, TOML by table and YAML/JSON by key. Each chunk records the
+//! heading or key path it covers, which is prepended to the embedded text.
+
+use crate::chunk::{chunk_blanklines, preview};
+
+/// A chunk of a structured file: byte range, preview and heading/key path
+/// (`Install > From source`, `profiles.dev`).
+pub(crate) struct Section {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) preview: String,
+    pub(crate) path: String,
+}
+
+// A heading or key with the lines it covers: from its first line (including
+// comments right above it) to the next sibling. Ends are filled in by `tile`.
+struct Node {
+    key: String,
+    start: usize,
+    end: usize,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(key: impl Into<String>, start: usize) -> Self {
+        Node {
+            key: key.into(),
+            start,
+            end: start,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Chunks of about `target` lines for Markdown, TOML, YAML and JSON files.
+/// Sections that fit are kept whole (with their subsections), small siblings
+/// are grouped and larger ones split at their children. None for other
+/// languages or when no headings/keys were found.
+pub(crate) fn chunk_sections(text: &str, lang: &str, target: usize) -> Option<Vec<Section>> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let (mut nodes, sep) = match lang {
+        "markdown" => (markdown(&lines), " > "),
+        "toml" => (toml(&lines), "."),
+        "yaml" => (yaml(&lines, 0, lines.len()), "."),
+        "json" => (json(text), "."),
+        _ => return None,
+    };
+    if nodes.is_empty() {
+        return None;
+    }
+    tile(&mut nodes, lines.len());
+    let mut starts = vec![0usize];
+    starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+    let mut packer = Packer {
+        text,
+        starts,
+        target: target.max(8),
+        sep,
+        out: Vec::new(),
+    };
+    packer.pack(&nodes, &mut Vec::new(), 0);
+    (!packer.out.is_empty()).then_some(packer.out)
+}
+
+// Each node runs up to the next sibling, the last one up to its parent's end.
+fn tile(nodes: &mut [Node], end: usize) {
+    for i in 0..nodes.len() {
+        let next = nodes.get(i + 1).map_or(end, |n| n.start);
+        let node = &mut nodes[i];
+        node.end = next.max(node.start);
+        tile(&mut node.children, node.end);
+    }
+}
+
+struct Packer<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+    target: usize,
+    sep: &'static str,
+    out: Vec<Section>,
+}
+
+impl Packer<'_> {
+    /// Emit `nodes` under `prefix`; the first chunk starts at line `lead` so
+    /// it takes in the parent's heading or key line (or the file preamble).
+    fn pack(&mut self, nodes: &[Node], prefix: &mut Vec<String>, mut lead: usize) {
+        // Siblings are grouped only while they stay well under the target, so
+        // a chunk still points at a handful of tables or sections.
+        let small = (self.target / 4).max(8).min(self.target);
+        let mut i = 0;
+        while i < nodes.len() {
+            let node = &nodes[i];
+            if node.end - lead > self.target && lead < node.start {
+                // The parent's own lines do not fit with this node: emit them alone.
+                self.emit(lead, node.start, &self.join(prefix, ""));
+                lead = node.start;
+            }
+            if node.end - lead > self.target && !node.children.is_empty() {
+                prefix.push(node.key.clone());
+                self.pack(&node.children, prefix, lead);
+                prefix.pop();
+                lead = node.end;
+                i += 1;
+                continue;
+            }
+            let mut j = i + 1;
+            while j < nodes.len() && nodes[j].end - lead <= small {
+                j += 1;
+            }
+            let mut keys: Vec<&str> = nodes[i..j].iter().map(|n| n.key.as_str()).collect();
+            keys.dedup();
+            let label = match keys.as_slice() {
+                [first, .., last] if keys.len() > 3 => format!("{first} … {last}"),
+                _ => keys.join(", "),
+            };
+            let path = self.join(prefix, &label);
+            self.emit(lead, nodes[j - 1].end, &path);
+            lead = nodes[j - 1].end;
+            i = j;
+        }
+    }
+
+    fn join(&self, prefix: &[String], last: &str) -> String {
+        prefix
+            .iter()
+            .map(String::as_str)
+            .chain(Some(last))
+            .filter(|k| !k.is_empty())
+            .collect::<Vec<_>>()
+            .join(self.sep)
+    }
+
+    fn offset(&self, line: usize) -> usize {
+        self.starts.get(line).copied().unwrap_or(self.text.len())
+    }
+
+    // Lines `from..to` as one chunk, or blank-line blocks when a section
+    // without sub-structure is too long.
+    fn emit(&mut self, from: usize, to: usize, path: &str) {
+        let (s, e) = (self.offset(from), self.offset(to));
+        let body = &self.text[s..e];
+        if body.trim().is_empty() {
+            return;
+        }
+        let pieces = if to - from > self.target {
+            chunk_blanklines(body, self.target, 0)
+        } else {
+            vec![(0, body.len(), preview(body))]
+        };
+        for (ps, pe, preview) in pieces {
+            if body[ps..pe].trim().is_empty() {
+                continue;
+            }
+            self.out.push(Section {
+                start: s + ps,
+                end: s + pe,
+                preview,
+                path: path.to_string(),
+            });
+        }
+    }
+}
+
+/// ATX (`#` to `######`) and setext (`===`/`---` underline) headings outside
+/// fenced code blocks and front matter, nested by level.
+fn markdown(lines: &[&str]) -> Vec<Node> {
+    let mut headings: Vec<(usize, usize, String)> = Vec::new();
+    let mut fence: Option<&str> = None;
+    let front_matter = match lines.first() {
+        Some(first) if first.trim_end() == "---" => lines[1..]
+            .iter()
+            .position(|l| matches!(l.trim_end(), "---" | "..."))
+            .map_or(0, |p| p + 2),
+        _ => 0,
+    };
+    for (i, line) in lines.iter().enumerate().skip(front_matter) {
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m));
+        match (fence, marker) {
+            (Some(open), Some(close)) if open == close => fence = None,
+            (Some(_), _) => {}
+            (None, Some(open)) => fence = Some(open),
+            (None, None) => {
+                if line.len() - trimmed.len() > 3 {
+                    continue;
+                }
+                let level = trimmed.bytes().take_while(|b| *b == b'#').count();
+                let rest = &trimmed[level..];
+                if (1..=6).contains(&level) && (rest.is_empty() || rest.starts_with([' ', '\t'])) {
+                    let title = rest.trim().trim_end_matches('#').trim_end();
+                    headings.push((i, level, title.to_string()));
+                    continue;
+                }
+                let underline = trimmed.trim_end();
+                let level = match underline.as_bytes().first() {
+                    Some(b'=') if underline.bytes().all(|b| b == b'=') => 1,
+                    Some(b'-') if underline.bytes().all(|b| b == b'-') => 2,
+                    _ => continue,
+                };
+                // The underlined line is the title, unless it is blank or
+                // already a heading (then `---` is a thematic break).
+                if i == front_matter {
+                    continue;
+                }
+                let title = lines[i - 1].trim();
+                if !title.is_empty() && headings.last().is_none_or(|h| h.0 != i - 1) {
+                    headings.push((i - 1, level, title.to_string()));
+                }
+            }
+        }
+    }
+    fn nest(headings: &[(usize, usize, String)]) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut i = 0;
+        while i < headings.len() {
+            let (line, level, title) = &headings[i];
+            let j = headings[i + 1..]
+                .iter()
+                .position(|h| h.1 <= *level)
+                .map_or(headings.len(), |p| i + 1 + p);
+            let mut node = Node::new(title.as_str(), *line);
+            node.children = nest(&headings[i + 1..j]);
+            nodes.push(node);
+            i = j;
+        }
+        nodes
+    }
+    nest(&headings)
+}
+
+/// Root keys and `[table]`/`[[array]]` headers, with each table's keys as
+/// children. Multi-line strings and arrays are skipped.
+fn toml(lines: &[&str]) -> Vec<Node> {
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut in_table = false;
+    let mut depth = 0i32;
+    let mut string: Option<&'static str> = None;
+    let mut comments: Option<usize> = None;
+    for (i, line) in lines.iter().enumerate() {
+        let at_top = depth <= 0 && string.is_none();
+        scan_toml_line(line, &mut depth, &mut string);
+        let trimmed = line.trim();
+        if !at_top || trimmed.is_empty() {
+            comments = None;
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            comments.get_or_insert(i);
+            continue;
+        }
+        let start = comments.take().unwrap_or(i);
+        if trimmed.starts_with('[') {
+            let header = trimmed
+                .trim_start_matches('[')
+                .split(']')
+                .next()
+                .unwrap_or("");
+            nodes.push(Node::new(header.trim(), start));
+            in_table = true;
+        } else if let Some((key, _)) = trimmed.split_once('=') {
+            let node = Node::new(key.trim(), start);
+            match nodes.last_mut() {
+                Some(table) if in_table => table.children.push(node),
+                _ => nodes.push(node),
+            }
+        }
+    }
+    nodes
+}
+
+// Track open brackets/braces and multi-line strings across a TOML line.
+fn scan_toml_line(line: &str, depth: &mut i32, string: &mut Option<&'static str>) {
+    let mut rest = line;
+    loop {
+        if let Some(delim) = *string {
+            match rest.find(delim) {
+                Some(p) => {
+                    rest = &rest[p + delim.len()..];
+                    *string = None;
+                }
+                None => return,
+            }
+            continue;
+        }
+        let Some(p) = rest.find(['"', '\'', '#', '[', ']', '{', '}']) else {
+            return;
+        };
+        let after = &rest[p..];
+        match after.as_bytes()[0] {
+            b'#' => return,
+            b'[' | b'{' => *depth += 1,
+            b']' | b'}' => *depth -= 1,
+            quote => {
+                let triple = if quote == b'"' { "\"\"\"" } else { "'''" };
+                if after.starts_with(triple) {
+                    *string = Some(triple);
+                    rest = &after[3..];
+                    continue;
+                }
+                // Basic strings honour backslash escapes, literal strings do not.
+                let mut escaped = false;
+                let close = after[1..].char_indices().find(|&(_, c)| {
+                    let end = c == quote as char && !escaped;
+                    escaped = quote == b'"' && c == '\\' && !escaped;
+                    end
+                });
+                match close {
+                    Some((q, _)) => rest = &after[q + 2..],
+                    None => return,
+                }
+                continue;
+            }
+        }
+        rest = &rest[p + 1..];
+    }
+}
+
+/// Mapping keys at the indentation of the first entry in `from..to`, with
+/// nested mappings as children. Sequences and block scalars are leaves.
+fn yaml(lines: &[&str], from: usize, to: usize) -> Vec<Node> {
+    let mut keys: Vec<(Node, usize, bool)> = Vec::new();
+    let mut level: Option<usize> = None;
+    let mut comments: Option<usize> = None;
+    for i in from..to {
+        let line = lines[i];
+        let trimmed = line.trim();
+        // Blank lines and document markers end a comment block.
+        if trimmed.is_empty() || trimmed.starts_with("---") || trimmed == "..." {
+            comments = None;
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            comments.get_or_insert(i);
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        let entry = yaml_key(trimmed);
+        let level = *level.get_or_insert(indent);
+        if level == indent && entry.is_none() && keys.is_empty() {
+            // A sequence or scalar at this level: nothing to split on.
+            return Vec::new();
+        }
+        match entry {
+            Some((key, mapping)) if indent == level => {
+                let start = comments.take().unwrap_or(i);
+                keys.push((Node::new(key, start), i, mapping));
+            }
+            _ => comments = None,
+        }
+    }
+    let ends: Vec<usize> = (1..=keys.len())
+        .map(|n| keys.get(n).map_or(to, |(next, ..)| next.start))
+        .collect();
+    keys.into_iter()
+        .zip(ends)
+        .map(|((mut node, line, mapping), end)| {
+            if mapping {
+                node.children = yaml(lines, line + 1, end);
+            }
+            node
+        })
+        .collect()
+}
+
+// `key: value` → (key, whether a nested mapping follows).
+fn yaml_key(trimmed: &str) -> Option<(&str, bool)> {
+    if trimmed.starts_with(['-', '[', '{', '?', '%']) {
+        return None;
+    }
+    let (key, value) = match trimmed.as_bytes()[0] {
+        q @ (b'"' | b'\'') => {
+            let close = trimmed[1..].find(q as char)? + 1;
+            (&trimmed[1..close], trimmed[close + 1..].strip_prefix(':')?)
+        }
+        _ => trimmed
+            .split_once(": ")
+            .or_else(|| trimmed.strip_suffix(':').map(|k| (k, "")))?,
+    };
+    let value = value.trim();
+    let mapping = value.is_empty()
+        || value.starts_with('#')
+        || (value.starts_with('&') && !value.contains(' '));
+    Some((key.trim(), mapping))
+}
+
+/// Object keys, each with the keys of its object value as children. Keys on
+/// the line of an earlier sibling fold into it, so minified JSON yields at
+/// most one node per line.
+fn json(text: &str) -> Vec<Node> {
+    struct Frame {
+        object: bool,
+        nodes: Vec<Node>,
+    }
+    let bytes = text.as_bytes();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut roots = Vec::new();
+    let mut line = 0usize;
+    let mut last_string: Option<(String, usize)> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => line += 1,
+            b'"' => {
+                let (start, at) = (i + 1, line);
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    match bytes[i] {
+                        b'\\' => i += 1,
+                        b'\n' => line += 1,
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                let key = text.get(start..i.min(bytes.len())).unwrap_or("");
+                last_string = Some((key.to_string(), at));
+            }
+            b':' => {
+                if let Some(frame) = stack.last_mut()
+                    && frame.object
+                    && let Some((key, at)) = last_string.take()
+                    && frame.nodes.last().is_none_or(|n| n.start < at)
+                {
+                    frame.nodes.push(Node::new(key, at));
+                }
+            }
+            b'{' | b'[' => stack.push(Frame {
+                object: bytes[i] == b'{',
+                nodes: Vec::new(),
+            }),
+            b'}' | b']' => {
+                if let Some(frame) = stack.pop() {
+                    match stack.last_mut() {
+                        Some(parent) => {
+                            if parent.object
+                                && let Some(node) = parent.nodes.last_mut()
+                                && node.children.is_empty()
+                            {
+                                node.children = frame.nodes;
+                            }
+                        }
+                        None => roots = frame.nodes,
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(text: &str, lang: &str, target: usize) -> Vec<(String, String)> {
+        chunk_sections(text, lang, target)
+            .expect("sections")
+            .into_iter()
+            .map(|s| (s.path, text[s.start..s.end].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn markdown_sections_follow_headings() {
+        let mut md =
+            String::from("# Tool\n\nIntro.\n\n## Install\n\n```sh\n# not a heading\n```\n");
+        md.push_str("\n### From source\n\n");
+        md.push_str(&"cargo build\n".repeat(4));
+        md.push_str("\n## Usage\n\nRun it.\n");
+        let chunks = paths(&md, "markdown", 8);
+        let names: Vec<&str> = chunks.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Tool",
+                "Tool > Install",
+                "Tool > Install > From source",
+                "Tool > Usage"
+            ]
+        );
+        assert!(chunks[0].1.starts_with("# Tool"));
+        assert!(chunks[1].1.contains("# not a heading"));
+        assert_eq!(
+            chunks.iter().map(|(_, t)| t.as_str()).collect::<String>(),
+            md
+        );
+
+        // A section that fits is kept whole with its subsections.
+        let whole = paths("# A\n\nx\n\n## B\n\ny\n", "markdown", 80);
+        assert_eq!(
+            whole,
+            [("A".to_string(), "# A\n\nx\n\n## B\n\ny\n".to_string())]
+        );
+        assert!(chunk_sections("no headings\n", "markdown", 80).is_none());
+
+        // Setext headings; front matter and thematic breaks are not headings.
+        let setext = "---\ntitle: x\n---\nTool\n====\n\nIntro.\n\n---\n\nUsage\n-----\n\nRun.\n";
+        let names: Vec<String> = paths(setext, "markdown", 8)
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect();
+        assert_eq!(names, ["", "Tool", "Tool > Usage"]);
+    }
+
+    #[test]
+    fn markdown_underline_on_the_first_line_is_not_a_heading() {
+        assert!(chunk_sections("===\nx\n", "markdown", 80).is_none());
+        // An unclosed `---` is not front matter, and has no line above it.
+        let names: Vec<String> = paths("---\n\n# Title\n\nText.\n", "markdown", 1)
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect();
+        assert_eq!(names.last().map(String::as_str), Some("Title"));
+    }
+
+    #[test]
+    fn config_sections_follow_keys() {
+        let toml = "model = \"o3\"\n\n# How commands run.\n[sandbox]\nmode = \"workspace-write\"\nnote = \"\"\"\n[not.a.table]\n\"\"\"\n\n[profiles.dev]\nmodel = \"gpt\"\n";
+        let chunks = paths(toml, "toml", 8);
+        let names: Vec<&str> = chunks.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(names, ["model", "sandbox", "profiles.dev"]);
+        assert!(chunks[1].1.starts_with("# How commands run.\n[sandbox]"));
+
+        let mut yaml = String::from("name: ci\non: [push]\njobs:\n  build:\n");
+        yaml.push_str(&"    - run: make\n".repeat(6));
+        yaml.push_str("  test:\n    script: |\n      key: not a key\n");
+        let names: Vec<String> = paths(&yaml, "yaml", 8)
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect();
+        assert_eq!(names, ["name, on", "jobs.build", "jobs.test"]);
+
+        let json = "{\n  \"name\": \"x\",\n  \"scripts\": {\n    \"build\": \"tsc\",\n    \"test\": \"jest\"\n  },\n  \"dependencies\": {\n    \"a\": \"1\"\n  }\n}\n";
+        let names: Vec<String> = paths(json, "json", 8).into_iter().map(|(p, _)| p).collect();
+        assert_eq!(names, ["name, scripts", "dependencies"]);
+        assert!(chunk_sections("[1, 2]\n", "json", 80).is_none());
+    }
+}
@@ -0,0 +1,394 @@
+//! Per-repo query server on a Unix socket, so short-lived CLI calls skip
+//! reloading the embedding model, vectors and graph on every query.
+//!
+//! The protocol is newline-delimited JSON: each request line (tagged by `op`)
+//! gets exactly one response line (tagged by `type`), and a connection may
+//! send any number of requests. The server exits after being idle.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::build::{BuildReport, Builder};
+use crate::search::{Hit, Retriever, SearchOptions, Timings};
+use crate::store::{self, Status};
+use crate::symbols::{SymbolMatch, find_symbols};
+
+/// Replies to everything but `refresh` are expected well within this.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    /// Search; inline `path:`/`lang:` terms are not parsed here, pass them in `opts`.
+    Query {
+        query: String,
+        #[serde(flatten)]
+        opts: SearchOptions,
+    },
+    Status,
+    Symbols {
+        name: String,
+        #[serde(default)]
+        kind: Option<String>,
+        #[serde(default)]
+        exact: bool,
+        #[serde(default = "default_symbol_limit")]
+        limit: usize,
+    },
+    /// Incremental rebuild in the server process (the model stays loaded).
+    Refresh,
+}
+
+fn default_symbol_limit() -> usize {
+    20
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Hits {
+        hits: Vec<Hit>,
+        #[serde(default)]
+        timings: Timings,
+    },
+    Status {
+        text: String,
+    },
+    Symbols {
+        symbols: Vec<SymbolMatch>,
+    },
+    Refreshed {
+        report: BuildReport,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Socket for the server of `root`: a directory private to the current user
+/// (`$XDG_RUNTIME_DIR`, else `codex-index-<uid>` in the temp dir) plus a hash
+/// of the root, which keeps it under the socket path length limit.
+pub fn socket_path(root: &Path) -> Result<PathBuf> {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let dir = std::env::temp_dir().join(format!("codex-index-{}", uid()));
+            match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+                Err(e) if e.kind() != ErrorKind::AlreadyExists => {
+                    return Err(e).with_context(|| format!("create {}", dir.display()));
+                }
+                _ => {}
+            }
+            dir
+        }
+    };
+    check_owner(&dir)?;
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let hash = store::sha256_hex(&root.to_string_lossy());
+    Ok(dir.join(format!("codex-index-{}.sock", &hash[..16])))
+}
+
+fn uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    unsafe { libc::getuid() }
+}
+
+// Another user's socket could serve forged hits, so `path` must belong to
+// the current user and, for a directory, be closed to everyone else.
+fn check_owner(path: &Path) -> Result<()> {
+    let meta =
+        std::fs::symlink_metadata(path).with_context(|| format!("stat {}", path.display()))?;
+    if meta.uid() != uid() {
+        bail!("{} belongs to another user", path.display());
+    }
+    if meta.is_dir() && meta.mode() & 0o077 != 0 {
+        bail!("{} is accessible to other users", path.display());
+    }
+    Ok(())
+}
+
+/// Serve queries for `root` until no request arrived for `idle`. Fails when
+/// another server already owns the socket.
+pub fn serve(root: &Path, idle: Duration) -> Result<()> {
+    let path = socket_path(root)?;
+    if path.symlink_metadata().is_ok() {
+        check_owner(&path)?;
+        if UnixStream::connect(&path).is_ok() {
+            bail!("index server already running at {}", path.display());
+        }
+        // Left behind by a server that did not exit cleanly.
+        let _ = std::fs::remove_file(&path);
+    }
+    let listener = UnixListener::bind(&path).with_context(|| format!("bind {}", path.display()))?;
+    let _socket = SocketFile(path.clone());
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+    // Load the index (and with it the embedding model on first query) up front.
+    let warm_root = root.to_path_buf();
+    std::thread::spawn(move || {
+        let _ =
+            Retriever::shared(&warm_root).and_then(|r| r.search("warm up", &Default::default()));
+    });
+
+    let state = Arc::new(ServerState {
+        last_active: Mutex::new(Instant::now()),
+        connections: AtomicUsize::new(0),
+        stopping: AtomicBool::new(false),
+        building: Mutex::new(()),
+    });
+
+    // Idle watchdog: flag the stop and wake the blocking accept with a dummy connection.
+    {
+        let state = state.clone();
+        let path = path.clone();
+        let tick = (idle / 4).clamp(Duration::from_millis(10), Duration::from_secs(1));
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(tick);
+                if state.connections.load(Ordering::SeqCst) == 0 && state.idle_for() >= idle {
+                    state.stopping.store(true, Ordering::SeqCst);
+                    let _ = UnixStream::connect(&path);
+                    break;
+                }
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        if state.stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = stream else { continue };
+        let state = state.clone();
+        let root = root.to_path_buf();
+        state.connections.fetch_add(1, Ordering::SeqCst);
+        std::thread::spawn(move || {
+            let _ = handle_connection(&root, &state, stream);
+            state.touch();
+            state.connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+struct ServerState {
+    last_active: Mutex<Instant>,
+    connections: AtomicUsize,
+    stopping: AtomicBool,
+    // One refresh at a time; queries keep being served from the shared retriever.
+    building: Mutex<()>,
+}
+
+impl ServerState {
+    fn touch(&self) {
+        if let Ok(mut t) = self.last_active.lock() {
+            *t = Instant::now();
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_active
+            .lock()
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+}
+
+// Removes the socket when the server stops.
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn handle_connection(root: &Path, state: &ServerState, stream: UnixStream) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        state.touch();
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => respond(root, state, request),
+            Err(e) => Response::Error {
+                message: format!("bad request: {e}"),
+            },
+        };
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+        state.touch();
+    }
+    Ok(())
+}
+
+fn respond(root: &Path, state: &ServerState, request: Request) -> Response {
+    let result = match request {
+        Request::Query { query, opts } => Retriever::shared(root)
+            .and_then(|r| r.search_timed(&query, &opts))
+            .map(|(hits, timings)| Response::Hits { hits, timings }),
+        Request::Status => Ok(Response::Status {
+            text: Status::describe(root),
+        }),
+        Request::Symbols {
+            name,
+            kind,
+            exact,
+            limit,
+        } => find_symbols(root, &name, kind.as_deref(), exact, limit)
+            .map(|symbols| Response::Symbols { symbols }),
+        Request::Refresh => {
+            let _building = state.building.lock();
+            Builder::refresh(root.to_path_buf())
+                .build()
+                .map(|report| Response::Refreshed { report })
+        }
+    };
+    result.unwrap_or_else(|e| Response::Error {
+        message: format!("{e:#}"),
+    })
+}
+
+/// Connection to a running [`serve`] process.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connect to the server for `root`; fails when none is running.
+    pub fn connect(root: &Path) -> Result<Self> {
+        let path = socket_path(root)?;
+        check_owner(&path).context("no index server")?;
+        let writer = UnixStream::connect(&path).context("connect to index server")?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self { reader, writer })
+    }
+
+    pub fn request(&mut self, request: &Request) -> Result<Response> {
+        let timeout = match request {
+            Request::Refresh => None,
+            _ => Some(READ_TIMEOUT),
+        };
+        self.writer.set_read_timeout(timeout)?;
+        writeln!(self.writer, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("index server closed the connection");
+        }
+        match serde_json::from_str(&line).context("parse index server response")? {
+            Response::Error { message } => bail!("index server: {message}"),
+            response => Ok(response),
+        }
+    }
+
+    pub fn query(&mut self, query: &str, opts: &SearchOptions) -> Result<Vec<Hit>> {
+        Ok(self.query_timed(query, opts)?.0)
+    }
+
+    /// [`Client::query`] plus the server-side search timings.
+    pub fn query_timed(
+        &mut self,
+        query: &str,
+        opts: &SearchOptions,
+    ) -> Result<(Vec<Hit>, Timings)> {
+        match self.request(&Request::Query {
+            query: query.to_string(),
+            opts: opts.clone(),
+        })? {
+            Response::Hits { hits, timings } => Ok((hits, timings)),
+            other => bail!("unexpected index server response: {other:?}"),
+        }
+    }
+
+    pub fn status(&mut self) -> Result<String> {
+        match self.request(&Request::Status)? {
+            Response::Status { text } => Ok(text),
+            other => bail!("unexpected index server response: {other:?}"),
+        }
+    }
+
+    pub fn symbols(
+        &mut self,
+        name: &str,
+        kind: Option<&str>,
+        exact: bool,
+        limit: usize,
+    ) -> Result<Vec<SymbolMatch>> {
+        match self.request(&Request::Symbols {
+            name: name.to_string(),
+            kind: kind.map(str::to_string),
+            exact,
+            limit,
+        })? {
+            Response::Symbols { symbols } => Ok(symbols),
+            other => bail!("unexpected index server response: {other:?}"),
+        }
+    }
+
+    pub fn refresh(&mut self) -> Result<BuildReport> {
+        match self.request(&Request::Refresh)? {
+            Response::Refreshed { report } => Ok(report),
+            other => bail!("unexpected index server response: {other:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_lines_round_trip() {
+        let line =
+            r#"{"op":"query","query":"spawn watcher","k":3,"mode":"lexical","langs":["rust"]}"#;
+        let Request::Query { query, opts } = serde_json::from_str(line).expect("parse") else {
+            panic!("expected a query");
+        };
+        assert_eq!(query, "spawn watcher");
+        assert_eq!(opts.k, 3);
+        assert_eq!(opts.mode, crate::SearchMode::Lexical);
+        assert_eq!(opts.langs, vec!["rust".to_string()]);
+        assert!(opts.paths.is_empty());
+
+        let line = r#"{"op":"symbols","name":"Builder"}"#;
+        let Request::Symbols { limit, exact, .. } = serde_json::from_str(line).expect("parse")
+        else {
+            panic!("expected symbols");
+        };
+        assert_eq!((limit, exact), (20, false));
+    }
+
+    #[test]
+    fn serves_status_and_exits_when_idle() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path().to_path_buf();
+        let server = {
+            let root = root.clone();
+            std::thread::spawn(move || serve(&root, Duration::from_millis(300)))
+        };
+        let mut client = (0..100)
+            .find_map(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                Client::connect(&root).ok()
+            })
+            .expect("server up");
+        assert_eq!(client.status().expect("status"), "Index: Missing");
+        assert!(client.query("anything", &SearchOptions::default()).is_err());
+        drop(client);
+
+        server.join().expect("join").expect("serve");
+        let path = socket_path(&root).expect("socket path");
+        assert!(!path.exists());
+        let dir = path.parent().expect("socket dir").metadata().expect("stat");
+        assert_eq!((dir.uid(), dir.mode() & 0o077), (uid(), 0));
+    }
+}
@@ -0,0 +1,366 @@
+//! "More like this": the chunks nearest to an indexed chunk's stored vector
+//! (`index similar`, `/similar`), and clusters of near-identical chunks as
+//! refactoring candidates (`index dupes`). Neither embeds anything.
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::search::{
+    Hit, Index, QueryFilter, Retriever, SearchOptions, TextFormat, format_hits_text, select,
+};
+use crate::store::{self, MetaRow};
+
+/// Default `--min-score` of `index dupes`.
+pub const DEFAULT_DUPE_SCORE: f32 = 0.95;
+
+// Neighbours looked up per chunk when clustering duplicates.
+const DUPE_NEIGHBOURS: usize = 8;
+
+/// Split `<path>:<line>` into a repo-relative path and a 1-based line. The
+/// path may be relative to the repo root or absolute.
+pub fn parse_location(root: &Path, location: &str) -> Result<(String, usize)> {
+    let (path, line) = location
+        .rsplit_once(':')
+        .with_context(|| format!("expected <path>:<line>, got `{location}`"))?;
+    let line: usize = line
+        .parse()
+        .with_context(|| format!("invalid line `{line}` in `{location}`"))?;
+    let rel = if Path::new(path).is_absolute() {
+        store::rel(root, Path::new(path))
+    } else {
+        path.trim_start_matches("./").to_string()
+    };
+    Ok((rel, line.max(1)))
+}
+
+/// Result of [`Retriever::similar`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Similar {
+    /// The chunk at the requested location.
+    pub source: Hit,
+    /// Its nearest neighbours from other files, best first.
+    pub hits: Vec<Hit>,
+}
+
+/// Settings for [`Retriever::dupes`].
+#[derive(Debug, Clone)]
+pub struct DupesOptions {
+    /// Lowest cosine similarity that links two chunks.
+    pub min_score: f32,
+    /// Chunks shorter than this many lines are ignored.
+    pub min_lines: usize,
+    /// Groups returned, most redundant lines first.
+    pub limit: usize,
+    pub paths: Vec<String>,
+    pub excludes: Vec<String>,
+    pub langs: Vec<String>,
+}
+
+impl Default for DupesOptions {
+    fn default() -> Self {
+        Self {
+            min_score: DEFAULT_DUPE_SCORE,
+            min_lines: 5,
+            limit: 20,
+            paths: Vec::new(),
+            excludes: Vec::new(),
+            langs: Vec::new(),
+        }
+    }
+}
+
+/// Near-identical chunks: each is linked to another member at or above the
+/// minimum score.
+#[derive(Debug, Clone, Serialize)]
+pub struct DupeGroup {
+    /// Weakest link in the group.
+    pub score: f32,
+    /// Lines that would go by keeping only the longest copy.
+    pub redundant_lines: usize,
+    /// Members in path and line order; `score` is each one's best link.
+    pub chunks: Vec<Hit>,
+}
+
+impl Retriever {
+    /// Chunks from other files nearest to the one covering `line` of `path`
+    /// (the narrowest, when several do). `opts` supplies `k`, the path/lang
+    /// filters and diversity; overlapping neighbours are merged as in search.
+    pub fn similar(&self, path: &str, line: usize, opts: &SearchOptions) -> Result<Similar> {
+        let index = self.index();
+        let rows = index.rows();
+        if !rows.iter().any(|r| r.path == path) {
+            bail!("{path} is not indexed");
+        }
+        let pos = (0..rows.len())
+            .filter(|&p| rows[p].path == path && rows[p].start <= line && line <= rows[p].end)
+            .min_by_key(|&p| rows[p].end - rows[p].start)
+            .with_context(|| format!("no indexed chunk covers {path}:{line}"))?;
+        let filter = QueryFilter::new(opts);
+        let qv = index.row_vector(pos);
+        let candidates = index
+            .nearest(&qv, opts.k * 2, |p| {
+                rows[p].path != path && filter.matches(&rows[p])
+            })
+            .into_iter()
+            .map(|(p, score)| (p, index.hit(p, score, score, 0.0)))
+            .collect();
+        let hits = select(
+            candidates,
+            opts.k,
+            opts.diversity,
+            |a, b| index.row_similarity(a, b),
+            |hit| index.span_preview(hit),
+        );
+        Ok(Similar {
+            source: index.hit(pos, 1.0, 1.0, 0.0),
+            hits,
+        })
+    }
+
+    /// Groups of near-identical chunks, most redundant lines first. Chunks are
+    /// linked to their nearest neighbours scoring at least `min_score` (never
+    /// to an overlapping chunk of the same file) and linked chunks are grouped
+    /// transitively.
+    pub fn dupes(&self, opts: &DupesOptions) -> Vec<DupeGroup> {
+        let index = self.index();
+        let rows = index.rows();
+        let filter = QueryFilter::new(&SearchOptions {
+            paths: opts.paths.clone(),
+            excludes: opts.excludes.clone(),
+            langs: opts.langs.clone(),
+            ..SearchOptions::default()
+        });
+        let eligible: Vec<bool> = rows
+            .iter()
+            .map(|r| r.end + 1 - r.start >= opts.min_lines.max(1) && filter.matches(r))
+            .collect();
+        let mut links: Vec<(usize, usize, f32)> = Vec::new();
+        for pos in (0..rows.len()).filter(|&p| eligible[p]) {
+            let qv = index.row_vector(pos);
+            let near = index.nearest(&qv, DUPE_NEIGHBOURS, |o| {
+                o != pos && eligible[o] && !overlaps(&rows[pos], &rows[o])
+            });
+            links.extend(
+                near.into_iter()
+                    .take_while(|(_, score)| *score >= opts.min_score)
+                    .map(|(other, score)| (pos, other, score)),
+            );
+        }
+        let components = cluster(rows.len(), &links);
+        let mut component: HashMap<usize, usize> = HashMap::new();
+        for (c, members) in components.iter().enumerate() {
+            component.extend(members.iter().map(|&pos| (pos, c)));
+        }
+        let mut component_links = vec![Vec::new(); components.len()];
+        for link in &links {
+            component_links[component[&link.0]].push(*link);
+        }
+        let mut groups = components
+            .iter()
+            .zip(&component_links)
+            .filter_map(|(members, links)| group(index, members, links))
+            .collect::<Vec<_>>();
+        groups.sort_by(|a, b| {
+            b.redundant_lines
+                .cmp(&a.redundant_lines)
+                .then(b.score.total_cmp(&a.score))
+        });
+        groups.truncate(opts.limit);
+        groups
+    }
+}
+
+// Same file and overlapping lines.
+fn overlaps(a: &MetaRow, b: &MetaRow) -> bool {
+    a.path == b.path && a.start <= b.end && b.start <= a.end
+}
+
+// Connected components of `links` over `n` rows (union-find), each in row
+// order; rows without links are left out.
+fn cluster(n: usize, links: &[(usize, usize, f32)]) -> Vec<Vec<usize>> {
+    fn find(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+    let mut parent: Vec<usize> = (0..n).collect();
+    for &(a, b, _) in links {
+        let (ra, rb) = (find(&mut parent, a), find(&mut parent, b));
+        if ra != rb {
+            parent[ra.max(rb)] = ra.min(rb);
+        }
+    }
+    let mut components: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    let mut linked: Vec<usize> = links.iter().flat_map(|&(a, b, _)| [a, b]).collect();
+    linked.sort_unstable();
+    linked.dedup();
+    for pos in linked {
+        let root = find(&mut parent, pos);
+        components.entry(root).or_default().push(pos);
+    }
+    components.into_values().collect()
+}
+
+// One report entry for the rows of a component and the links between them.
+// Overlapping members of the same file are merged; None when fewer than two
+// chunks remain.
+fn group(index: &Index, members: &[usize], links: &[(usize, usize, f32)]) -> Option<DupeGroup> {
+    let mut best: HashMap<usize, f32> = HashMap::new();
+    let mut score = f32::MAX;
+    for &(a, b, s) in links {
+        score = score.min(s);
+        for pos in [a, b] {
+            let e = best.entry(pos).or_insert(s);
+            *e = e.max(s);
+        }
+    }
+    let mut hits: Vec<Hit> = members
+        .iter()
+        .map(|&p| {
+            let s = best.get(&p).copied().unwrap_or(score);
+            index.hit(p, s, s, 0.0)
+        })
+        .collect();
+    hits.sort_by(|a, b| a.path.cmp(&b.path).then(a.start.cmp(&b.start)));
+    let mut chunks: Vec<Hit> = Vec::new();
+    for hit in hits {
+        match chunks.last_mut() {
+            Some(prev) if prev.path == hit.path && hit.start <= prev.end => {
+                prev.end = prev.end.max(hit.end);
+                prev.score = prev.score.max(hit.score);
+                prev.semantic = prev.score;
+            }
+            _ => chunks.push(hit),
+        }
+    }
+    if chunks.len() < 2 {
+        return None;
+    }
+    let lines: Vec<usize> = chunks.iter().map(|h| h.end + 1 - h.start).collect();
+    let redundant_lines = lines.iter().sum::<usize>() - lines.iter().max().copied().unwrap_or(0);
+    Some(DupeGroup {
+        score,
+        redundant_lines,
+        chunks,
+    })
+}
+
+/// `Similar to path:start-end (lang)` followed by the neighbours as in
+/// [`format_hits_text`].
+pub fn format_similar_text(similar: &Similar, fmt: &TextFormat) -> String {
+    let s = &similar.source;
+    let mut out = format!("Similar to {}:{}-{} ({})\n", s.path, s.start, s.end, s.lang);
+    if similar.hits.is_empty() {
+        out.push_str("No similar chunks in other files.\n");
+    } else {
+        out.push_str(&format_hits_text(&similar.hits, fmt));
+    }
+    out
+}
+
+/// `[n] N copies, score, R redundant lines` per group, then one
+/// `path:start-end (lang)` line per copy.
+pub fn format_dupes_text(groups: &[DupeGroup]) -> String {
+    if groups.is_empty() {
+        return "No near-duplicate chunks found.\n".to_string();
+    }
+    let mut out = String::new();
+    for (rank, g) in groups.iter().enumerate() {
+        out.push_str(&format!(
+            "[{rank}] {} copies, {:.3}, {} redundant lines\n",
+            g.chunks.len(),
+            g.score,
+            g.redundant_lines
+        ));
+        for hit in &g.chunks {
+            out.push_str(&format!(
+                "    {}:{}-{} ({})\n",
+                hit.path, hit.start, hit.end, hit.lang
+            ));
+        }
+    }
+    out
+}
+
+/// Run a `/similar` command line (`<path>:<line> [lang:L] [path:GLOB]
+/// [exclude:GLOB] [-k N]`) against the index of `root` and render the result
+/// as text.
+pub fn similar_command(root: &Path, input: &str) -> String {
+    let mut opts = SearchOptions::default();
+    let rest = opts.take_command_flags(input);
+    let Some(location) = rest.split_whitespace().next() else {
+        return "Usage: /similar <path>:<line> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]"
+            .to_string();
+    };
+    let found = parse_location(root, location).and_then(|(path, line)| {
+        Retriever::shared(root).and_then(|r| r.similar(&path, line, &opts))
+    });
+    match found {
+        Ok(similar) => {
+            let fmt = TextFormat {
+                snippets: true,
+                ..TextFormat::default()
+            };
+            format_similar_text(&similar, &fmt)
+        }
+        Err(e) => format!("Error: {e:#}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, Builder};
+    use std::fs;
+
+    #[test]
+    fn links_cluster_transitively() {
+        let links = [(0, 3, 0.97), (3, 5, 0.96), (5, 3, 0.96), (1, 2, 0.99)];
+        assert_eq!(cluster(6, &links), [vec![0, 3, 5], vec![1, 2]]);
+        assert!(cluster(4, &[]).is_empty());
+
+        let root = Path::new("/repo");
+        assert_eq!(
+            parse_location(root, "./src/lib.rs:42").unwrap(),
+            ("src/lib.rs".to_string(), 42)
+        );
+        assert_eq!(
+            parse_location(root, "/repo/a/b.rs:7").unwrap(),
+            ("a/b.rs".to_string(), 7)
+        );
+        assert!(parse_location(root, "src/lib.rs").is_err());
+    }
+
+    #[test]
+    fn similar_fills_k_from_a_narrow_filter() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).expect("mkdir");
+        let rust = "pub fn handle(request: Request) -> Response {\n    route(request)\n}\n";
+        for i in 0..40 {
+            fs::write(root.join(format!("src/r{i}.rs")), rust).expect("write");
+        }
+        for i in 0..4 {
+            let python = format!("def load_{i}(path):\n    return open(path).read()\n");
+            fs::write(root.join(format!("src/p{i}.py")), python).expect("write");
+        }
+        Builder::new(root, BuildOptions::fake("fake-small"))
+            .build()
+            .expect("build");
+        let opts = SearchOptions {
+            k: 3,
+            langs: vec!["python".into()],
+            ..SearchOptions::default()
+        };
+        let similar = Retriever::open(root)
+            .expect("open")
+            .similar("src/r0.rs", 1, &opts)
+            .expect("similar");
+        assert_eq!(similar.hits.len(), 3);
+        assert!(similar.hits.iter().all(|h| h.lang == "python"));
+    }
+}
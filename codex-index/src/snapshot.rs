@@ -0,0 +1,499 @@
+//! Portable index snapshots: `index export` packs the index files of a commit
+//! into a `.tar.zst` with a digest list; `index import` validates one,
+//! installs it under the local root and catches up to HEAD incrementally.
+//!
+//! The digest list is signed with HMAC-SHA256 over the commit and file
+//! digests when `CODEX_INDEX_SNAPSHOT_KEY` is set. Otherwise it only carries a
+//! plain SHA-256 checksum, which guards against corruption but says nothing
+//! about who made the snapshot.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::build::{BuildReport, Builder};
+use crate::lexical::LEXICAL_FILE;
+use crate::store::{
+    self, BuildLock, EXACT_FILE, HNSW_BASENAME, MANIFEST_FILE, META_FILE, VECTORS_FILE,
+};
+use crate::vectors;
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const SNAPSHOT_FORMAT: u32 = 1;
+const KEY_ENV: &str = "CODEX_INDEX_SNAPSHOT_KEY";
+
+// Index files carried by a snapshot; analytics and the lock stay local.
+fn snapshot_files() -> Vec<String> {
+    vec![
+        MANIFEST_FILE.to_string(),
+        VECTORS_FILE.to_string(),
+        EXACT_FILE.to_string(),
+        META_FILE.to_string(),
+        LEXICAL_FILE.to_string(),
+        format!("{HNSW_BASENAME}.hnsw.graph"),
+        format!("{HNSW_BASENAME}.hnsw.data"),
+    ]
+}
+
+/// Contents of `snapshot.json` inside the archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub format: u32,
+    /// Commit the index was built at (`RepoInfo.git_sha`).
+    pub git_sha: Option<String>,
+    pub embedder: String,
+    pub model: String,
+    pub dim: usize,
+    pub chunks: usize,
+    pub created_at: String,
+    /// sha256 of every packed file.
+    pub files: BTreeMap<String, String>,
+    /// `hmac-sha256` (keyed signature) or `sha256` (unsigned checksum).
+    pub signature_alg: String,
+    pub signature: String,
+}
+
+impl SnapshotInfo {
+    fn payload(&self) -> String {
+        let mut s = format!(
+            "codex-index-snapshot/{}\n{}\n",
+            self.format,
+            self.git_sha.as_deref().unwrap_or("-")
+        );
+        for (name, digest) in &self.files {
+            s.push_str(&format!("{name} {digest}\n"));
+        }
+        s
+    }
+
+    fn sign(&mut self, key: Option<&str>) {
+        let payload = self.payload();
+        (self.signature_alg, self.signature) = match key {
+            Some(key) => (
+                "hmac-sha256".into(),
+                hmac_sha256(key.as_bytes(), payload.as_bytes()),
+            ),
+            None => ("sha256".into(), store::sha256_hex(&payload)),
+        };
+    }
+
+    fn check_signature(&self, key: Option<&str>) -> Result<()> {
+        let payload = self.payload();
+        let expected = match (self.signature_alg.as_str(), key) {
+            ("hmac-sha256", Some(key)) => hmac_sha256(key.as_bytes(), payload.as_bytes()),
+            ("hmac-sha256", None) => bail!("snapshot is signed; set {KEY_ENV} to verify it"),
+            ("sha256", Some(_)) => {
+                bail!("snapshot is unsigned but {KEY_ENV} is set; refusing to import it")
+            }
+            ("sha256", None) => store::sha256_hex(&payload),
+            (alg, _) => bail!("unknown snapshot signature `{alg}`"),
+        };
+        if expected != self.signature {
+            let what = if self.is_signed() {
+                "signature"
+            } else {
+                "checksum"
+            };
+            bail!("snapshot {what} does not match its contents");
+        }
+        Ok(())
+    }
+
+    /// True for a keyed signature, false for a plain checksum.
+    pub fn is_signed(&self) -> bool {
+        self.signature_alg == "hmac-sha256"
+    }
+}
+
+fn hmac_sha256(key: &[u8], msg: &[u8]) -> String {
+    const BLOCK: usize = 64;
+    let mut k = if key.len() > BLOCK {
+        Sha256::digest(key).to_vec()
+    } else {
+        key.to_vec()
+    };
+    k.resize(BLOCK, 0);
+    let ipad: Vec<u8> = k.iter().map(|b| b ^ 0x36).collect();
+    let opad: Vec<u8> = k.iter().map(|b| b ^ 0x5c).collect();
+    let inner = Sha256::new()
+        .chain_update(&ipad)
+        .chain_update(msg)
+        .finalize();
+    hex::encode(
+        Sha256::new()
+            .chain_update(&opad)
+            .chain_update(inner)
+            .finalize(),
+    )
+}
+
+fn signing_key() -> Option<String> {
+    std::env::var(KEY_ENV).ok().filter(|k| !k.is_empty())
+}
+
+/// Pack the index of `root` into a zstd-compressed tar at `out`.
+pub fn export(root: &Path, out: &Path) -> Result<SnapshotInfo> {
+    let dir = store::index_dir(root);
+    let manifest = store::read_manifest(root).context("no index to export; run `index build`")?;
+    if !store::verify(root).unwrap_or(false) {
+        bail!("index does not match its manifest; run `index verify --repair` first");
+    }
+    let mut files = BTreeMap::new();
+    for name in snapshot_files() {
+        let path = dir.join(&name);
+        if path.is_file() {
+            files.insert(name, store::sha256_file(&path)?);
+        }
+    }
+    let mut info = SnapshotInfo {
+        format: SNAPSHOT_FORMAT,
+        git_sha: manifest.repo.git_sha.clone(),
+        embedder: manifest.embedder.as_str().to_string(),
+        model: manifest.model.clone(),
+        dim: manifest.dim,
+        chunks: manifest.counts.chunks,
+        created_at: store::now_iso(),
+        files,
+        signature_alg: String::new(),
+        signature: String::new(),
+    };
+    info.sign(signing_key().as_deref());
+
+    let file = File::create(out).with_context(|| format!("create {}", out.display()))?;
+    let mut tar = tar::Builder::new(zstd::Encoder::new(file, 0)?.auto_finish());
+    let json = serde_json::to_vec_pretty(&info)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, SNAPSHOT_FILE, json.as_slice())?;
+    for name in info.files.keys() {
+        tar.append_path_with_name(dir.join(name), name)?;
+    }
+    tar.into_inner()?;
+    Ok(info)
+}
+
+/// Outcome of [`import`].
+#[derive(Debug, Clone)]
+pub struct ImportReport {
+    pub snapshot: SnapshotInfo,
+    /// Files changed between the snapshot commit and the working tree; None
+    /// when the commit is unknown locally and every file was re-checked.
+    pub changed_files: Option<usize>,
+    /// The catch-up build, unless it was skipped.
+    pub catch_up: Option<BuildReport>,
+}
+
+/// Install the snapshot at `archive` as the index of `root`. Refuses to
+/// replace an index built with a different embedder, model or dim unless
+/// `force`. The embedding endpoint stays the local one (that of the current
+/// index, else the provider default) unless `snapshot_endpoint`. With
+/// `catch_up`, files changed since the snapshot commit are then re-indexed.
+pub fn import(
+    root: &Path,
+    archive: &Path,
+    force: bool,
+    snapshot_endpoint: bool,
+    catch_up: bool,
+) -> Result<ImportReport> {
+    let dir = store::index_dir(root);
+    fs::create_dir_all(&dir)?;
+    let staging = dir.with_file_name("index.import");
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(&staging)?;
+    let result = unpack_and_install(root, archive, &staging, force, snapshot_endpoint);
+    let _ = fs::remove_dir_all(&staging);
+    let snapshot = result?;
+
+    let mut report = ImportReport {
+        snapshot,
+        changed_files: None,
+        catch_up: None,
+    };
+    if catch_up {
+        let builder = Builder::refresh(root.to_path_buf());
+        let changed = report
+            .snapshot
+            .git_sha
+            .as_deref()
+            .and_then(|sha| changed_since(root, sha));
+        report.catch_up = Some(match &changed {
+            Some(paths) => builder.update(paths)?,
+            None => builder.build()?,
+        });
+        report.changed_files = changed.map(|p| p.len());
+    }
+    Ok(report)
+}
+
+fn unpack_and_install(
+    root: &Path,
+    archive: &Path,
+    staging: &Path,
+    force: bool,
+    snapshot_endpoint: bool,
+) -> Result<SnapshotInfo> {
+    let file = File::open(archive).with_context(|| format!("open {}", archive.display()))?;
+    let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+    let allowed = snapshot_files();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        // Flat archive of known names only; nothing can land outside staging.
+        if name != SNAPSHOT_FILE && !allowed.contains(&name) {
+            bail!("unexpected file `{name}` in snapshot");
+        }
+        // Links could point outside staging or at devices; only plain files.
+        if !entry.header().entry_type().is_file() {
+            bail!("`{name}` in snapshot is not a regular file");
+        }
+        entry.unpack(staging.join(&name))?;
+    }
+
+    let info: SnapshotInfo = serde_json::from_slice(
+        &fs::read(staging.join(SNAPSHOT_FILE)).context("snapshot.json missing")?,
+    )?;
+    if info.format != SNAPSHOT_FORMAT {
+        bail!("unsupported snapshot format {}", info.format);
+    }
+    info.check_signature(signing_key().as_deref())?;
+    for (name, digest) in &info.files {
+        if &store::sha256_file(staging.join(name))? != digest {
+            bail!("{name} does not match the snapshot digest");
+        }
+    }
+
+    let mut manifest: store::Manifest =
+        serde_json::from_slice(&fs::read(staging.join(MANIFEST_FILE))?)?;
+    if manifest.embedder.as_str() != info.embedder
+        || manifest.model != info.model
+        || manifest.dim != info.dim
+    {
+        bail!("snapshot manifest does not match snapshot.json");
+    }
+    if manifest.dim > 0 {
+        let (ids, data) = vectors::load(staging.join(VECTORS_FILE))?;
+        if data.len() != ids.len() * manifest.dim || ids.len() != manifest.counts.chunks {
+            bail!("snapshot vectors do not match its manifest dim/count");
+        }
+        for row in store::load_meta(staging.join(META_FILE))?.values() {
+            let p = Path::new(&row.path);
+            if p.is_absolute() || p.components().any(|c| c.as_os_str() == "..") {
+                bail!("snapshot row path `{}` escapes the repository", row.path);
+            }
+        }
+    }
+    let local = store::read_manifest(root).ok();
+    if let Some(local) = &local
+        && !force
+        && (local.embedder != manifest.embedder
+            || local.model != manifest.model
+            || local.dim != manifest.dim)
+    {
+        bail!(
+            "snapshot uses {} {} ({}-D) but the local index uses {} {} ({}-D); pass --force to replace it",
+            info.embedder,
+            info.model,
+            info.dim,
+            local.embedder.as_str(),
+            local.model,
+            local.dim
+        );
+    }
+
+    // Queries send their text to the endpoint, so a snapshot from elsewhere
+    // does not get to choose it.
+    if !snapshot_endpoint {
+        manifest.embed_url = match local {
+            Some(local) if local.embedder == manifest.embedder => local.embed_url,
+            _ => manifest.embedder.default_url(),
+        };
+    }
+    // Rows are repo-relative; only the recorded root needs rebasing.
+    manifest.repo.root = root.to_string_lossy().into();
+    let dir = store::index_dir(root);
+    let _guard = BuildLock::acquire(root).context("an index build is running")?;
+    let _ = fs::remove_file(dir.join(MANIFEST_FILE));
+    for name in snapshot_files() {
+        if name == MANIFEST_FILE {
+            continue;
+        }
+        let src = staging.join(&name);
+        if src.is_file() {
+            fs::rename(&src, dir.join(&name))?;
+        } else {
+            let _ = fs::remove_file(dir.join(&name));
+        }
+    }
+    store::write_manifest(root, &manifest)?;
+    Ok(info)
+}
+
+// Paths changed between `sha` and the working tree (committed, staged,
+// unstaged and untracked), or None when `sha` is not known locally.
+fn changed_since(root: &Path, sha: &str) -> Option<Vec<PathBuf>> {
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .args(args)
+            .current_dir(root)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).to_string())
+    };
+    git(&["cat-file", "-e", &format!("{sha}^{{commit}}")])?;
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for out in [
+        git(&["diff", "--name-only", "--no-renames", sha])?,
+        git(&["ls-files", "--others", "--exclude-standard"])?,
+    ] {
+        paths.extend(out.lines().filter(|l| !l.is_empty()).map(PathBuf::from));
+    }
+    paths.sort();
+    paths.dedup();
+    Some(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{Checksums, ChunkCfg, Counts, Manifest, MetaRow, RepoInfo};
+    use crate::vectors::Storage;
+
+    fn write_index(root: &Path) {
+        let dir = store::index_dir(root);
+        fs::create_dir_all(&dir).expect("mkdir");
+        vectors::write(dir.join(VECTORS_FILE), Storage::F32, 2, &[7], &[0.6, 0.8])
+            .expect("vectors");
+        let row = MetaRow {
+            id: 7,
+            preview: "fn a() {}".into(),
+            ..MetaRow::fixture("src/a.rs", 1, 1)
+        };
+        store::write_meta(dir.join(META_FILE), &[row]).expect("meta");
+        let manifest = Manifest {
+            index_version: store::INDEX_VERSION,
+            engine: "fastembed+hnsw".into(),
+            embedder: Default::default(),
+            model: "bge-small".into(),
+            embed_url: None,
+            dim: 2,
+            storage: Storage::F32,
+            rescore: false,
+            metric: "cosine".into(),
+            chunk_mode: "auto".into(),
+            chunk_header: String::new(),
+            secrets: String::new(),
+            chunk: ChunkCfg {
+                lines: 50,
+                overlap: 10,
+            },
+            limits: Default::default(),
+            repo: RepoInfo {
+                root: root.to_string_lossy().into(),
+                git_sha: Some("abc123".into()),
+            },
+            counts: Counts {
+                files: 1,
+                chunks: 1,
+                redacted: 0,
+            },
+            checksums: Checksums {
+                vectors_hnsw: store::sha256_file(dir.join(VECTORS_FILE)).expect("sha"),
+                meta_jsonl: store::sha256_file(dir.join(META_FILE)).expect("sha"),
+            },
+            created_at: store::now_iso(),
+            last_refresh: store::now_iso(),
+        };
+        store::write_manifest(root, &manifest).expect("manifest");
+    }
+
+    #[test]
+    fn export_then_import_rebases_onto_the_new_root() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        fs::create_dir_all(&dst).expect("mkdir");
+        write_index(&src);
+        let mut built = store::read_manifest(&src).expect("manifest");
+        built.embed_url = Some("http://embed.example".into());
+        store::write_manifest(&src, &built).expect("manifest");
+        let archive = dir.path().join("idx.tar.zst");
+        let info = export(&src, &archive).expect("export");
+        assert_eq!(info.git_sha.as_deref(), Some("abc123"));
+        assert!(info.files.contains_key(META_FILE));
+
+        let report = import(&dst, &archive, false, false, false).expect("import");
+        assert!(report.catch_up.is_none());
+        let manifest = store::read_manifest(&dst).expect("manifest");
+        assert_eq!(manifest.repo.root, dst.to_string_lossy());
+        assert_eq!(manifest.repo.git_sha.as_deref(), Some("abc123"));
+        assert_eq!(manifest.embed_url, None);
+        assert!(store::verify(&dst).expect("verify"));
+        assert!(!dst.join(".codex/index.import").exists());
+
+        // A different model is refused unless forced.
+        let mut local = manifest;
+        local.model = "bge-large".into();
+        store::write_manifest(&dst, &local).expect("manifest");
+        assert!(import(&dst, &archive, false, false, false).is_err());
+        assert!(import(&dst, &archive, true, false, false).is_ok());
+        import(&dst, &archive, true, true, false).expect("import");
+        let manifest = store::read_manifest(&dst).expect("manifest");
+        assert_eq!(manifest.embed_url.as_deref(), Some("http://embed.example"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn import_rejects_links() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let archive = dir.path().join("idx.tar.zst");
+        let file = File::create(&archive).expect("create");
+        let mut tar = tar::Builder::new(zstd::Encoder::new(file, 0).expect("zstd").auto_finish());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        tar.append_link(&mut header, META_FILE, "/dev/zero")
+            .expect("append");
+        tar.into_inner().expect("finish");
+        let err = import(dir.path(), &archive, false, false, false).expect_err("link");
+        assert!(err.to_string().contains("not a regular file"), "{err}");
+    }
+
+    #[test]
+    fn signature_covers_commit_and_digests() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let mut info = SnapshotInfo {
+            format: SNAPSHOT_FORMAT,
+            git_sha: Some("abc".into()),
+            embedder: "fastembed".into(),
+            model: "bge-small".into(),
+            dim: 384,
+            chunks: 1,
+            created_at: String::new(),
+            files: BTreeMap::from([(META_FILE.to_string(), "d1".to_string())]),
+            signature_alg: String::new(),
+            signature: String::new(),
+        };
+        info.sign(Some("k"));
+        assert!(info.check_signature(Some("k")).is_ok());
+        assert!(info.check_signature(Some("other")).is_err());
+        assert!(info.check_signature(None).is_err());
+
+        let mut tampered = info.clone();
+        tampered.git_sha = Some("abd".into());
+        assert!(tampered.check_signature(Some("k")).is_err());
+
+        info.sign(None);
+        assert!(info.check_signature(None).is_ok());
+        info.files.insert(VECTORS_FILE.into(), "d2".into());
+        assert!(info.check_signature(None).is_err());
+    }
+}
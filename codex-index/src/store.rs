@@ -0,0 +1,654 @@
+//! On-disk layout under `.codex/index`: manifest, flat vector store, chunk
+//! metadata, analytics, ignore patterns and the build lock.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+use crate::embed::Provider;
+use crate::symbols::Symbol;
+use crate::vectors::Storage;
+
+pub const INDEX_DIR: &str = ".codex/index";
+pub(crate) const IGNORE_FILE: &str = ".index-ignore";
+pub(crate) const VECTORS_FILE: &str = "vectors.hnsw"; // flat vectors + ids (linear scan + id map for HNSW)
+pub(crate) const EXACT_FILE: &str = "vectors.exact"; // f32 copy of a quantized store, for re-scoring
+pub(crate) const HNSW_BASENAME: &str = "vectors"; // creates vectors.hnsw.graph + vectors.hnsw.data
+pub(crate) const META_FILE: &str = "meta.jsonl";
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+pub(crate) const ANALYTICS_FILE: &str = "analytics.json";
+pub(crate) const LOCK_FILE: &str = "lock";
+// Bumped when chunking or meta rows change; older indexes are re-chunked on the
+// next build (vectors of identical chunks are still reused).
+pub(crate) const INDEX_VERSION: u32 = 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub index_version: u32,
+    pub engine: String,
+    /// Embedding provider; indexes from before providers existed used fastembed.
+    #[serde(default)]
+    pub embedder: Provider,
+    pub model: String,
+    /// Provider endpoint for HTTP embedders (never holds credentials).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embed_url: Option<String>,
+    pub dim: usize,
+    /// Encoding of the vector store; indexes from before quantization used f32.
+    #[serde(default)]
+    pub storage: Storage,
+    /// An exact f32 copy of a quantized store is kept to re-score top candidates.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rescore: bool,
+    pub metric: String,
+    pub chunk_mode: String,
+    /// Format of the header embedded with each chunk, `none` when built
+    /// without headers; empty for indexes from before chunk headers.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub chunk_header: String,
+    /// Secret scrubbing rules the chunks were built with (`builtin/N`, plus a
+    /// digest of `.index-secrets`); empty for indexes from before scrubbing.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secrets: String,
+    pub chunk: ChunkCfg,
+    /// File limits of the build, reused by background refreshes.
+    #[serde(default)]
+    pub limits: Limits,
+    pub repo: RepoInfo,
+    pub counts: Counts,
+    pub checksums: Checksums,
+    pub created_at: String,
+    pub last_refresh: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkCfg {
+    pub lines: usize,
+    pub overlap: usize,
+}
+
+/// Files larger than this are not indexed unless `--max-file-size` says otherwise.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Limits {
+    /// Largest file indexed, in bytes.
+    pub max_file_size: u64,
+    /// Most files indexed; 0 = no limit.
+    #[serde(default)]
+    pub max_files: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_files: 0,
+        }
+    }
+}
+
+/// Parse a size like `512K`, `5M`, `1G` or a plain byte count (`K`/`M`/`G`
+/// are powers of 1024; a trailing `B`/`iB` is accepted).
+pub fn parse_size(s: &str) -> Result<u64> {
+    let t = s.trim();
+    let split = t.find(|c: char| !c.is_ascii_digit()).unwrap_or(t.len());
+    let (n, unit) = t.split_at(split);
+    let shift = match unit.trim_end_matches("iB").trim_end_matches('B') {
+        "" => 0,
+        "K" | "k" => 10,
+        "M" | "m" => 20,
+        "G" | "g" => 30,
+        _ => bail!("invalid size `{s}` (expected e.g. 512K, 5M or 1G)"),
+    };
+    let n: u64 = n
+        .parse()
+        .with_context(|| format!("invalid size `{s}` (expected e.g. 512K, 5M or 1G)"))?;
+    n.checked_mul(1 << shift)
+        .with_context(|| format!("size `{s}` is too large"))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RepoInfo {
+    pub root: String,
+    pub git_sha: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Counts {
+    pub files: usize,
+    pub chunks: usize,
+    /// Chunks with secrets redacted before embedding.
+    #[serde(default)]
+    pub redacted: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Checksums {
+    pub vectors_hnsw: String,
+    pub meta_jsonl: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct MetaRow {
+    pub(crate) id: u64,
+    pub(crate) path: String,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) lang: String,
+    pub(crate) sha256: String,
+    // sha256 of the whole file at build time; lets incremental builds skip unchanged files.
+    #[serde(default)]
+    pub(crate) file_sha256: String,
+    // Definitions (fn/struct/class/...) that start inside this chunk.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) symbols: Vec<Symbol>,
+    // Heading path (Markdown) or key path (TOML/YAML/JSON) of the chunk.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) section: String,
+    // Secrets were replaced with `[REDACTED]` in the embedded text and preview.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) redacted: bool,
+    pub(crate) preview: String,
+}
+
+#[cfg(test)]
+impl MetaRow {
+    /// A `rust` row of `path` covering lines `start..=end`, with id 0 and
+    /// empty hashes and preview.
+    pub(crate) fn fixture(path: &str, start: usize, end: usize) -> Self {
+        Self {
+            id: 0,
+            path: path.into(),
+            start,
+            end,
+            lang: "rust".into(),
+            sha256: String::new(),
+            file_sha256: String::new(),
+            symbols: Vec::new(),
+            section: String::new(),
+            redacted: false,
+            preview: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Analytics {
+    pub queries: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub last_query_ts: Option<String>,
+    // Last time a background or manual index attempt/check ran
+    pub last_attempt_ts: Option<String>,
+}
+
+/// Top-level directory of the repository containing `cwd` (git toplevel when
+/// available); the index lives in `<root>/.codex/index`.
+pub fn repo_root(cwd: &Path) -> PathBuf {
+    if let Ok(out) = std::process::Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(cwd)
+        .output()
+    {
+        if out.status.success() {
+            let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
+            if !s.is_empty() {
+                return PathBuf::from(s);
+            }
+        }
+    }
+    cwd.to_path_buf()
+}
+
+pub fn index_dir(root: &Path) -> PathBuf {
+    root.join(INDEX_DIR)
+}
+
+pub(crate) fn read_manifest(root: &Path) -> Result<Manifest> {
+    let path = index_dir(root).join(MANIFEST_FILE);
+    let bytes = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+pub(crate) fn write_manifest(root: &Path, m: &Manifest) -> Result<()> {
+    let dir = index_dir(root);
+    let tmp = dir.join(format!("{}.tmp", MANIFEST_FILE));
+    fs::write(&tmp, serde_json::to_vec_pretty(m)?)?;
+    fs::rename(&tmp, dir.join(MANIFEST_FILE))?;
+    Ok(())
+}
+
+// ---------- ignore patterns ----------
+
+pub fn ignore_file(root: &Path) -> PathBuf {
+    root.join(IGNORE_FILE)
+}
+
+pub const DEFAULT_IGNORE: &str = r"# Patterns ignored by the local code index (gitignore syntax)
+# Hidden files/dirs
+.*
+# VCS / tooling / caches
+.git
+.codex
+.idea
+.vscode
+node_modules
+target
+dist
+build
+";
+
+// Create the root `.index-ignore` with the defaults when missing.
+pub(crate) fn ensure_ignore_file(root: &Path) {
+    let p = ignore_file(root);
+    if !p.exists() {
+        let _ = fs::write(&p, DEFAULT_IGNORE);
+    }
+}
+
+/// Patterns from the root `.index-ignore`, in file order, creating it with
+/// the defaults when missing.
+pub fn load_ignore_patterns(root: &Path) -> Vec<String> {
+    ensure_ignore_file(root);
+    fs::read_to_string(ignore_file(root))
+        .map(|s| {
+            s.lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(|l| l.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Rewrite the root `.index-ignore` to hold `pats`. Patterns already in the
+/// file keep their place and comments (order matters for `!` rules); new
+/// ones are appended.
+pub fn save_ignore_patterns(root: &Path, pats: Vec<String>) -> Result<()> {
+    let existing = fs::read_to_string(ignore_file(root)).unwrap_or_default();
+    let mut written: HashSet<&str> = HashSet::new();
+    let mut lines: Vec<&str> = Vec::new();
+    for line in existing.lines() {
+        let t = line.trim();
+        if t.is_empty() || t.starts_with('#') {
+            lines.push(line);
+        } else if pats.iter().any(|p| p == t) && written.insert(t) {
+            lines.push(t);
+        }
+    }
+    for p in &pats {
+        if written.insert(p) {
+            lines.push(p);
+        }
+    }
+    fs::write(ignore_file(root), format!("{}\n", lines.join("\n"))).context("write .index-ignore")
+}
+
+pub fn reset_ignore_patterns(root: &Path) -> Result<()> {
+    fs::write(ignore_file(root), DEFAULT_IGNORE).context("write default .index-ignore")
+}
+
+// Unanchored regex source for a glob-like pattern (`*` and `?` supported).
+pub(crate) fn glob_pattern(glob: &str) -> String {
+    let mut r = String::new();
+    for ch in glob.chars() {
+        match ch {
+            '*' => r.push_str(".*"),
+            '?' => r.push('.'),
+            '.' | '+' | '(' | ')' | '|' | '{' | '}' | '[' | ']' | '^' | '$' | '\\' => {
+                r.push('\\');
+                r.push(ch);
+            }
+            _ => r.push(ch),
+        }
+    }
+    r
+}
+
+// ---------- helpers ----------
+
+pub(crate) fn now_iso() -> String {
+    OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_else(|_| "1970-01-01T00:00:00Z".into())
+}
+
+pub(crate) fn read_git_head_sha(root: &Path) -> Option<String> {
+    // Best-effort call to git if available
+    let out = std::process::Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(root)
+        .output()
+        .ok()?;
+    if out.status.success() {
+        Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+pub(crate) fn load_meta(path: impl AsRef<Path>) -> Result<std::collections::HashMap<u64, MetaRow>> {
+    let f = BufReader::new(File::open(path)?);
+    let mut map = std::collections::HashMap::new();
+    for line in f.lines() {
+        let l = line?;
+        if l.trim().is_empty() {
+            continue;
+        }
+        let row: MetaRow = serde_json::from_str(&l)?;
+        map.insert(row.id, row);
+    }
+    Ok(map)
+}
+
+pub(crate) fn write_meta(path: impl AsRef<Path>, rows: &[MetaRow]) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    for row in rows {
+        serde_json::to_writer(&mut w, row)?;
+        w.write_all(b"\n")?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+pub(crate) fn sha256_file(path: impl AsRef<Path>) -> Result<String> {
+    let mut f = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub(crate) fn sha256_hex(s: &str) -> String {
+    hex::encode(Sha256::digest(s.as_bytes()))
+}
+
+pub(crate) fn rel(root: &Path, p: &Path) -> String {
+    pathdiff::diff_paths(p, root)
+        .unwrap_or_else(|| p.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+// ---------- analytics ----------
+
+pub fn read_analytics(root: &Path) -> Result<Analytics> {
+    let p = index_dir(root).join(ANALYTICS_FILE);
+    if !p.exists() {
+        return Ok(Analytics::default());
+    }
+    Ok(serde_json::from_slice(&fs::read(p)?)?)
+}
+
+pub(crate) fn update_analytics<F: FnOnce(Analytics) -> Analytics>(root: &Path, f: F) -> Result<()> {
+    let dir = index_dir(root);
+    let a0 = read_analytics(root).unwrap_or_default();
+    let a1 = f(a0);
+    let tmp = dir.join(format!("{}.tmp", ANALYTICS_FILE));
+    fs::write(&tmp, serde_json::to_vec_pretty(&a1)?)?;
+    fs::rename(tmp, dir.join(ANALYTICS_FILE))?;
+    Ok(())
+}
+
+/// Count a query in `analytics.json`; `hit` when results passed the threshold.
+pub fn record_query(root: &Path, hit: bool) -> Result<()> {
+    update_analytics(root, |mut a| {
+        a.queries += 1;
+        a.hits += u64::from(hit);
+        a.misses = a.queries.saturating_sub(a.hits);
+        a.last_query_ts = Some(now_iso());
+        a
+    })
+}
+
+/// Record that a build or background freshness check ran.
+pub fn record_attempt(root: &Path) -> Result<()> {
+    update_analytics(root, |mut a| {
+        a.last_attempt_ts = Some(now_iso());
+        a
+    })
+}
+
+// ---------- status / verify / clean ----------
+
+/// Summary of the on-disk index, as shown by `index status` and `/index`.
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub manifest: Manifest,
+    pub analytics: Analytics,
+    /// Size of the flat vector store.
+    pub vectors_bytes: u64,
+    /// Size of the exact f32 copy kept with `rescore`.
+    pub exact_bytes: u64,
+    /// Size of the HNSW graph and its (always f32) point data.
+    pub graph_bytes: u64,
+}
+
+impl Status {
+    /// None when no index has been built for `root`.
+    pub fn load(root: &Path) -> Result<Option<Self>> {
+        if !index_dir(root).join(MANIFEST_FILE).exists() {
+            return Ok(None);
+        }
+        let manifest = read_manifest(root)?;
+        let analytics = read_analytics(root).unwrap_or_default();
+        let dir = index_dir(root);
+        let size = |name: &str| fs::metadata(dir.join(name)).map(|m| m.len()).unwrap_or(0);
+        Ok(Some(Self {
+            manifest,
+            analytics,
+            vectors_bytes: size(VECTORS_FILE),
+            exact_bytes: size(EXACT_FILE),
+            graph_bytes: size(&format!("{HNSW_BASENAME}.hnsw.graph"))
+                + size(&format!("{HNSW_BASENAME}.hnsw.data")),
+        }))
+    }
+
+    /// Human-readable status for `root`, or `Index: Missing`.
+    pub fn describe(root: &Path) -> String {
+        match Self::load(root) {
+            Ok(Some(s)) => match crate::history::status_line(root) {
+                Some(history) => format!("{s}\n{history}"),
+                None => s.to_string(),
+            },
+            Ok(None) => "Index: Missing".into(),
+            Err(e) => format!("Index: Unreadable ({e})"),
+        }
+    }
+
+    /// Bytes on disk for vectors: the flat store, its exact copy and the graph.
+    pub fn total_bytes(&self) -> u64 {
+        self.vectors_bytes + self.exact_bytes + self.graph_bytes
+    }
+
+    // `(int8 vectors 3.6x smaller, 1.4x overall with the f32 HNSW graph, exact
+    // re-scoring)` for quantized stores; the graph keeps f32 rows either way.
+    fn storage_note(&self) -> String {
+        let m = &self.manifest;
+        if m.storage == Storage::F32 || self.vectors_bytes == 0 {
+            return String::new();
+        }
+        let f32_bytes = Storage::F32.file_bytes(m.counts.chunks, m.dim);
+        let overall = match self.graph_bytes {
+            0 => String::new(),
+            graph => format!(
+                ", {:.1}x overall with the f32 HNSW graph",
+                (f32_bytes + graph) as f64 / self.total_bytes() as f64
+            ),
+        };
+        format!(
+            " ({} vectors {:.1}x smaller than f32{overall}{})",
+            m.storage.as_str(),
+            f32_bytes as f64 / self.vectors_bytes as f64,
+            if m.rescore { ", exact re-scoring" } else { "" }
+        )
+    }
+
+    pub fn hit_ratio(&self) -> f32 {
+        if self.analytics.queries > 0 {
+            self.analytics.hits as f32 / self.analytics.queries as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let m = &self.manifest;
+        write!(
+            f,
+            "Index: Ready\nLast indexed: {}\nModel: {} {} ({}-D)\nVectors: {}\nSize: {} bytes{}\nAnalytics: queries={}, hit_ratio={:.2}",
+            relative_age(&m.last_refresh).unwrap_or_else(|| m.last_refresh.clone()),
+            m.embedder.as_str(),
+            m.model,
+            m.dim,
+            m.counts.chunks,
+            self.total_bytes(),
+            self.storage_note(),
+            self.analytics.queries,
+            self.hit_ratio(),
+        )?;
+        if !m.secrets.is_empty() {
+            write!(f, "\nSecrets: {} chunks redacted", m.counts.redacted)?;
+        }
+        Ok(())
+    }
+}
+
+/// True when the vector and meta files match the manifest checksums.
+pub fn verify(root: &Path) -> Result<bool> {
+    let m = read_manifest(root)?;
+    let dir = index_dir(root);
+    let vchk = sha256_file(dir.join(VECTORS_FILE))?;
+    let mchk = sha256_file(dir.join(META_FILE))?;
+    Ok(vchk == m.checksums.vectors_hnsw && mchk == m.checksums.meta_jsonl)
+}
+
+/// Remove the on-disk index.
+pub fn clean(root: &Path) -> Result<()> {
+    let dir = index_dir(root);
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+pub(crate) struct BuildLock(PathBuf);
+
+// A lock untouched this long is left over from a crashed build: running builds
+// rewrite it as files are embedded.
+const STALE_LOCK: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+impl BuildLock {
+    pub(crate) fn acquire(root: &Path) -> Result<Self> {
+        Self::acquire_in(&index_dir(root))
+    }
+
+    /// Lock a collection kept in its own directory (e.g. commit history). A
+    /// stale lock (see [`lock_is_stale`]) is taken over.
+    pub(crate) fn acquire_in(dir: &Path) -> Result<Self> {
+        let p = dir.join(LOCK_FILE);
+        let create = || File::options().create_new(true).write(true).open(&p);
+        let file = match create() {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && lock_is_stale(&p) => {
+                let _ = fs::remove_file(&p);
+                create()
+            }
+            other => other,
+        };
+        match file {
+            Ok(_) => {
+                let lock = Self(p);
+                lock.set_pending(0);
+                Ok(lock)
+            }
+            Err(e) => Err(anyhow::anyhow!(e)),
+        }
+    }
+
+    /// Record how many files this build is reading, for [`indexing`].
+    pub(crate) fn set_pending(&self, files: usize) {
+        let _ = fs::write(&self.0, format!("{} {files}", std::process::id()));
+    }
+}
+
+/// True when the lock at `path` was left behind: its owner process is gone
+/// (Linux) or it has not been touched for [`STALE_LOCK`].
+fn lock_is_stale(path: &Path) -> bool {
+    let Ok(meta) = fs::metadata(path) else {
+        return false;
+    };
+    let idle = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .is_some_and(|age| age > STALE_LOCK);
+    idle || !lock_owner_alive(path)
+}
+
+#[cfg(target_os = "linux")]
+fn lock_owner_alive(path: &Path) -> bool {
+    let pid = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| s.split_whitespace().next()?.parse::<u32>().ok());
+    // Locks without a pid (still being written) count as live.
+    pid.is_none_or(|pid| Path::new(&format!("/proc/{pid}")).exists())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lock_owner_alive(_path: &Path) -> bool {
+    true
+}
+
+/// Number of files a running build is (re)indexing, or None when no build
+/// holds the lock (a stale lock left by a crashed build does not count).
+/// Lets UIs show `Indexing N files…` while updates run.
+pub fn indexing(root: &Path) -> Option<usize> {
+    let p = index_dir(root).join(LOCK_FILE);
+    let s = fs::read_to_string(&p).ok()?;
+    if lock_is_stale(&p) {
+        return None;
+    }
+    Some(
+        s.split_whitespace()
+            .last()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(0),
+    )
+}
+
+impl Drop for BuildLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Compact age of an RFC 3339 timestamp, e.g. `5m ago`.
+pub fn relative_age(iso: &str) -> Option<String> {
+    let t = OffsetDateTime::parse(iso, &time::format_description::well_known::Rfc3339).ok()?;
+    let secs = (OffsetDateTime::now_utc() - t).whole_seconds();
+    Some(if secs < 60 {
+        "now".into()
+    } else if secs < 3600 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 86400 {
+        format!("{}h ago", secs / 3600)
+    } else if secs < 86400 * 7 {
+        format!("{}d ago", secs / 86400)
+    } else {
+        format!("{}w ago", secs / (86400 * 7))
+    })
+}
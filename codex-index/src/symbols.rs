@@ -0,0 +1,211 @@
+//! Symbol table: definitions recorded per chunk in `meta.jsonl`, and the
+//! lookup behind `index symbols`.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::store;
+
+/// A definition found by tree-sitter inside a chunk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    /// Normalized kind: fn, struct, enum, trait, impl, class, interface, type, mod.
+    pub kind: String,
+    /// Enclosing impl/class/module name, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// 1-based line of the definition.
+    pub line: usize,
+}
+
+/// Kinds whose definitions enclose other symbols.
+pub(crate) fn is_container_kind(kind: &str) -> bool {
+    matches!(
+        kind,
+        "impl" | "class" | "struct" | "trait" | "interface" | "mod" | "enum"
+    )
+}
+
+/// Name of the item defined by `node`, across the supported grammars.
+pub(crate) fn definition_name(node: tree_sitter::Node, text: &str) -> Option<String> {
+    let node_text = |n: tree_sitter::Node| text.get(n.byte_range()).map(str::to_string);
+    if node.kind() == "impl_item" {
+        // `impl<T> Trait for Foo<T>` is named after the implementing type.
+        return node_text(node.child_by_field_name("type")?).map(|t| strip_generics(&t));
+    }
+    if let Some(n) = node.child_by_field_name("name") {
+        return node_text(n);
+    }
+    if let Some(d) = node.child_by_field_name("declarator") {
+        return declarator_name(d).and_then(node_text);
+    }
+    // Go `type Foo struct { .. }`: the name lives on the inner type_spec.
+    let mut cursor = node.walk();
+    let spec = node
+        .named_children(&mut cursor)
+        .find(|c| matches!(c.kind(), "type_spec" | "type_alias"))?;
+    node_text(spec.child_by_field_name("name")?)
+}
+
+/// Refine the table kind where the grammar node is generic (Go type declarations).
+pub(crate) fn definition_kind(node: tree_sitter::Node, kind: &str) -> String {
+    if node.kind() == "type_declaration" {
+        let mut cursor = node.walk();
+        let inner = node
+            .named_children(&mut cursor)
+            .find(|c| c.kind() == "type_spec")
+            .and_then(|s| s.child_by_field_name("type"))
+            .map(|t| t.kind());
+        return match inner {
+            Some("struct_type") => "struct",
+            Some("interface_type") => "interface",
+            _ => kind,
+        }
+        .into();
+    }
+    kind.into()
+}
+
+// C/C++ nest the identifier inside (pointer/function/reference) declarators.
+fn declarator_name(mut node: tree_sitter::Node) -> Option<tree_sitter::Node> {
+    loop {
+        match node.kind() {
+            "identifier"
+            | "field_identifier"
+            | "type_identifier"
+            | "qualified_identifier"
+            | "destructor_name"
+            | "operator_name" => return Some(node),
+            _ => node = node.child_by_field_name("declarator")?,
+        }
+    }
+}
+
+fn strip_generics(s: &str) -> String {
+    s.split('<').next().unwrap_or(s).trim().to_string()
+}
+
+/// How well `sym` matches the query; None when it does not match at all.
+/// Queries may be qualified with the container (`VectorStore::new`, `Foo.bar`).
+pub(crate) fn match_score(query: &str, sym: &Symbol) -> Option<f32> {
+    let (container_q, name_q) = match query.rsplit_once("::").or_else(|| query.rsplit_once('.')) {
+        Some((c, n)) if !c.is_empty() && !n.is_empty() => (Some(c), n),
+        _ => (None, query),
+    };
+    if let Some(cq) = container_q {
+        let c = sym.container.as_deref()?;
+        if !c.eq_ignore_ascii_case(cq) && !c.to_lowercase().ends_with(&cq.to_lowercase()) {
+            return None;
+        }
+    }
+    let name = sym.name.as_str();
+    if name == name_q {
+        return Some(1.0);
+    }
+    let (nl, ql) = (name.to_lowercase(), name_q.to_lowercase());
+    if nl == ql {
+        return Some(0.9);
+    }
+    let ratio = ql.len() as f32 / nl.len().max(1) as f32;
+    if nl.starts_with(&ql) {
+        return Some(0.6 + 0.2 * ratio);
+    }
+    if nl.contains(&ql) {
+        return Some(0.4 + 0.2 * ratio);
+    }
+    // Subsequence match, e.g. `spm` -> `spawn_periodic_maintenance`.
+    let mut it = nl.chars();
+    if ql.chars().all(|qc| it.any(|c| c == qc)) {
+        return Some(0.2 * ratio);
+    }
+    None
+}
+
+/// A symbol definition matched by [`find_symbols`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolMatch {
+    pub path: String,
+    #[serde(flatten)]
+    pub symbol: Symbol,
+    pub score: f32,
+}
+
+/// Look up definitions by name in the index of `root`, best matches first.
+/// `kind` restricts the symbol kind; `exact` keeps only (case-insensitive) exact names.
+pub fn find_symbols(
+    root: &Path,
+    name: &str,
+    kind: Option<&str>,
+    exact: bool,
+    limit: usize,
+) -> Result<Vec<SymbolMatch>> {
+    let meta = store::load_meta(store::index_dir(root).join(store::META_FILE))?;
+    let mut rows: Vec<store::MetaRow> = meta.into_values().collect();
+    rows.sort_by_key(|r| r.id);
+
+    let mut found: Vec<SymbolMatch> = Vec::new();
+    for row in rows {
+        for sym in row.symbols {
+            if let Some(kind) = kind
+                && !sym.kind.eq_ignore_ascii_case(kind)
+            {
+                continue;
+            }
+            let Some(score) = match_score(name, &sym) else {
+                continue;
+            };
+            if exact && score < 0.9 {
+                continue;
+            }
+            found.push(SymbolMatch {
+                path: row.path.clone(),
+                symbol: sym,
+                score,
+            });
+        }
+    }
+    found.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.path.cmp(&b.path))
+            .then(a.symbol.line.cmp(&b.symbol.line))
+    });
+    found.dedup_by(|a, b| a.path == b.path && a.symbol == b.symbol);
+    found.truncate(limit);
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sym(name: &str, container: Option<&str>) -> Symbol {
+        Symbol {
+            name: name.into(),
+            kind: "fn".into(),
+            container: container.map(str::to_string),
+            line: 1,
+        }
+    }
+
+    #[test]
+    fn match_score_ranks_exact_over_fuzzy() {
+        let exact = match_score(
+            "spawn_periodic_maintenance",
+            &sym("spawn_periodic_maintenance", None),
+        );
+        let prefix = match_score("spawn", &sym("spawn_periodic_maintenance", None));
+        let subseq = match_score("spm", &sym("spawn_periodic_maintenance", None));
+        assert_eq!(exact, Some(1.0));
+        assert!(prefix.unwrap() > subseq.unwrap());
+        assert!(match_score("xyz", &sym("spawn", None)).is_none());
+        assert_eq!(
+            match_score("VectorStore::new", &sym("new", Some("VectorStore"))),
+            Some(1.0)
+        );
+        assert!(match_score("Other::new", &sym("new", Some("VectorStore"))).is_none());
+    }
+}
@@ -0,0 +1,362 @@
+//! Flat vector store (`vectors.hnsw`): a header, the row ids, then the rows as
+//! f32, f16 or int8 (one f32 scale per row). Queries read rows straight from
+//! a memory map; an optional exact f32 copy (`vectors.exact`) re-scores the
+//! top candidates of a quantized store.
+
+use anyhow::{Result, bail};
+use half::f16;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::store::{EXACT_FILE, VECTORS_FILE};
+
+/// On-disk encoding of the vector rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    #[default]
+    F32,
+    /// Half precision; about half the size with negligible loss.
+    F16,
+    /// Symmetric 8-bit quantization with a per-row scale; about a quarter.
+    Int8,
+}
+
+impl Storage {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "f32" => Some(Self::F32),
+            "f16" => Some(Self::F16),
+            "int8" | "i8" => Some(Self::Int8),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::F32 => "f32",
+            Self::F16 => "f16",
+            Self::Int8 => "int8",
+        }
+    }
+
+    fn magic(self) -> &'static [u8; 4] {
+        match self {
+            Self::F32 => b"VEC0",
+            Self::F16 => b"VECH",
+            Self::Int8 => b"VECQ",
+        }
+    }
+
+    fn value_bytes(self) -> usize {
+        match self {
+            Self::F32 => 4,
+            Self::F16 => 2,
+            Self::Int8 => 1,
+        }
+    }
+
+    /// Bytes of one row of `dim` values, including the int8 scale.
+    pub fn row_bytes(self, dim: usize) -> usize {
+        dim * self.value_bytes() + if self == Self::Int8 { 4 } else { 0 }
+    }
+
+    /// File size of a store with `rows` rows of `dim` values.
+    pub fn file_bytes(self, rows: usize, dim: usize) -> u64 {
+        (16 + rows * (8 + self.row_bytes(dim))) as u64
+    }
+}
+
+/// Write `ids` and their rows (`data`, row-major f32) encoded as `storage`.
+pub(crate) fn write(
+    path: impl AsRef<Path>,
+    storage: Storage,
+    dim: usize,
+    ids: &[u64],
+    data: &[f32],
+) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(storage.magic())?;
+    w.write_all(&(dim as u32).to_le_bytes())?;
+    w.write_all(&(ids.len() as u64).to_le_bytes())?;
+    for id in ids {
+        w.write_all(&id.to_le_bytes())?;
+    }
+    match storage {
+        Storage::F32 => {
+            for &v in data {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+        Storage::F16 => {
+            for &v in data {
+                w.write_all(&f16::from_f32(v).to_le_bytes())?;
+            }
+        }
+        Storage::Int8 => {
+            // Scales first so rows stay contiguous.
+            let rows: Vec<&[f32]> = data.chunks_exact(dim.max(1)).collect();
+            let scales: Vec<f32> = rows
+                .iter()
+                .map(|r| r.iter().fold(0f32, |m, x| m.max(x.abs())) / 127.0)
+                .collect();
+            for s in &scales {
+                w.write_all(&s.to_le_bytes())?;
+            }
+            for (row, &scale) in rows.iter().zip(&scales) {
+                let q: Vec<u8> = row
+                    .iter()
+                    .map(|&x| {
+                        let q = if scale > 0.0 {
+                            (x / scale).round()
+                        } else {
+                            0.0
+                        };
+                        q.clamp(-127.0, 127.0) as i8 as u8
+                    })
+                    .collect();
+                w.write_all(&q)?;
+            }
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Ids and decoded f32 rows of the store at `path`.
+pub(crate) fn load(path: impl AsRef<Path>) -> Result<(Vec<u64>, Vec<f32>)> {
+    Ok(VectorStore::open(path.as_ref())?.decode())
+}
+
+/// Like [`load`] for the index in `dir`, preferring the exact copy when one
+/// is kept for the same rows.
+pub(crate) fn load_exact(dir: &Path) -> Result<(Vec<u64>, Vec<f32>)> {
+    let store = VectorStore::open(&dir.join(VECTORS_FILE))?;
+    if let Ok(exact) = load(dir.join(EXACT_FILE))
+        && exact.0 == store.ids()
+    {
+        return Ok(exact);
+    }
+    Ok(store.decode())
+}
+
+enum Bytes {
+    #[cfg(unix)]
+    Mapped(memmap2::Mmap),
+    #[cfg(not(unix))]
+    Owned(Vec<u8>),
+}
+
+impl std::ops::Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(unix)]
+            Self::Mapped(m) => m,
+            #[cfg(not(unix))]
+            Self::Owned(v) => v,
+        }
+    }
+}
+
+/// Read-only view of a vector store; rows are decoded on access.
+pub(crate) struct VectorStore {
+    bytes: Bytes,
+    storage: Storage,
+    dim: usize,
+    rows: usize,
+    // Offsets of the int8 scales (int8 only) and of the first row.
+    scales: usize,
+    data: usize,
+}
+
+impl VectorStore {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: stores are only ever replaced by renaming a new file over
+        // them, never modified in place, so the mapping stays valid.
+        #[cfg(unix)]
+        let bytes = Bytes::Mapped(unsafe { memmap2::Mmap::map(&file)? });
+        // Windows cannot rename over a mapped file, which builds do while a
+        // cached retriever still holds the old store.
+        #[cfg(not(unix))]
+        let bytes = Bytes::Owned({
+            use std::io::Read;
+            let mut v = Vec::new();
+            (&file).read_to_end(&mut v)?;
+            v
+        });
+        Self::parse(bytes)
+    }
+
+    fn parse(bytes: Bytes) -> Result<Self> {
+        if bytes.len() < 16 {
+            bail!("vector store truncated: no header");
+        }
+        let storage = match &bytes[0..4] {
+            b"VEC0" => Storage::F32,
+            b"VECH" => Storage::F16,
+            b"VECQ" => Storage::Int8,
+            _ => bail!("not a vector store"),
+        };
+        let dim = u32::from_le_bytes(bytes[4..8].try_into()?) as usize;
+        let rows = u64::from_le_bytes(bytes[8..16].try_into()?) as usize;
+        let scales = rows
+            .checked_mul(8)
+            .and_then(|n| n.checked_add(16))
+            .unwrap_or(usize::MAX);
+        let data = match storage {
+            Storage::Int8 => scales.saturating_add(rows.saturating_mul(4)),
+            _ => scales,
+        };
+        let end = rows
+            .checked_mul(dim)
+            .and_then(|n| n.checked_mul(storage.value_bytes()))
+            .and_then(|n| n.checked_add(data));
+        if end.is_none_or(|end| bytes.len() < end) {
+            bail!("vector store truncated: {} rows x {} dims", rows, dim);
+        }
+        Ok(Self {
+            bytes,
+            storage,
+            dim,
+            rows,
+            scales,
+            data,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub(crate) fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub(crate) fn ids(&self) -> Vec<u64> {
+        self.bytes[16..self.scales]
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().expect("8 bytes")))
+            .collect()
+    }
+
+    fn decode(&self) -> (Vec<u64>, Vec<f32>) {
+        let mut data = Vec::with_capacity(self.rows * self.dim);
+        for pos in 0..self.rows {
+            data.extend(self.row(pos));
+        }
+        (self.ids(), data)
+    }
+
+    fn scale(&self, pos: usize) -> f32 {
+        let at = self.scales + pos * 4;
+        f32::from_le_bytes(self.bytes[at..at + 4].try_into().expect("4 bytes"))
+    }
+
+    fn raw(&self, pos: usize) -> &[u8] {
+        let width = self.dim * self.storage.value_bytes();
+        let at = self.data + pos * width;
+        &self.bytes[at..at + width]
+    }
+
+    /// Row `pos` decoded to f32.
+    pub(crate) fn row(&self, pos: usize) -> Vec<f32> {
+        let raw = self.raw(pos);
+        match self.storage {
+            Storage::F32 => raw
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().expect("4 bytes")))
+                .collect(),
+            Storage::F16 => raw
+                .chunks_exact(2)
+                .map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32())
+                .collect(),
+            Storage::Int8 => {
+                let scale = self.scale(pos);
+                raw.iter().map(|&b| b as i8 as f32 * scale).collect()
+            }
+        }
+    }
+
+    /// Cosine similarity between row `pos` and `q`, without decoding the row
+    /// into a buffer.
+    pub(crate) fn cosine(&self, pos: usize, q: &[f32]) -> f32 {
+        let raw = self.raw(pos);
+        let (mut dot, mut nq, mut nv) = (0f32, 0f32, 0f32);
+        let mut acc = |x: f32, v: f32| {
+            dot += x * v;
+            nq += x * x;
+            nv += v * v;
+        };
+        match self.storage {
+            Storage::F32 => {
+                for (x, b) in q.iter().zip(raw.chunks_exact(4)) {
+                    acc(*x, f32::from_le_bytes(b.try_into().expect("4 bytes")));
+                }
+            }
+            Storage::F16 => {
+                for (x, b) in q.iter().zip(raw.chunks_exact(2)) {
+                    acc(*x, f16::from_le_bytes([b[0], b[1]]).to_f32());
+                }
+            }
+            // The row scale cancels out of the cosine.
+            Storage::Int8 => {
+                for (x, &b) in q.iter().zip(raw) {
+                    acc(*x, b as i8 as f32);
+                }
+            }
+        }
+        if nq == 0.0 || nv == 0.0 {
+            return 0.0;
+        }
+        dot / (nq.sqrt() * nv.sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantized_stores_round_trip_within_tolerance() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let data = [0.6, -0.8, 0.0, 0.1, 0.2, -0.3, 0.0, 0.0, 0.0];
+        let ids = [4, 9, 2];
+        for (storage, tol) in [
+            (Storage::F32, 0.0),
+            (Storage::F16, 1e-3),
+            (Storage::Int8, 1e-2),
+        ] {
+            let path = dir.path().join(storage.as_str());
+            write(&path, storage, 3, &ids, &data).expect("write");
+            assert_eq!(
+                std::fs::metadata(&path).expect("metadata").len(),
+                storage.file_bytes(3, 3)
+            );
+            let store = VectorStore::open(&path).expect("open");
+            assert_eq!(store.ids(), ids);
+            let (_, decoded) = load(&path).expect("load");
+            for (a, b) in data.iter().zip(&decoded) {
+                assert!((a - b).abs() <= tol, "{storage:?}: {a} vs {b}");
+            }
+            assert!((store.cosine(0, &[0.6, -0.8, 0.0]) - 1.0).abs() < 1e-3);
+            assert_eq!(store.cosine(2, &[1.0, 0.0, 0.0]), 0.0);
+        }
+    }
+
+    #[test]
+    fn truncated_store_is_rejected() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join(VECTORS_FILE);
+        write(&path, Storage::Int8, 4, &[1, 2], &[0.5; 8]).expect("write");
+        let bytes = std::fs::read(&path).expect("read");
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).expect("truncate");
+        assert!(VectorStore::open(&path).is_err());
+        assert_eq!(Storage::parse("I8"), Some(Storage::Int8));
+        assert_eq!(Storage::parse("f64"), None);
+    }
+}
@@ -0,0 +1,387 @@
+//! Deep integrity checks across the index files and targeted repair.
+//!
+//! [`store::verify`] only compares the manifest checksums; [`verify_deep`]
+//! cross-checks every file against the others and against the working tree,
+//! and [`repair`] rebuilds just the parts that are broken.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::build::{BuildOptions, Builder, write_graph};
+use crate::lexical::{LEXICAL_FILE, LexicalIndex};
+use crate::search::load_hnsw;
+use crate::store::{self, EXACT_FILE, HNSW_BASENAME, META_FILE, MetaRow, VECTORS_FILE};
+use crate::vectors;
+
+/// Class of inconsistency; also decides how [`repair`] fixes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// Manifest missing or unreadable.
+    Manifest,
+    /// A file checksum differs from the manifest.
+    Checksum,
+    /// Vector store or metadata missing, truncated or unparsable.
+    Store,
+    /// `dim` disagrees between the manifest and the vector store.
+    Dim,
+    /// Chunk count disagrees between the manifest and the vector store.
+    Count,
+    /// Vector ids without a `meta.jsonl` row.
+    MissingMeta,
+    /// `meta.jsonl` rows without a vector.
+    OrphanMeta,
+    /// HNSW graph missing or not built over the current vectors.
+    Graph,
+    /// BM25 index missing or not built over the current rows.
+    Lexical,
+    /// Indexed file no longer exists.
+    Deleted,
+    /// Indexed file changed since it was indexed.
+    Stale,
+}
+
+impl IssueKind {
+    /// The name used in `--output json`, e.g. `orphan_meta`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Manifest => "manifest",
+            Self::Checksum => "checksum",
+            Self::Store => "store",
+            Self::Dim => "dim",
+            Self::Count => "count",
+            Self::MissingMeta => "missing_meta",
+            Self::OrphanMeta => "orphan_meta",
+            Self::Graph => "graph",
+            Self::Lexical => "lexical",
+            Self::Deleted => "deleted",
+            Self::Stale => "stale",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub detail: String,
+    /// Repo-relative file for `deleted`/`stale`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Issue {
+    fn new(kind: IssueKind, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            detail: detail.into(),
+            path: None,
+        }
+    }
+
+    // Vector store damage cannot be patched; everything is re-embedded.
+    fn needs_rebuild(&self) -> bool {
+        matches!(
+            self.kind,
+            IssueKind::Manifest
+                | IssueKind::Checksum
+                | IssueKind::Store
+                | IssueKind::Dim
+                | IssueKind::Count
+                | IssueKind::MissingMeta
+        )
+    }
+}
+
+/// Result of [`verify_deep`]; empty `issues` means consistent.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    pub issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    pub fn ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// What [`repair`] did and what is still wrong afterwards.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepairReport {
+    pub repaired: Vec<Issue>,
+    pub actions: Vec<String>,
+    pub remaining: Vec<Issue>,
+}
+
+/// Check that the manifest, vector store, metadata, HNSW graph and BM25 index
+/// agree with each other, and that indexed files still exist unchanged.
+pub fn verify_deep(root: &Path) -> Result<VerifyReport> {
+    let mut issues = Vec::new();
+    let dir = store::index_dir(root);
+    let manifest = match store::read_manifest(root) {
+        Ok(m) => m,
+        Err(e) => {
+            issues.push(Issue::new(IssueKind::Manifest, format!("{e:#}")));
+            return Ok(VerifyReport { issues });
+        }
+    };
+    if manifest.dim == 0 || manifest.counts.chunks == 0 {
+        // Nothing was indexed; only the manifest exists.
+        return Ok(VerifyReport { issues });
+    }
+
+    for (file, want) in [
+        (VECTORS_FILE, &manifest.checksums.vectors_hnsw),
+        (META_FILE, &manifest.checksums.meta_jsonl),
+    ] {
+        match store::sha256_file(dir.join(file)) {
+            Ok(got) if &got == want => {}
+            Ok(_) => issues.push(Issue::new(
+                IssueKind::Checksum,
+                format!("{file} does not match the manifest checksum"),
+            )),
+            Err(e) => issues.push(Issue::new(IssueKind::Store, format!("{file}: {e}"))),
+        }
+    }
+
+    let (ids, data) = match vectors::load(dir.join(VECTORS_FILE)) {
+        Ok(v) => v,
+        Err(e) => {
+            issues.push(Issue::new(
+                IssueKind::Store,
+                format!("{VECTORS_FILE}: {e:#}"),
+            ));
+            return Ok(VerifyReport { issues });
+        }
+    };
+    let dim = if ids.is_empty() {
+        0
+    } else {
+        data.len() / ids.len()
+    };
+    if dim != manifest.dim {
+        issues.push(Issue::new(
+            IssueKind::Dim,
+            format!("manifest dim {} but {VECTORS_FILE} has {dim}", manifest.dim),
+        ));
+    }
+    if ids.len() != manifest.counts.chunks {
+        issues.push(Issue::new(
+            IssueKind::Count,
+            format!(
+                "manifest lists {} chunks but {VECTORS_FILE} has {}",
+                manifest.counts.chunks,
+                ids.len()
+            ),
+        ));
+    }
+
+    if manifest.rescore {
+        match vectors::load(dir.join(EXACT_FILE)) {
+            Ok((exact_ids, exact)) if exact_ids == ids && exact.len() == data.len() => {}
+            Ok(_) => issues.push(Issue::new(
+                IssueKind::Store,
+                format!("{EXACT_FILE} does not hold the rows of {VECTORS_FILE}"),
+            )),
+            Err(e) => issues.push(Issue::new(IssueKind::Store, format!("{EXACT_FILE}: {e:#}"))),
+        }
+    }
+
+    let meta = match store::load_meta(dir.join(META_FILE)) {
+        Ok(m) => m,
+        Err(e) => {
+            issues.push(Issue::new(IssueKind::Store, format!("{META_FILE}: {e:#}")));
+            return Ok(VerifyReport { issues });
+        }
+    };
+    let id_set: HashSet<u64> = ids.iter().copied().collect();
+    let missing = ids.iter().filter(|id| !meta.contains_key(id)).count();
+    if missing > 0 {
+        issues.push(Issue::new(
+            IssueKind::MissingMeta,
+            format!("{missing} vector ids have no {META_FILE} row"),
+        ));
+    }
+    let orphans = meta.keys().filter(|id| !id_set.contains(id)).count();
+    if orphans > 0 {
+        issues.push(Issue::new(
+            IssueKind::OrphanMeta,
+            format!("{orphans} {META_FILE} rows have no vector"),
+        ));
+    }
+
+    let graph = dir.join(format!("{HNSW_BASENAME}.hnsw.graph"));
+    let graph_data = dir.join(format!("{HNSW_BASENAME}.hnsw.data"));
+    if !graph.exists() || !graph_data.exists() {
+        issues.push(Issue::new(IssueKind::Graph, "HNSW graph files are missing"));
+    } else {
+        match load_hnsw(&dir).map(|h| h.get_nb_point()) {
+            Some(points) if points == ids.len() => {}
+            Some(points) => issues.push(Issue::new(
+                IssueKind::Graph,
+                format!("HNSW graph has {points} points for {} vectors", ids.len()),
+            )),
+            None => issues.push(Issue::new(IssueKind::Graph, "HNSW graph is unreadable")),
+        }
+    }
+
+    match LexicalIndex::load(&dir.join(LEXICAL_FILE)) {
+        Ok(l) if l.docs() == ids.len() => {}
+        Ok(l) => issues.push(Issue::new(
+            IssueKind::Lexical,
+            format!(
+                "{LEXICAL_FILE} has {} docs for {} vectors",
+                l.docs(),
+                ids.len()
+            ),
+        )),
+        Err(_) => issues.push(Issue::new(
+            IssueKind::Lexical,
+            format!("{LEXICAL_FILE} is missing or unreadable"),
+        )),
+    }
+
+    issues.extend(file_issues(root, meta.values()));
+    Ok(VerifyReport { issues })
+}
+
+// Deleted and modified source files, one issue per file.
+fn file_issues<'a>(root: &Path, rows: impl Iterator<Item = &'a MetaRow>) -> Vec<Issue> {
+    let mut files: BTreeMap<&str, (&str, usize)> = BTreeMap::new();
+    for row in rows {
+        files.entry(&row.path).or_insert((&row.file_sha256, 0)).1 += 1;
+    }
+    let mut issues = Vec::new();
+    for (path, (sha, rows)) in files {
+        let abs = root.join(path);
+        let (kind, what) = if !abs.is_file() {
+            (IssueKind::Deleted, "deleted")
+        } else if !sha.is_empty() && store::sha256_file(&abs).is_ok_and(|got| got != sha) {
+            (IssueKind::Stale, "changed")
+        } else {
+            continue;
+        };
+        issues.push(Issue {
+            kind,
+            detail: format!("{path} {what} since indexing ({rows} rows)"),
+            path: Some(path.to_string()),
+        });
+    }
+    issues
+}
+
+/// Fix what [`verify_deep`] reports, touching only the broken parts: rows of
+/// deleted/changed files are dropped or re-chunked, a bad graph is rebuilt
+/// from the stored vectors, orphan metadata is pruned. Damage to the vector
+/// store itself forces a full rebuild.
+pub fn repair(root: &Path) -> Result<RepairReport> {
+    let found = verify_deep(root)?.issues;
+    let mut actions = Vec::new();
+    if found.iter().any(Issue::needs_rebuild) {
+        let opts = BuildOptions {
+            force: true,
+            ..Builder::refresh(root.to_path_buf()).options().clone()
+        };
+        Builder::new(root.to_path_buf(), opts).build()?;
+        actions.push("rebuilt the index from scratch".to_string());
+    } else {
+        let files: Vec<PathBuf> = found
+            .iter()
+            .filter_map(|i| i.path.as_deref().map(PathBuf::from))
+            .collect();
+        let has = |kind: IssueKind| found.iter().any(|i| i.kind == kind);
+        if !files.is_empty() {
+            // Rewrites vectors, meta, graph and BM25 index, so it covers the rest too.
+            Builder::refresh(root.to_path_buf()).update(&files)?;
+            actions.push(format!(
+                "re-indexed {} deleted or changed files",
+                files.len()
+            ));
+        } else if has(IssueKind::Lexical) {
+            // Re-chunks every file but reuses all stored vectors.
+            Builder::refresh(root.to_path_buf()).build()?;
+            actions.push(format!("regenerated {LEXICAL_FILE}"));
+        } else {
+            if has(IssueKind::OrphanMeta) {
+                prune_meta(root)?;
+                actions.push(format!("dropped orphan {META_FILE} rows"));
+            }
+            if has(IssueKind::Graph) {
+                let (ids, data) = vectors::load_exact(&store::index_dir(root))?;
+                let dim = data.len() / ids.len().max(1);
+                let rows: Vec<&[f32]> = data.chunks_exact(dim.max(1)).collect();
+                write_graph(&store::index_dir(root), &rows)?;
+                actions.push("regenerated the HNSW graph from the vector store".to_string());
+            }
+        }
+    }
+    let remaining = verify_deep(root)?.issues;
+    Ok(RepairReport {
+        repaired: found,
+        actions,
+        remaining,
+    })
+}
+
+// Rewrite meta.jsonl with only the rows that have vectors, in vector order.
+fn prune_meta(root: &Path) -> Result<()> {
+    let dir = store::index_dir(root);
+    let (ids, _) = vectors::load(dir.join(VECTORS_FILE))?;
+    let mut meta = store::load_meta(dir.join(META_FILE))?;
+    let rows: Vec<MetaRow> = ids.iter().filter_map(|id| meta.remove(id)).collect();
+    let tmp = dir.join(format!("{META_FILE}.tmp"));
+    store::write_meta(&tmp, &rows)?;
+    std::fs::rename(&tmp, dir.join(META_FILE))?;
+    let mut m = store::read_manifest(root)?;
+    m.checksums.meta_jsonl = store::sha256_file(dir.join(META_FILE))?;
+    store::write_manifest(root, &m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_issues_reports_deleted_and_changed_files() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        std::fs::write(root.join("same.rs"), "fn a() {}\n").expect("write");
+        std::fs::write(root.join("edited.rs"), "fn b() {}\n").expect("write");
+        let row = |path: &str, sha: String| MetaRow {
+            file_sha256: sha,
+            ..MetaRow::fixture(path, 1, 1)
+        };
+        let rows = [
+            row(
+                "same.rs",
+                store::sha256_file(root.join("same.rs")).expect("sha"),
+            ),
+            row("edited.rs", "0".repeat(64)),
+            row("gone.rs", "0".repeat(64)),
+            row("gone.rs", "0".repeat(64)),
+        ];
+        let issues = file_issues(root, rows.iter());
+        let got: Vec<(IssueKind, &str)> = issues
+            .iter()
+            .map(|i| (i.kind, i.path.as_deref().unwrap_or_default()))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                (IssueKind::Stale, "edited.rs"),
+                (IssueKind::Deleted, "gone.rs")
+            ]
+        );
+        assert_eq!(issues[1].detail, "gone.rs deleted since indexing (2 rows)");
+    }
+
+    #[test]
+    fn missing_manifest_is_a_single_issue() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let report = verify_deep(dir.path()).expect("verify");
+        assert!(!report.ok());
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].kind, IssueKind::Manifest);
+    }
+}
@@ -0,0 +1,137 @@
+//! Live index maintenance: a file watcher that debounces change events and
+//! feeds the changed paths into incremental [`Builder::update`]s.
+
+use anyhow::{Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::build::Builder;
+use crate::ignores::PathFilter;
+use crate::store;
+
+/// Quiet period before a batch of changes is indexed, from
+/// `CODEX_INDEX_WATCH_DEBOUNCE_MS` (default 2000).
+pub fn watch_debounce() -> Duration {
+    std::env::var("CODEX_INDEX_WATCH_DEBOUNCE_MS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::from_secs(2))
+}
+
+/// Watch `root` and update its index once changes settle for `debounce`.
+/// Only directories a scan would enter are watched, so hidden, git-ignored or
+/// `.index-ignore`d trees (`.git/`, `target/`, `node_modules/`, …) cost no
+/// watches and never trigger work; edits to an ignore file trigger a full
+/// refresh. The thread runs until the process exits.
+pub fn spawn_watcher(root: PathBuf, debounce: Duration) -> Result<JoinHandle<()>> {
+    let (tx, rx) = mpsc::channel::<PathBuf>();
+    let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res
+            && !matches!(event.kind, EventKind::Access(_))
+        {
+            for path in event.paths {
+                let _ = tx.send(path);
+            }
+        }
+    })
+    .context("create file watcher")?;
+    let mut watches = Watches {
+        watcher,
+        dirs: HashSet::new(),
+    };
+    watches
+        .watcher
+        .watch(&root, RecursiveMode::NonRecursive)
+        .with_context(|| format!("watch {}", root.display()))?;
+    watches.add_tree(&PathFilter::new(&root), &root);
+
+    Ok(std::thread::spawn(move || {
+        while let Ok(first) = rx.recv() {
+            let mut batch = vec![first];
+            // Collect until the tree is quiet, but flush a steady stream of
+            // events at least every ten debounce periods.
+            let deadline = Instant::now() + debounce * 10;
+            while Instant::now() < deadline {
+                match rx.recv_timeout(debounce) {
+                    Ok(path) => batch.push(path),
+                    Err(_) => break,
+                }
+            }
+            let _ = apply(&root, &mut watches, batch);
+        }
+    }))
+}
+
+// One non-recursive watch per indexed directory.
+struct Watches {
+    watcher: notify::RecommendedWatcher,
+    dirs: HashSet<PathBuf>,
+}
+
+impl Watches {
+    // Watch `dir` and every subdirectory the filter allows; symlinks are not
+    // followed, matching the scan.
+    fn add_tree(&mut self, filter: &PathFilter, dir: &Path) {
+        self.dirs.retain(|d| d.is_dir());
+        let mut stack = vec![dir.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if !entry.file_type().is_ok_and(|t| t.is_dir()) || !filter.allows(&path) {
+                    continue;
+                }
+                if !self.dirs.contains(&path)
+                    && self
+                        .watcher
+                        .watch(&path, RecursiveMode::NonRecursive)
+                        .is_ok()
+                {
+                    self.dirs.insert(path.clone());
+                }
+                stack.push(path);
+            }
+        }
+    }
+}
+
+fn apply(root: &Path, watches: &mut Watches, mut batch: Vec<PathBuf>) -> Result<()> {
+    let rules_changed = batch.iter().any(|p| {
+        p.file_name()
+            .is_some_and(|n| n == ".gitignore" || n == store::IGNORE_FILE)
+    });
+    let filter = PathFilter::new(root);
+    if rules_changed {
+        // Directories may have become (un)ignored.
+        watches.add_tree(&filter, root);
+        Builder::background(root.to_path_buf()).build()?;
+        return Ok(());
+    }
+    batch.retain(|p| filter.allows(p));
+    batch.sort();
+    batch.dedup();
+    if batch.is_empty() {
+        return Ok(());
+    }
+    // Watch directories created (or moved in) since the last batch.
+    // Re-watching is harmless and covers a directory removed and recreated.
+    for dir in batch.iter().filter(|p| p.is_dir()) {
+        if watches
+            .watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .is_ok()
+        {
+            watches.dirs.insert(dir.clone());
+        }
+        watches.add_tree(&filter, dir);
+    }
+    Builder::background(root.to_path_buf()).update(&batch)?;
+    Ok(())
+}
@@ -0,0 +1,297 @@
+//! Federated search over several indexes: sibling repositories listed in
+//! `.codex/workspace.toml`, plus git submodules, which are discovered
+//! automatically, and linked worktrees when opted into (they are usually other
+//! checkouts of the same code and would repeat every hit).
+//!
+//! Each member keeps its own index; queries run against every member that has
+//! one and the hits are merged by score. Hit paths are relative to the
+//! primary root (e.g. `../api/src/lib.rs`) and carry the member name in
+//! [`Hit::repo`].
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::build::{BuildOptions, BuildReport, Builder};
+use crate::progress::BuildProgress;
+use crate::search::{Hit, Retriever, SearchOptions, Timings};
+use crate::store;
+
+pub const WORKSPACE_FILE: &str = ".codex/workspace.toml";
+
+/// Progress callback of [`Workspace::build_all`], told which member is building.
+pub type MemberProgressFn = Arc<dyn Fn(&Member, &BuildProgress) + Send + Sync>;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WorkspaceFile {
+    /// Other repository roots, relative to the primary root.
+    repos: Vec<String>,
+    /// Also federate git submodules (default true).
+    discover: Option<bool>,
+    /// Also federate linked git worktrees (default false).
+    worktrees: bool,
+}
+
+/// One repository of a [`Workspace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// Short unique name, the directory name by default.
+    pub name: String,
+    pub root: PathBuf,
+}
+
+/// The primary repository and the repositories searched together with it.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    root: PathBuf,
+    members: Vec<Member>,
+}
+
+impl Workspace {
+    /// Members of the workspace of `root`: `root` itself first, then the
+    /// `repos` of its workspace file, then submodules and (when enabled) worktrees.
+    pub fn load(root: &Path) -> Result<Self> {
+        let path = root.join(WORKSPACE_FILE);
+        let file: WorkspaceFile = match std::fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).with_context(|| format!("parse {}", path.display()))?,
+            Err(_) => WorkspaceFile::default(),
+        };
+        let mut roots = vec![root.to_path_buf()];
+        roots.extend(file.repos.iter().map(|r| root.join(r)));
+        if file.discover.unwrap_or(true) {
+            roots.extend(git_submodules(root));
+        }
+        if file.worktrees {
+            roots.extend(git_worktrees(root));
+        }
+
+        let mut seen: HashSet<PathBuf> = HashSet::new();
+        let mut names: HashSet<String> = HashSet::new();
+        let mut members = Vec::new();
+        for r in roots {
+            let Ok(r) = r.canonicalize() else {
+                continue;
+            };
+            if !r.is_dir() || !seen.insert(r.clone()) {
+                continue;
+            }
+            let base = r
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "repo".into());
+            let mut name = base.clone();
+            let mut n = 2;
+            while !names.insert(name.clone()) {
+                name = format!("{base}-{n}");
+                n += 1;
+            }
+            members.push(Member { name, root: r });
+        }
+        Ok(Self {
+            root: members
+                .first()
+                .map(|m: &Member| m.root.clone())
+                .unwrap_or_else(|| root.to_path_buf()),
+            members,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// More than one repository to search.
+    pub fn is_federated(&self) -> bool {
+        self.members.len() > 1
+    }
+
+    /// Build or refresh the index of every member with the same options,
+    /// reporting each member's progress to `progress` when given.
+    pub fn build_all(
+        &self,
+        opts: &BuildOptions,
+        progress: Option<MemberProgressFn>,
+    ) -> Vec<(Member, Result<BuildReport>)> {
+        self.members
+            .iter()
+            .map(|m| {
+                let mut builder = Builder::new(m.root.clone(), opts.clone());
+                if let Some(progress) = &progress {
+                    let (progress, member) = (progress.clone(), m.clone());
+                    builder = builder.on_progress(move |p| progress(&member, p));
+                }
+                (m.clone(), builder.build())
+            })
+            .collect()
+    }
+
+    /// Top `opts.k` hits across all members that have an index, best first.
+    /// Fails only when no member could be searched.
+    pub fn search(&self, query: &str, opts: &SearchOptions) -> Result<Vec<Hit>> {
+        Ok(self.search_timed(query, opts)?.0)
+    }
+
+    /// [`Workspace::search`] plus the time spent across members.
+    pub fn search_timed(&self, query: &str, opts: &SearchOptions) -> Result<(Vec<Hit>, Timings)> {
+        let mut timings = Timings::default();
+        let mut searched: Vec<(&Member, Vec<Hit>)> = Vec::new();
+        let mut last_err = None;
+        for m in &self.members {
+            if store::Status::load(&m.root).ok().flatten().is_none() {
+                continue;
+            }
+            match Retriever::shared(&m.root).and_then(|r| r.search_timed(query, opts)) {
+                Ok((found, t)) => {
+                    timings += t;
+                    searched.push((m, found));
+                }
+                Err(e) => last_err = Some(e.context(format!("search {}", m.name))),
+            }
+        }
+        if searched.is_empty()
+            && let Some(e) = last_err
+        {
+            return Err(e);
+        }
+        let roots: Vec<&Path> = searched.iter().map(|(m, _)| m.root.as_path()).collect();
+        let mut hits: Vec<Hit> = Vec::new();
+        for (m, found) in searched {
+            for mut hit in found {
+                let abs = m.root.join(&hit.path);
+                // A nested member (submodule) answers for its own files, when
+                // it was searched; otherwise the enclosing index does.
+                if owner(&roots, &abs).is_some_and(|o| o != m.root) {
+                    continue;
+                }
+                hit.path = store::rel(&self.root, &abs);
+                hit.repo = Some(m.name.clone());
+                hits.push(hit);
+            }
+        }
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(opts.k);
+        Ok((hits, timings))
+    }
+}
+
+// The root among `roots` that is the longest prefix of `path`.
+fn owner<'a>(roots: &[&'a Path], path: &Path) -> Option<&'a Path> {
+    roots
+        .iter()
+        .copied()
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.components().count())
+}
+
+fn git_lines(root: &Path, args: &[&str]) -> Vec<String> {
+    std::process::Command::new("git")
+        .args(args)
+        .current_dir(root)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Checked-out submodules (lines of `submodule.<name>.path <dir>`).
+fn git_submodules(root: &Path) -> Vec<PathBuf> {
+    if !root.join(".gitmodules").is_file() {
+        return Vec::new();
+    }
+    git_lines(
+        root,
+        &[
+            "config",
+            "--file",
+            ".gitmodules",
+            "--get-regexp",
+            r"^submodule\..*\.path$",
+        ],
+    )
+    .iter()
+    .filter_map(|l| l.split_once(' ').map(|(_, p)| root.join(p)))
+    .filter(|p| p.join(".git").exists())
+    .collect()
+}
+
+// Worktrees of the same repository; `root` itself is listed too and deduplicated by the caller.
+fn git_worktrees(root: &Path) -> Vec<PathBuf> {
+    git_lines(root, &["worktree", "list", "--porcelain"])
+        .iter()
+        .filter_map(|l| l.strip_prefix("worktree "))
+        .map(PathBuf::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_members_from_workspace_file() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let main = dir.path().join("app");
+        for d in ["app/.codex", "api", "other/api"] {
+            std::fs::create_dir_all(dir.path().join(d)).expect("mkdir");
+        }
+        std::fs::write(
+            main.join(WORKSPACE_FILE),
+            "repos = [\"../api\", \"../other/api\", \"../missing\", \".\"]\ndiscover = false\n",
+        )
+        .expect("write");
+        let ws = Workspace::load(&main).expect("load");
+        let dir = dir.path().canonicalize().expect("canonicalize");
+        let names: Vec<&str> = ws.members().iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["app", "api", "api-2"]);
+        assert!(ws.is_federated());
+        let roots: Vec<&Path> = ws.members().iter().map(|m| m.root.as_path()).collect();
+        let other_api = dir.join("other/api");
+        assert_eq!(
+            owner(&roots, &other_api.join("src/lib.rs")),
+            Some(other_api.as_path())
+        );
+    }
+
+    #[test]
+    fn nested_member_without_index_leaves_its_files_to_the_root() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path().canonicalize().expect("canonicalize");
+        std::fs::create_dir_all(root.join(".codex")).expect("mkdir");
+        std::fs::create_dir_all(root.join("vendor/lib")).expect("mkdir");
+        std::fs::write(root.join(WORKSPACE_FILE), "repos = [\"vendor/lib\"]\n").expect("write");
+        std::fs::write(root.join("vendor/lib/parse.rs"), "fn parse_header() {}\n").expect("write");
+        Builder::new(&root, BuildOptions::fake("fake-small"))
+            .build()
+            .expect("build");
+        let ws = Workspace::load(&root).expect("load");
+        assert_eq!(ws.members().len(), 2);
+        let hits = ws
+            .search("parse_header", &SearchOptions::default())
+            .expect("search");
+        assert_eq!(
+            hits.first().map(|h| h.path.as_str()),
+            Some("vendor/lib/parse.rs")
+        );
+    }
+
+    #[test]
+    fn without_workspace_file_only_the_root_is_searched() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let ws = Workspace::load(dir.path()).expect("load");
+        assert_eq!(ws.members().len(), 1);
+        assert!(!ws.is_federated());
+    }
+}
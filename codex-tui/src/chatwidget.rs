@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -52,7 +53,6 @@ use ratatui::layout::Layout;
 use ratatui::layout::Rect;
 use ratatui::widgets::Widget;
 use ratatui::widgets::WidgetRef;
-use std::process::Command as StdCommand;
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::debug;
 
@@ -435,7 +435,7 @@ impl ChatWidget {
         }
 
         // Trigger a best-effort post-turn index refresh (git-delta) if due.
-        maybe_trigger_post_turn_index_refresh();
+        maybe_trigger_post_turn_index_refresh(&self.config.cwd);
         // Refresh the footer's index last-updated status after each turn.
         self.refresh_index_last_updated_footer();
     }
@@ -1045,18 +1045,16 @@ impl ChatWidget {
                             });
                             return; // Do not submit as a normal user message
                         } else if let Some(rest) = trimmed.strip_prefix("/index") {
-                            let args_text = rest.trim();
-                            let mut args: Vec<String> = vec!["index".into()];
-                            if args_text.is_empty() {
-                                args.push("status".into());
-                            } else if let Some(tokens) = shlex::split(args_text) {
-                                args.extend(tokens);
-                            }
+                            let input = rest.trim().to_string();
                             let cfg = self.config.clone();
                             let tx = self.app_event_tx.clone();
                             tokio::spawn(async move {
-                                let out =
-                                    crate::review_codebase::run_local_cli_capture(&cfg.cwd, args);
+                                let root = codex_index::repo_root(&cfg.cwd);
+                                let out = tokio::task::spawn_blocking(move || {
+                                    codex_index::index_command(&root, &input)
+                                })
+                                .await
+                                .unwrap_or_else(|e| format!("Index command failed: {e}"));
                                 let msg = format!("```text\n{}\n```", out);
                                 let mut lines: Vec<ratatui::text::Line<'static>> = Vec::new();
                                 crate::markdown::append_markdown(&msg, &mut lines, &cfg);
@@ -1068,29 +1066,49 @@ impl ChatWidget {
                             let query = rest.trim();
                             if query.is_empty() {
                                 self.add_to_history(history_cell::new_error_event(
-                                    "Usage: /search <query> [-k N]".to_string(),
+                                    "Usage: /search <query> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]".to_string(),
                                 ));
                                 return;
                             }
-                            let args = {
-                                // Note: do not shlex the query — pass as a single argument
-                                let mut v = vec![
-                                    "index".to_string(),
-                                    "query".to_string(),
-                                    query.to_string(),
-                                ];
-                                if !query.contains("-k ") {
-                                    v.push("-k".into());
-                                    v.push("8".into());
-                                }
-                                v.push("--show-snippets".into());
-                                v
-                            };
+                            let input = query.to_string();
+                            let cfg = self.config.clone();
+                            let tx = self.app_event_tx.clone();
+                            tokio::spawn(async move {
+                                let root = codex_index::repo_root(&cfg.cwd);
+                                let out = tokio::task::spawn_blocking(move || {
+                                    codex_index::search_command(
+                                        &root,
+                                        &input,
+                                        codex_index::QuerySource::Tui,
+                                    )
+                                })
+                                .await
+                                .unwrap_or_else(|e| format!("Search failed: {e}"));
+                                let msg = format!("```text\n{}\n```", out);
+                                let mut lines: Vec<ratatui::text::Line<'static>> = Vec::new();
+                                crate::markdown::append_markdown(&msg, &mut lines, &cfg);
+                                let cell = history_cell::AgentMessageCell::new(lines, true);
+                                tx.send(AppEvent::InsertHistoryCell(Box::new(cell)));
+                            });
+                            return;
+                        } else if let Some(rest) = trimmed.strip_prefix("/similar") {
+                            let location = rest.trim();
+                            if location.is_empty() {
+                                self.add_to_history(history_cell::new_error_event(
+                                    "Usage: /similar <path>:<line> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]".to_string(),
+                                ));
+                                return;
+                            }
+                            let input = location.to_string();
                             let cfg = self.config.clone();
                             let tx = self.app_event_tx.clone();
                             tokio::spawn(async move {
-                                let out =
-                                    crate::review_codebase::run_local_cli_capture(&cfg.cwd, args);
+                                let root = codex_index::repo_root(&cfg.cwd);
+                                let out = tokio::task::spawn_blocking(move || {
+                                    codex_index::similar_command(&root, &input)
+                                })
+                                .await
+                                .unwrap_or_else(|e| format!("Similar search failed: {e}"));
                                 let msg = format!("```text\n{}\n```", out);
                                 let mut lines: Vec<ratatui::text::Line<'static>> = Vec::new();
                                 crate::markdown::append_markdown(&msg, &mut lines, &cfg);
@@ -1224,10 +1242,7 @@ impl ChatWidget {
                 let cfg = self.config.clone();
                 let tx = self.app_event_tx.clone();
                 tokio::spawn(async move {
-                    let out = crate::review_codebase::run_local_cli_capture(
-                        &cfg.cwd,
-                        vec!["index".into(), "status".into()],
-                    );
+                    let out = codex_index::Status::describe(&codex_index::repo_root(&cfg.cwd));
                     let msg = format!("```text\n{}\n```", out);
                     let mut lines: Vec<ratatui::text::Line<'static>> = Vec::new();
                     crate::markdown::append_markdown(&msg, &mut lines, &cfg);
@@ -1237,7 +1252,14 @@ impl ChatWidget {
             }
             SlashCommand::Search => {
                 self.add_to_history(history_cell::new_info_event(
-                    "Usage: /search <query> [-k N]".to_string(),
+                    "Usage: /search <query> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]".to_string(),
+                    None,
+                ));
+            }
+            SlashCommand::Similar => {
+                self.add_to_history(history_cell::new_info_event(
+                    "Usage: /similar <path>:<line> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]"
+                        .to_string(),
                     None,
                 ));
             }
@@ -1338,7 +1360,7 @@ impl ChatWidget {
             .map(|v| v == "0" || v.eq_ignore_ascii_case("off"))
             .unwrap_or(false)
         {
-            if let Some((ctx, summary)) = fetch_retrieval_context_plus(&text) {
+            if let Some((ctx, summary)) = fetch_retrieval_context_plus(&self.config.cwd, &text) {
                 // Display compact index status in the footer (not in the chat history).
                 self.bottom_pane.set_index_status(Some(summary));
                 // Inject the detailed context into the model input.
@@ -2113,159 +2135,12 @@ fn extract_first_bold(s: &str) -> Option<String> {
 #[cfg(test)]
 pub(crate) mod tests;
 
-#[allow(dead_code)]
-fn token_budget_env() -> usize {
-    std::env::var("CODEX_INDEX_CONTEXT_TOKENS")
-        .ok()
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(800)
-}
-#[allow(dead_code)]
-fn est_tokens(s: &str) -> usize {
-    s.len().div_ceil(4)
-}
-
-#[allow(dead_code)]
-fn fetch_retrieval_context(query: &str) -> Option<String> {
-    if query.trim().is_empty() {
-        return None;
-    }
-    // best-effort call to codex-agentic index query
-    let out = StdCommand::new("codex-agentic")
-        .arg("index")
-        .arg("query")
-        .arg(query)
-        .arg("-k")
-        .arg("8")
-        .arg("--show-snippets")
-        .output()
-        .ok()?;
-    if !out.status.success() {
-        return None;
-    }
-    let s = String::from_utf8_lossy(&out.stdout).to_string();
-    if s.trim().is_empty() {
-        return None;
-    }
-    // Token-aware trim
-    let budget = token_budget_env();
-    let mut acc = String::new();
-    for line in s.lines() {
-        if est_tokens(&(acc.clone() + line)) > budget {
-            break;
-        }
-        acc.push_str(line);
-        acc.push('\n');
-    }
-    Some(format!(
-        concat!(
-            "Context (top matches from local code index) — read these first:\n\n",
-            "```text\n{}\n```\n\n",
-            "Instructions:\n",
-            "1) Treat the above files as the primary sources for answering. Read them carefully before any grep/other searches.\n",
-            "2) Only if these sources are insufficient, you may run additional searches.\n",
-            "3) Do not include low-confidence references (< threshold) in your reasoning.\n",
-            "4) Cite file paths and line ranges when you reference code.\n"
-        ),
-        acc
-    ))
-}
-
-/// Fetch retrieval context plus a compact references list for UI display.
-/// Returns (context_block_for_llm, references_markdown_for_ui).
-fn fetch_retrieval_context_plus(query: &str) -> Option<(String, String)> {
-    if query.trim().is_empty() {
-        return None;
-    }
-    // best-effort call to codex-agentic index query
-    let out = StdCommand::new("codex-agentic")
-        .arg("index")
-        .arg("query")
-        .arg(query)
-        .arg("-k")
-        .arg("8")
-        .arg("--show-snippets")
-        .output()
-        .ok()?;
-    if !out.status.success() {
-        return None;
-    }
-    let s = String::from_utf8_lossy(&out.stdout).to_string();
-    if s.trim().is_empty() {
-        return None;
-    }
-    // Determine top confidence and count of items over threshold from header lines
-    let mut top_score: Option<f32> = None;
-    let mut found: usize = 0;
-    let threshold: f32 = std::env::var("CODEX_INDEX_RETRIEVAL_THRESHOLD")
-        .ok()
-        .and_then(|v| v.parse::<f32>().ok())
-        .unwrap_or(0.65);
-    for line in s.lines() {
-        let l = line.trim();
-        if !l.starts_with('[') {
-            continue;
-        }
-        // expected: "[rank] <score> <path>:<start>-<end> (<lang>)"
-        // parse score token after the first closing bracket
-        if let Some(pos) = l.find(']') {
-            let after = l[(pos + 1)..].trim();
-            if let Some(score_tok) = after.split_whitespace().next()
-                && let Ok(sc) = score_tok.parse::<f32>()
-            {
-                if top_score.is_none() {
-                    top_score = Some(sc);
-                }
-                if sc >= threshold {
-                    found += 1;
-                }
-            }
-        }
-    }
-    let top = top_score.unwrap_or(0.0);
-    if top < threshold {
-        // Below confidence threshold: do not inject or display anything.
-        return None;
-    }
-    // Build file references list from header lines only
-    let mut refs: Vec<String> = Vec::new();
-    for line in s.lines() {
-        let l = line.trim();
-        if !l.starts_with("[") {
-            continue;
-        }
-        if let Some(pos) = l.find("]") {
-            let after = l[(pos + 1)..].trim();
-            let mut it = after.split_whitespace();
-            let score_tok = it.next();
-            let path_tok = it.next();
-            if let (Some(st), Some(pt)) = (score_tok, path_tok)
-                && let Ok(sc) = st.parse::<f32>()
-                && sc >= threshold
-            {
-                let (path, rng) = pt.split_once(":").unwrap_or((pt, ""));
-                refs.push(format!("- @{} {}", path, rng));
-            }
-        }
-    }
-    let refs_block = refs.join("\n");
-    let ctx = format!(
-        concat!(
-            "Local code references — read these first.\n\n",
-            "Instructions:\n",
-            "1) Treat the files below as the primary sources for answering. Read them carefully before any grep/other searches.\n",
-            "2) Only if these sources are insufficient, you may run additional searches.\n",
-            "3) Do not include low-confidence references (< threshold) in your reasoning.\n",
-            "4) Cite file paths and line ranges when you reference code.\n\n",
-            "References:\n",
-            "{}\n"
-        ),
-        refs_block
-    );
-    // Compact UI summary: show highest confidence as percent and number of items found
-    let cf_pct = (top * 100.0).round();
-    let summary_md = format!("> {:.0}% -- {} items found", cf_pct, found);
-    Some((ctx, summary_md))
+/// Fetch retrieval context plus a compact summary for UI display.
+/// Returns (context_block_for_llm, summary_markdown_for_ui).
+fn fetch_retrieval_context_plus(cwd: &Path, query: &str) -> Option<(String, String)> {
+    let root = codex_index::repo_root(cwd);
+    let rc = codex_index::retrieval_context(&root, query, 8, codex_index::QuerySource::Tui)?;
+    Some((rc.context, rc.summary))
 }
 
 fn compute_relative_age(iso: &str) -> Option<String> {
@@ -2294,84 +2169,35 @@ fn compute_relative_age(iso: &str) -> Option<String> {
 
 impl ChatWidget {
     fn refresh_index_last_updated_footer(&mut self) {
-        use std::fs;
-        use std::path::PathBuf;
-        // Try CWD-based location first, then local process directory.
-        let mut p = self.config.cwd.clone().join(".codex/index/manifest.json");
-        if !p.exists() {
-            let alt = PathBuf::from(".codex/index/manifest.json");
-            if alt.exists() {
-                p = alt;
+        let root = codex_index::repo_root(&self.config.cwd);
+        let mut parts: Vec<String> = Vec::new();
+        // A running build (watcher update, post-turn refresh, first run) holds the lock.
+        if let Some(n) = codex_index::indexing(&root) {
+            parts.push(format!("Indexing {} files…", n));
+        }
+        if let Ok(Some(status)) = codex_index::Status::load(&root) {
+            if let Some(i) = compute_relative_age(&status.manifest.last_refresh) {
+                parts.push(format!("Indexed {}", i));
             }
-        }
-        let text = match fs::read_to_string(&p) {
-            Ok(s) => s,
-            Err(_) => {
-                // Fallback: call CLI `index status` and parse relative age.
-                if let Ok(out) = StdCommand::new("codex-agentic")
-                    .arg("index")
-                    .arg("status")
-                    .output()
-                    && out.status.success()
-                {
-                    let s = String::from_utf8_lossy(&out.stdout);
-                    if let Some(line) = s
-                        .lines()
-                        .find(|l| l.trim_start().starts_with("Last indexed:"))
-                    {
-                        let rel = line.trim_start().trim_start_matches("Last indexed:").trim();
-                        if !rel.is_empty() {
-                            self.bottom_pane
-                                .set_index_last_updated(Some(format!("Indexed {}", rel)));
-                            return;
-                        }
-                    }
-                }
-                self.bottom_pane.set_index_last_updated(None);
-                return;
-            }
-        };
-        let last = match serde_json::from_str::<serde_json::Value>(&text)
-            .ok()
-            .and_then(|v| {
-                v.get("last_refresh")
-                    .and_then(|x| x.as_str())
-                    .map(|s| s.to_string())
-            }) {
-            Some(s) => s,
-            None => {
-                self.bottom_pane.set_index_last_updated(None);
-                return;
+            if let Some(a) = status
+                .analytics
+                .last_attempt_ts
+                .as_deref()
+                .and_then(compute_relative_age)
+            {
+                parts.push(format!("Checked {}", a));
             }
-        };
-        let idx_rel = compute_relative_age(&last);
-        // Also attempt to read analytics.json for last_attempt_ts
-        let mut attempt_rel: Option<String> = None;
-        let ap = p
-            .parent()
-            .map(|d| d.join("analytics.json"))
-            .unwrap_or_else(|| PathBuf::from(".codex/index/analytics.json"));
-        if let Ok(at) = std::fs::read_to_string(&ap)
-            && let Ok(val) = serde_json::from_str::<serde_json::Value>(&at)
-            && let Some(ts) = val.get("last_attempt_ts").and_then(|x| x.as_str())
-        {
-            attempt_rel = compute_relative_age(ts);
         }
-        let line = match (idx_rel, attempt_rel) {
-            (Some(i), Some(a)) => format!("Indexed {} • Checked {}", i, a),
-            (Some(i), None) => format!("Indexed {}", i),
-            (None, Some(a)) => format!("Checked {}", a),
-            _ => String::new(),
-        };
-        if line.is_empty() {
+        if parts.is_empty() {
             self.bottom_pane.set_index_last_updated(None);
         } else {
-            self.bottom_pane.set_index_last_updated(Some(line));
+            self.bottom_pane
+                .set_index_last_updated(Some(parts.join(" • ")));
         }
     }
 }
 // --- Post‑turn index refresh trigger (non‑blocking) ---
-fn maybe_trigger_post_turn_index_refresh() {
+fn maybe_trigger_post_turn_index_refresh(cwd: &Path) {
     use std::sync::{Mutex, OnceLock};
     static LAST_RUN: OnceLock<Mutex<std::time::Instant>> = OnceLock::new();
     let min_secs: u64 = std::env::var("CODEX_INDEX_REFRESH_MIN_SECS")
@@ -2395,10 +2221,8 @@ fn maybe_trigger_post_turn_index_refresh() {
         *last = now;
     }
     // Spawn a detached thread to run the refresh without blocking the UI.
-    std::thread::spawn(|| {
-        let _ = StdCommand::new("codex-agentic")
-            .arg("index")
-            .arg("build")
-            .status();
+    let root = codex_index::repo_root(cwd);
+    std::thread::spawn(move || {
+        let _ = codex_index::Builder::background(root).build();
     });
 }
@@ -1338,27 +1338,13 @@ pub(crate) fn new_status_output(
     // 🗂 Index (best-effort)
     lines.push("".into());
     lines.push(vec![padded_emoji("🗂").into(), "Index".bold()].into());
-    match std::process::Command::new("codex-agentic")
-        .args(["index", "status"]) // human-readable
-        .output()
-    {
-        Ok(out) if out.status.success() => {
-            let s = String::from_utf8_lossy(&out.stdout).to_string();
-            if s.trim().is_empty() {
-                lines.push("  • (no index info)".dim().into());
-            } else {
-                for l in s.lines() {
-                    let mut text = l.to_string();
-                    if text.len() > 200 {
-                        text.truncate(200);
-                    }
-                    lines.push(Line::from(vec!["  • ".into(), text.into()]));
-                }
-            }
-        }
-        _ => {
-            lines.push("  • (index unavailable)".dim().into());
+    let status = codex_index::Status::describe(&codex_index::repo_root(&config.cwd));
+    for l in status.lines() {
+        let mut text = l.to_string();
+        if text.len() > 200 {
+            text.truncate(200);
         }
+        lines.push(Line::from(vec!["  • ".into(), text.into()]));
     }
 
     PlainHistoryCell { lines }
@@ -25,10 +25,12 @@ pub enum SlashCommand {
     Status,
     Limits,
     Mcp,
-    /// Manage local index (status/build/verify/clean) — mirrors ACP
+    /// Manage local index (status/build/symbols/verify/clean) — mirrors ACP
     Index,
     /// Semantic search in local codebase — mirrors ACP
     Search,
+    /// Code similar to a file location — mirrors ACP
+    Similar,
     Logout,
     Quit,
     #[cfg(debug_assertions)]
@@ -55,9 +57,10 @@ impl SlashCommand {
             SlashCommand::Approvals => "choose what Codex can do without approval",
             SlashCommand::Mcp => "list configured MCP tools",
             SlashCommand::Index => {
-                "manage local index: /index status | build [--model bge-small|bge-large] [--force] | verify | clean"
+                "manage local index: /index status | build [--model bge-small|bge-large] [--force] | symbols <name> [--kind fn] [--exact] | verify [--deep|--repair] | clean"
             }
-            SlashCommand::Search => "semantic search in codebase (local): /search <query> [-k N]",
+            SlashCommand::Search => "semantic search in codebase (local): /search <query> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]",
+            SlashCommand::Similar => "find similar code in other files (local): /similar <path>:<line> [lang:L] [path:GLOB] [exclude:GLOB] [-k N]",
             SlashCommand::Reasoning => "show/hide thinking: hidden | summary | raw",
             SlashCommand::Logout => "log out of Codex",
             #[cfg(debug_assertions)]
@@ -90,6 +93,7 @@ impl SlashCommand {
             | SlashCommand::Mcp
             | SlashCommand::Index
             | SlashCommand::Search
+            | SlashCommand::Similar
             | SlashCommand::Quit => true,
 
             #[cfg(debug_assertions)]